@@ -0,0 +1,824 @@
+//! Assembler for the mnemonics printed by [`Opcode`]'s `Display` and the
+//! disassembler.
+//!
+//! ```text
+//! SPEED EQU 2             ; constants
+//! start:                  ; labels, on their own line or before an instruction
+//!     LD V0, SPEED
+//!     LD I, sprite
+//!     DRW V0, V1, 5
+//! loop: JP loop
+//! sprite:
+//!     DB 0xF0, 0x90, 0xF0 ; bytes
+//!     DW 0x9090           ; big-endian words
+//! INCLUDE "font.asm"      ; relative to the including file
+//! ```
+//!
+//! Mnemonics, registers and directives are case-insensitive, labels and
+//! constants are not. Numbers are decimal, `0x`/`$` hex or `0b` binary, and
+//! operands may add and subtract numbers, labels and constants.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::chip::PROGRAM_START;
+use crate::nibble::Nibble;
+use crate::opcode::Opcode;
+
+/// Nested includes allowed before assuming a cycle
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Error with the position in the source it was found at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column, in characters
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Source line of every assembled instruction, for debuggers
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    /// In address order
+    lines: Vec<SourceLine>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    pub file: String,
+    /// 1-based line number
+    pub line: usize,
+    pub addr: usize,
+}
+
+impl SourceMap {
+    pub fn lines(&self) -> &[SourceLine] {
+        &self.lines
+    }
+
+    /// Instruction on `line` of `file`, or on the closest line after it
+    pub fn find_line(&self, file: &str, line: usize) -> Option<&SourceLine> {
+        self.lines
+            .iter()
+            .filter(|l| l.file == file && l.line >= line)
+            .min_by_key(|l| (l.line, l.addr))
+    }
+
+    /// Instruction assembled at `addr`
+    pub fn find_addr(&self, addr: usize) -> Option<&SourceLine> {
+        self.lines
+            .binary_search_by_key(&addr, |l| l.addr)
+            .ok()
+            .map(|idx| &self.lines[idx])
+    }
+}
+
+/// Where a piece of source text is
+#[derive(Debug, Clone)]
+struct Loc {
+    file: FileName,
+    line: usize,
+    column: usize,
+}
+
+/// Name of a source file, shared by every location in it
+type FileName = Rc<str>;
+
+impl Loc {
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.file.to_string(),
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Term {
+    Number(i64),
+    Symbol(String),
+}
+
+/// Sum of terms, each added or subtracted
+#[derive(Debug, Clone)]
+struct Expr {
+    terms: Vec<(bool, Term, Loc)>,
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    V(u8),
+    /// `Vx-Vy`
+    VRange(u8, u8),
+    I,
+    /// `[I]`
+    IndirectI,
+    DT,
+    ST,
+    K,
+    F,
+    B,
+    HF,
+    R,
+    /// `LONG expr`
+    Long(Expr),
+    Value(Expr),
+}
+
+#[derive(Debug)]
+enum Item {
+    Instruction {
+        mnemonic: String,
+        operands: Vec<(Operand, Loc)>,
+    },
+    Bytes(Vec<Expr>),
+    Words(Vec<Expr>),
+}
+
+impl Item {
+    fn size(&self) -> usize {
+        match self {
+            Item::Instruction { operands, .. } => {
+                if operands
+                    .iter()
+                    .any(|(op, _)| matches!(op, Operand::Long(_)))
+                {
+                    4
+                } else {
+                    2
+                }
+            }
+            Item::Bytes(values) => values.len(),
+            Item::Words(values) => values.len() * 2,
+        }
+    }
+}
+
+struct Assembler {
+    items: Vec<(Item, Loc)>,
+    labels: HashMap<String, (usize, Loc)>,
+    constants: HashMap<String, (Expr, Loc)>,
+    addr: usize,
+}
+
+/// Assemble source code into a ROM loaded at [`PROGRAM_START`]. Includes
+/// are relative to the working directory.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut asm = Assembler::new();
+    asm.parse("<source>".into(), source, Path::new("."), 0)?;
+    asm.finish().map(|(rom, _)| rom)
+}
+
+/// Assemble a source file into a ROM loaded at [`PROGRAM_START`]
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>, AsmError> {
+    assemble_file_with_map(path).map(|(rom, _)| rom)
+}
+
+/// [`assemble_file`], also returning where each instruction came from.
+/// File names in the map are the paths as given and as joined for includes.
+pub fn assemble_file_with_map(path: &Path) -> Result<(Vec<u8>, SourceMap), AsmError> {
+    let name: FileName = path.display().to_string().into();
+    let source = fs::read_to_string(path).map_err(|err| AsmError {
+        file: name.to_string(),
+        line: 0,
+        column: 0,
+        message: err.to_string(),
+    })?;
+    let mut asm = Assembler::new();
+    asm.parse(name, &source, &parent(path), 0)?;
+    asm.finish()
+}
+
+fn parent(path: &Path) -> PathBuf {
+    path.parent().map(Path::to_path_buf).unwrap_or_default()
+}
+
+impl Assembler {
+    fn new() -> Self {
+        Assembler {
+            items: Vec::new(),
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            addr: PROGRAM_START,
+        }
+    }
+
+    /// First pass: read the statements and place the labels
+    fn parse(
+        &mut self,
+        file: FileName,
+        source: &str,
+        dir: &Path,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        for (n, text) in source.lines().enumerate() {
+            let code = strip_comment(text);
+            let loc = |offset: usize| Loc {
+                file: file.clone(),
+                line: n + 1,
+                column: text[..offset].chars().count() + 1,
+            };
+            let mut offset = skip_space(code, 0);
+
+            // A label ends with a colon before any space
+            let word_end = code[offset..]
+                .find(char::is_whitespace)
+                .map_or(code.len(), |i| offset + i);
+            if code[offset..word_end].ends_with(':') {
+                let name = &code[offset..word_end - 1];
+                self.define_label(name, loc(offset))?;
+                offset = skip_space(code, word_end);
+            }
+            if offset >= code.len() {
+                continue;
+            }
+
+            let (word, rest) = next_word(code, offset);
+            let (second, after_second) = next_word(code, rest);
+            if second.eq_ignore_ascii_case("EQU") {
+                check_name(word, &loc(offset))?;
+                let start = skip_space(code, after_second);
+                let expr = parse_expr(&code[start..], &loc(start), &|o| loc(start + o))?;
+                if self
+                    .constants
+                    .insert(word.to_string(), (expr, loc(offset)))
+                    .is_some()
+                {
+                    return Err(loc(offset).error(format!("{} is already defined", word)));
+                }
+                continue;
+            }
+
+            let operands = split_operands(code, rest);
+            let mnemonic = word.to_ascii_uppercase();
+            let item = match mnemonic.as_str() {
+                "INCLUDE" => {
+                    let (text, at) = match operands.as_slice() {
+                        [one] => *one,
+                        _ => return Err(loc(offset).error("INCLUDE takes one file name")),
+                    };
+                    let name = text
+                        .strip_prefix('"')
+                        .and_then(|t| t.strip_suffix('"'))
+                        .ok_or_else(|| loc(at).error("expected a quoted file name"))?;
+                    self.include(&dir.join(name), &loc(at), depth)?;
+                    continue;
+                }
+                "DB" | "DW" => {
+                    if operands.is_empty() {
+                        return Err(loc(offset).error(format!("{} needs values", word)));
+                    }
+                    let values = operands
+                        .iter()
+                        .map(|&(text, at)| parse_expr(text, &loc(at), &|o| loc(at + o)))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    if mnemonic == "DB" {
+                        Item::Bytes(values)
+                    } else {
+                        Item::Words(values)
+                    }
+                }
+                _ => Item::Instruction {
+                    mnemonic: word.to_string(),
+                    operands: operands
+                        .iter()
+                        .map(|&(text, at)| {
+                            parse_operand(text, &loc(at), &|o| loc(at + o)).map(|op| (op, loc(at)))
+                        })
+                        .collect::<Result<Vec<_>, _>>()?,
+                },
+            };
+            self.addr += item.size();
+            self.items.push((item, loc(offset)));
+        }
+        Ok(())
+    }
+
+    fn define_label(&mut self, name: &str, loc: Loc) -> Result<(), AsmError> {
+        check_name(name, &loc)?;
+        if self.labels.contains_key(name) {
+            return Err(loc.error(format!("label {} is already defined", name)));
+        }
+        self.labels.insert(name.to_string(), (self.addr, loc));
+        Ok(())
+    }
+
+    fn include(&mut self, path: &Path, loc: &Loc, depth: usize) -> Result<(), AsmError> {
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(loc.error("includes nested too deeply"));
+        }
+        let source = fs::read_to_string(path)
+            .map_err(|err| loc.error(format!("cannot include {}: {}", path.display(), err)))?;
+        let name: FileName = path.display().to_string().into();
+        self.parse(name, &source, &parent(path), depth + 1)
+    }
+
+    /// Second pass: encode everything now that all labels are known
+    fn finish(self) -> Result<(Vec<u8>, SourceMap), AsmError> {
+        let mut rom = Vec::new();
+        let mut map = SourceMap::default();
+        for (item, loc) in &self.items {
+            match item {
+                Item::Bytes(values) => {
+                    for expr in values {
+                        rom.push(self.value(expr, 0xFF)? as u8);
+                    }
+                }
+                Item::Words(values) => {
+                    for expr in values {
+                        rom.extend_from_slice(&(self.value(expr, 0xFFFF)? as u16).to_be_bytes());
+                    }
+                }
+                Item::Instruction { mnemonic, operands } => {
+                    let opcode = self.instruction(mnemonic, operands, loc)?;
+                    map.lines.push(SourceLine {
+                        file: loc.file.to_string(),
+                        line: loc.line,
+                        addr: PROGRAM_START + rom.len(),
+                    });
+                    rom.extend_from_slice(&opcode.encode().to_be_bytes());
+                    if let Opcode::LDILong { addr } = opcode {
+                        rom.extend_from_slice(&addr.to_be_bytes());
+                    }
+                }
+            }
+        }
+        Ok((rom, map))
+    }
+
+    fn eval(&self, expr: &Expr, depth: usize) -> Result<i64, AsmError> {
+        let mut total: i64 = 0;
+        for (negative, term, loc) in &expr.terms {
+            let value = match term {
+                Term::Number(n) => *n,
+                Term::Symbol(name) => {
+                    if let Some((addr, _)) = self.labels.get(name) {
+                        *addr as i64
+                    } else if let Some((constant, _)) = self.constants.get(name) {
+                        if depth > self.constants.len() {
+                            return Err(
+                                loc.error(format!("{} is defined in terms of itself", name))
+                            );
+                        }
+                        self.eval(constant, depth + 1)?
+                    } else {
+                        return Err(loc.error(format!("undefined symbol {}", name)));
+                    }
+                }
+            };
+            total = if *negative {
+                total.wrapping_sub(value)
+            } else {
+                total.wrapping_add(value)
+            };
+        }
+        Ok(total)
+    }
+
+    /// Evaluate `expr`, which must lie within `0..=max`
+    fn value(&self, expr: &Expr, max: i64) -> Result<i64, AsmError> {
+        let value = self.eval(expr, 0)?;
+        if !(0..=max).contains(&value) {
+            let loc = &expr.terms[0].2;
+            return Err(loc.error(format!(
+                "value {} does not fit, expected 0 to {:#X}",
+                value, max
+            )));
+        }
+        Ok(value)
+    }
+
+    fn instruction(
+        &self,
+        mnemonic: &str,
+        operands: &[(Operand, Loc)],
+        loc: &Loc,
+    ) -> Result<Opcode, AsmError> {
+        use Operand as O;
+
+        let nib = |n: u8| Nibble::from_low(n);
+        let addr = |e: &Expr| self.value(e, 0xFFF).map(|v| v as u16);
+        let byte = |e: &Expr| self.value(e, 0xFF).map(|v| v as u8);
+        let nibble = |e: &Expr| self.value(e, 0xF).map(|v| nib(v as u8));
+        let ops: Vec<&Operand> = operands.iter().map(|(op, _)| op).collect();
+
+        let upper = mnemonic.to_ascii_uppercase();
+        let opcode = match (upper.as_str(), ops.as_slice()) {
+            ("CLS", []) => Opcode::CLS,
+            ("RET", []) => Opcode::RET,
+            ("SCR", []) => Opcode::SCR,
+            ("SCL", []) => Opcode::SCL,
+            ("EXIT", []) => Opcode::EXIT,
+            ("LOW", []) => Opcode::LOW,
+            ("HIGH", []) => Opcode::HIGH,
+            ("AUDIO", []) => Opcode::AUDIO,
+
+            ("SCD", [O::Value(n)]) => Opcode::SCD { n: nibble(n)? },
+            ("SCU", [O::Value(n)]) => Opcode::SCU { n: nibble(n)? },
+            ("PLANE", [O::Value(n)]) => Opcode::PLANE { n: nibble(n)? },
+            ("PITCH", [O::V(x)]) => Opcode::PITCH { x: nib(*x) },
+
+            ("SYS", [O::Value(a)]) => Opcode::Sys { addr: addr(a)? },
+            ("JP", [O::Value(a)]) => Opcode::JP { addr: addr(a)? },
+            ("JP", [O::V(0), O::Value(a)]) => Opcode::JPPlusV0 { addr: addr(a)? },
+            ("CALL", [O::Value(a)]) => Opcode::CALL { addr: addr(a)? },
+
+            ("SE", [O::V(x), O::Value(b)]) => Opcode::SEByte {
+                x: nib(*x),
+                byte: byte(b)?,
+            },
+            ("SE", [O::V(x), O::V(y)]) => Opcode::SEReg {
+                x: nib(*x),
+                y: nib(*y),
+            },
+            ("SNE", [O::V(x), O::Value(b)]) => Opcode::SNEByte {
+                x: nib(*x),
+                byte: byte(b)?,
+            },
+            ("SNE", [O::V(x), O::V(y)]) => Opcode::SNEReg {
+                x: nib(*x),
+                y: nib(*y),
+            },
+
+            ("LD", [O::V(x), O::Value(b)]) => Opcode::LDByte {
+                x: nib(*x),
+                byte: byte(b)?,
+            },
+            ("LD", [O::V(x), O::V(y)]) => Opcode::LDReg {
+                x: nib(*x),
+                y: nib(*y),
+            },
+            ("LD", [O::I, O::Value(a)]) => Opcode::LDI { addr: addr(a)? },
+            ("LD", [O::I, O::Long(a)]) => Opcode::LDILong {
+                addr: self.value(a, 0xFFFF)? as u16,
+            },
+            ("LD", [O::V(x), O::DT]) => Opcode::LDxDT { x: nib(*x) },
+            ("LD", [O::V(x), O::K]) => Opcode::LDxK { x: nib(*x) },
+            ("LD", [O::DT, O::V(x)]) => Opcode::LDdtX { x: nib(*x) },
+            ("LD", [O::ST, O::V(x)]) => Opcode::LDstX { x: nib(*x) },
+            ("LD", [O::F, O::V(x)]) => Opcode::LDF { x: nib(*x) },
+            ("LD", [O::HF, O::V(x)]) => Opcode::LDHF { x: nib(*x) },
+            ("LD", [O::B, O::V(x)]) => Opcode::LDB { x: nib(*x) },
+            ("LD", [O::IndirectI, O::V(x)]) => Opcode::LDIStore { x: nib(*x) },
+            ("LD", [O::V(x), O::IndirectI]) => Opcode::LDIRead { x: nib(*x) },
+            ("LD", [O::R, O::V(x)]) => Opcode::LDRStore { x: nib(*x) },
+            ("LD", [O::V(x), O::R]) => Opcode::LDRRead { x: nib(*x) },
+            ("LD", [O::IndirectI, O::VRange(x, y)]) => Opcode::LDRangeStore {
+                x: nib(*x),
+                y: nib(*y),
+            },
+            ("LD", [O::VRange(x, y), O::IndirectI]) => Opcode::LDRangeRead {
+                x: nib(*x),
+                y: nib(*y),
+            },
+
+            ("ADD", [O::V(x), O::Value(b)]) => Opcode::ADDByte {
+                x: nib(*x),
+                byte: byte(b)?,
+            },
+            ("ADD", [O::V(x), O::V(y)]) => Opcode::ADD {
+                x: nib(*x),
+                y: nib(*y),
+            },
+            ("ADD", [O::I, O::V(x)]) => Opcode::ADDI { x: nib(*x) },
+
+            ("OR", [O::V(x), O::V(y)]) => Opcode::OR {
+                x: nib(*x),
+                y: nib(*y),
+            },
+            ("AND", [O::V(x), O::V(y)]) => Opcode::AND {
+                x: nib(*x),
+                y: nib(*y),
+            },
+            ("XOR", [O::V(x), O::V(y)]) => Opcode::XOR {
+                x: nib(*x),
+                y: nib(*y),
+            },
+            ("SUB", [O::V(x), O::V(y)]) => Opcode::SUB {
+                x: nib(*x),
+                y: nib(*y),
+            },
+            ("SUBN", [O::V(x), O::V(y)]) => Opcode::SUBN {
+                x: nib(*x),
+                y: nib(*y),
+            },
+            ("SHR", [O::V(x), O::V(y)]) => Opcode::SHR {
+                x: nib(*x),
+                y: nib(*y),
+            },
+            ("SHR", [O::V(x)]) => Opcode::SHR {
+                x: nib(*x),
+                y: nib(*x),
+            },
+            ("SHL", [O::V(x), O::V(y)]) => Opcode::SHL {
+                x: nib(*x),
+                y: nib(*y),
+            },
+            ("SHL", [O::V(x)]) => Opcode::SHL {
+                x: nib(*x),
+                y: nib(*x),
+            },
+
+            ("RND", [O::V(x), O::Value(b)]) => Opcode::RND {
+                x: nib(*x),
+                byte: byte(b)?,
+            },
+            ("DRW", [O::V(x), O::V(y), O::Value(n)]) => Opcode::DRW {
+                x: nib(*x),
+                y: nib(*y),
+                n: nibble(n)?,
+            },
+            ("SKP", [O::V(x)]) => Opcode::SKP { x: nib(*x) },
+            ("SKNP", [O::V(x)]) => Opcode::SKNP { x: nib(*x) },
+
+            (
+                "CLS" | "RET" | "SCR" | "SCL" | "EXIT" | "LOW" | "HIGH" | "AUDIO" | "SCD" | "SCU"
+                | "PLANE" | "PITCH" | "SYS" | "JP" | "CALL" | "SE" | "SNE" | "LD" | "ADD" | "OR"
+                | "AND" | "XOR" | "SUB" | "SUBN" | "SHR" | "SHL" | "RND" | "DRW" | "SKP" | "SKNP",
+                _,
+            ) => {
+                let at = operands.first().map_or(loc, |(_, at)| at);
+                return Err(at.error(format!("invalid operands for {}", upper)));
+            }
+            _ => return Err(loc.error(format!("unknown instruction {}", mnemonic))),
+        };
+        Ok(opcode)
+    }
+}
+
+/// The line up to a `;` comment, leaving quoted text alone
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn skip_space(text: &str, offset: usize) -> usize {
+    text[offset..]
+        .find(|c: char| !c.is_whitespace())
+        .map_or(text.len(), |i| offset + i)
+}
+
+/// Word starting at `offset` and the offset just past it
+fn next_word(text: &str, offset: usize) -> (&str, usize) {
+    let start = skip_space(text, offset);
+    let end = text[start..]
+        .find(char::is_whitespace)
+        .map_or(text.len(), |i| start + i);
+    (&text[start..end], end)
+}
+
+/// Comma separated operands after `offset`, trimmed, with their offsets
+fn split_operands(text: &str, offset: usize) -> Vec<(&str, usize)> {
+    let start = skip_space(text, offset);
+    if start >= text.len() {
+        return Vec::new();
+    }
+    let mut operands = Vec::new();
+    let mut from = start;
+    for part in text[start..].split(',') {
+        let lead = part.len() - part.trim_start().len();
+        operands.push((part.trim(), from + lead));
+        from += part.len() + 1;
+    }
+    operands
+}
+
+/// Operand keywords, which a label or constant of the same name would hide
+const RESERVED: [&str; 8] = ["I", "DT", "ST", "K", "F", "B", "HF", "R"];
+
+fn check_name(name: &str, loc: &Loc) -> Result<(), AsmError> {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid || parse_register(name).is_some() {
+        return Err(loc.error(format!("invalid name {:?}", name)));
+    }
+    if RESERVED.iter().any(|word| word.eq_ignore_ascii_case(name)) {
+        return Err(loc.error(format!("{} is reserved for an operand", name)));
+    }
+    Ok(())
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix(['V', 'v'])?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+fn parse_operand(text: &str, loc: &Loc, at: &dyn Fn(usize) -> Loc) -> Result<Operand, AsmError> {
+    if text.is_empty() {
+        return Err(loc.error("missing operand"));
+    }
+    if let Some(x) = parse_register(text) {
+        return Ok(Operand::V(x));
+    }
+    if let Some((x, y)) = text.split_once('-') {
+        if let (Some(x), Some(y)) = (parse_register(x.trim()), parse_register(y.trim())) {
+            return Ok(Operand::VRange(x, y));
+        }
+    }
+    let upper = text.to_ascii_uppercase();
+    Ok(match upper.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::DT,
+        "ST" => Operand::ST,
+        "K" => Operand::K,
+        "F" => Operand::F,
+        "B" => Operand::B,
+        "HF" => Operand::HF,
+        "R" => Operand::R,
+        _ if upper.starts_with("LONG ") => {
+            let start = skip_space(text, 4);
+            Operand::Long(parse_expr(&text[start..], &at(start), &|o| at(start + o))?)
+        }
+        _ => Operand::Value(parse_expr(text, loc, at)?),
+    })
+}
+
+/// `term (+|- term)*`, `at` gives the location of an offset in `text`
+fn parse_expr(text: &str, loc: &Loc, at: &dyn Fn(usize) -> Loc) -> Result<Expr, AsmError> {
+    let mut terms = Vec::new();
+    let mut negative = false;
+    let mut offset = 0;
+    loop {
+        let start = skip_space(text, offset);
+        let end = text[start..]
+            .find(['+', '-'])
+            .map_or(text.len(), |i| start + i);
+        let token = text[start..end].trim();
+        if token.is_empty() {
+            return Err(if start >= text.len() {
+                loc.error("missing value")
+            } else {
+                at(start).error("missing value")
+            });
+        }
+        terms.push((negative, parse_term(token, &at(start))?, at(start)));
+        if end >= text.len() {
+            break;
+        }
+        negative = text[end..].starts_with('-');
+        offset = end + 1;
+    }
+    Ok(Expr { terms })
+}
+
+fn parse_term(token: &str, loc: &Loc) -> Result<Term, AsmError> {
+    let number = if let Some(hex) = token
+        .strip_prefix("0x")
+        .or_else(|| token.strip_prefix("0X"))
+        .or_else(|| token.strip_prefix('$'))
+    {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = token
+        .strip_prefix("0b")
+        .or_else(|| token.strip_prefix("0B"))
+    {
+        i64::from_str_radix(bin, 2).ok()
+    } else if token.starts_with(|c: char| c.is_ascii_digit()) {
+        token.parse().ok()
+    } else {
+        check_name(token, loc)?;
+        return Ok(Term::Symbol(token.to_string()));
+    };
+    number
+        .map(Term::Number)
+        .ok_or_else(|| loc.error(format!("invalid number {}", token)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::disassemble;
+    use crate::platform::Platform;
+
+    fn err(source: &str) -> (usize, usize, String) {
+        let e = assemble(source).unwrap_err();
+        (e.line, e.column, e.message)
+    }
+
+    #[test]
+    fn test_assembles_instructions_labels_and_data() {
+        let rom = assemble(
+            "SPEED EQU 2
+            start:  LD V0, SPEED   ; comment
+                    LD I, sprite
+                    DRW V0, V1, 5
+            loop:   JP loop
+                    LD I, LONG sprite + 0x100
+            sprite: db 0xF0, $90, 0b11110000
+                    dw 0x1234",
+        )
+        .unwrap();
+        assert_eq!(
+            rom,
+            [
+                0x60, 0x02, 0xA2, 0x0C, 0xD0, 0x15, 0x12, 0x06, 0xF0, 0x00, 0x03, 0x0C, 0xF0, 0x90,
+                0xF0, 0x12, 0x34
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reports_line_and_column() {
+        assert_eq!(
+            err("CLS\n  FOO V0"),
+            (2, 3, "unknown instruction FOO".into())
+        );
+        assert_eq!(
+            err("LD V0, 0x100"),
+            (1, 8, "value 256 does not fit, expected 0 to 0xFF".into())
+        );
+        assert_eq!(
+            err("JP 0x2345"),
+            (1, 4, "value 9029 does not fit, expected 0 to 0xFFF".into())
+        );
+        assert_eq!(err("JP nowhere"), (1, 4, "undefined symbol nowhere".into()));
+        assert_eq!(err("LD DT, 5"), (1, 4, "invalid operands for LD".into()));
+        assert_eq!(
+            err("a:\na: CLS"),
+            (2, 1, "label a is already defined".into())
+        );
+        assert_eq!(
+            err("CLS\nb: JP b"),
+            (2, 1, "b is reserved for an operand".into())
+        );
+    }
+
+    #[test]
+    fn test_round_trips_disassembly() {
+        let rom = [
+            0x22, 0x0A, 0x60, 0x05, 0x5A, 0xB2, 0xF0, 0x00, 0x12, 0x34, 0x00, 0xFF, 0xF3, 0x01,
+            0xB2, 0x00, 0x00, 0xEE, 0xAA, 0x55,
+        ];
+        let listing = disassemble(&rom, Platform::XoChip).to_string();
+        assert_eq!(assemble(&listing).unwrap(), rom);
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(format!("chip8-asm-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("sprite.asm"), "sprite: DB 0x80\n").unwrap();
+        fs::write(
+            dir.join("main.asm"),
+            "LD I, sprite\nINCLUDE \"sprite.asm\"\n",
+        )
+        .unwrap();
+
+        let rom = assemble_file(&dir.join("main.asm")).unwrap();
+        assert_eq!(rom, [0xA2, 0x02, 0x80]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_source_map() {
+        let dir = std::env::temp_dir().join(format!("chip8-asm-map-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let main = dir.join("main.asm");
+        fs::write(&main, "; start\nCLS\nDB 1, 2\n\nloop: JP loop\n").unwrap();
+
+        let (_, map) = assemble_file_with_map(&main).unwrap();
+        let file = main.display().to_string();
+        assert_eq!(map.lines().len(), 2);
+        assert_eq!(map.find_line(&file, 1).unwrap().addr, 0x200);
+        let jump = map.find_line(&file, 3).unwrap();
+        assert_eq!((jump.line, jump.addr), (5, 0x204));
+        assert_eq!(map.find_addr(0x204).unwrap().line, 5);
+        assert!(map.find_addr(0x202).is_none());
+        assert!(map.find_line(&file, 6).is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,14 @@
+use std::io;
+
+use crate::audio::registers::AudioRegisters;
+
+pub trait AudioSink {
+    /// Called once per 60 Hz frame with whether the buzzer sounds, that is
+    /// whether the sound timer is running, and the XO-CHIP audio registers.
+    fn frame(&mut self, buzzer: bool, registers: &AudioRegisters) -> io::Result<()>;
+
+    /// Flush whatever the sink buffered, called once when emulation ends
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,45 @@
+use std::io::{self, Write};
+
+use crate::audio::audio_sink::AudioSink;
+use crate::audio::registers::AudioRegisters;
+
+/// Rings the terminal bell whenever the buzzer starts sounding.
+pub struct BellSink<W: Write> {
+    out: W,
+    sounding: bool,
+}
+
+impl<W: Write> BellSink<W> {
+    pub fn new(out: W) -> Self {
+        BellSink {
+            out,
+            sounding: false,
+        }
+    }
+}
+
+impl<W: Write> AudioSink for BellSink<W> {
+    fn frame(&mut self, buzzer: bool, _registers: &AudioRegisters) -> io::Result<()> {
+        if buzzer && !self.sounding {
+            self.out.write_all(b"\x07")?;
+            self.out.flush()?;
+        }
+        self.sounding = buzzer;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rings_once_per_beep() {
+        let registers = AudioRegisters::new();
+        let mut sink = BellSink::new(Vec::new());
+        for buzzer in [false, true, true, false, true] {
+            sink.frame(buzzer, &registers).unwrap();
+        }
+        assert_eq!(sink.out, b"\x07\x07");
+    }
+}
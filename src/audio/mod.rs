@@ -0,0 +1,7 @@
+pub mod audio_sink;
+pub mod bell;
+pub mod null;
+pub mod pcm;
+pub mod registers;
+pub mod square;
+pub mod wav;
@@ -0,0 +1,14 @@
+use std::io;
+
+use crate::audio::audio_sink::AudioSink;
+use crate::audio::registers::AudioRegisters;
+
+/// Sink that discards all audio, for tests and silent runs.
+#[derive(Default)]
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn frame(&mut self, _buzzer: bool, _registers: &AudioRegisters) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,38 @@
+use std::io::{self, Write};
+
+use crate::audio::audio_sink::AudioSink;
+use crate::audio::registers::AudioRegisters;
+use crate::audio::square::{SquareWave, Tone};
+
+/// Streams raw signed 16-bit little-endian mono PCM at
+/// [`SAMPLE_RATE`](crate::audio::square::SAMPLE_RATE), for piping into
+/// `aplay -f S16_LE -r 44100 -c 1`.
+pub struct PcmSink<W: Write> {
+    out: W,
+    wave: SquareWave,
+    samples: Vec<i16>,
+}
+
+impl<W: Write> PcmSink<W> {
+    pub fn new(out: W, tone: Tone) -> Self {
+        PcmSink {
+            out,
+            wave: SquareWave::new(tone),
+            samples: Vec::new(),
+        }
+    }
+}
+
+impl<W: Write> AudioSink for PcmSink<W> {
+    fn frame(&mut self, buzzer: bool, registers: &AudioRegisters) -> io::Result<()> {
+        self.samples.clear();
+        self.wave.frame(buzzer, registers, &mut self.samples);
+
+        let bytes: Vec<u8> = self.samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        self.out.write_all(&bytes)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
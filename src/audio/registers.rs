@@ -0,0 +1,27 @@
+/// XO-CHIP audio state, set by F002 and FX3A.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioRegisters {
+    /// 128 one-bit samples, `None` until a program loads a pattern
+    pub pattern: Option<[u8; 16]>,
+    pub pitch: u8,
+}
+
+impl AudioRegisters {
+    pub fn new() -> Self {
+        AudioRegisters {
+            pattern: None,
+            pitch: 64,
+        }
+    }
+
+    /// Samples per second the pattern is played back at
+    pub fn playback_rate(&self) -> f64 {
+        4000.0 * 2f64.powf((self.pitch as f64 - 64.0) / 48.0)
+    }
+}
+
+impl Default for AudioRegisters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,96 @@
+use crate::audio::registers::AudioRegisters;
+use crate::scheduler::FRAME_RATE;
+
+/// Samples per second of the generated PCM
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// Pitch and loudness of the buzzer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    /// Frequency of the square wave in Hz
+    pub frequency: f64,
+    /// Volume between 0.0 and 1.0
+    pub volume: f64,
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Tone {
+            frequency: 440.0,
+            volume: 0.25,
+        }
+    }
+}
+
+/// Turns the per-frame buzzer state into signed 16-bit mono PCM.
+///
+/// Plays a square wave at the tone frequency, or the XO-CHIP pattern at its
+/// pitch once a program has loaded one.
+pub struct SquareWave {
+    tone: Tone,
+    /// Position within the current period, 0.0 to 1.0
+    phase: f64,
+}
+
+impl SquareWave {
+    pub fn new(tone: Tone) -> Self {
+        SquareWave { tone, phase: 0.0 }
+    }
+
+    /// Append one frame worth of samples to `out`
+    pub fn frame(&mut self, buzzer: bool, registers: &AudioRegisters, out: &mut Vec<i16>) {
+        let samples = (SAMPLE_RATE / FRAME_RATE) as usize;
+        if !buzzer {
+            self.phase = 0.0;
+            out.extend(std::iter::repeat_n(0, samples));
+            return;
+        }
+
+        let amplitude = (self.tone.volume.clamp(0.0, 1.0) * i16::MAX as f64) as i16;
+        let step = match registers.pattern {
+            // The 128 bit pattern is one period
+            Some(_) => registers.playback_rate() / 128.0 / SAMPLE_RATE as f64,
+            None => self.tone.frequency / SAMPLE_RATE as f64,
+        };
+
+        for _ in 0..samples {
+            let high = match registers.pattern {
+                Some(pattern) => {
+                    let bit = (self.phase * 128.0) as usize % 128;
+                    pattern[bit / 8] & (0x80 >> (bit % 8)) != 0
+                }
+                None => self.phase < 0.5,
+            };
+            out.push(if high { amplitude } else { -amplitude });
+            self.phase = (self.phase + step).fract();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_square_wave_frame() {
+        let mut wave = SquareWave::new(Tone {
+            frequency: SAMPLE_RATE as f64 / 4.0,
+            volume: 1.0,
+        });
+        let mut out = Vec::new();
+        wave.frame(true, &AudioRegisters::new(), &mut out);
+
+        assert_eq!(out.len(), 735);
+        assert_eq!(&out[..4], &[i16::MAX, i16::MAX, -i16::MAX, -i16::MAX]);
+    }
+
+    #[test]
+    fn test_silence_when_buzzer_off() {
+        let mut wave = SquareWave::new(Tone::default());
+        let mut out = Vec::new();
+        wave.frame(false, &AudioRegisters::new(), &mut out);
+
+        assert_eq!(out.len(), 735);
+        assert!(out.iter().all(|&s| s == 0));
+    }
+}
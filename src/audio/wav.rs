@@ -0,0 +1,92 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+use crate::audio::audio_sink::AudioSink;
+use crate::audio::registers::AudioRegisters;
+use crate::audio::square::{SquareWave, Tone, SAMPLE_RATE};
+
+const HEADER_SIZE: u32 = 44;
+
+/// Records the buzzer into a 16-bit mono WAV file.
+///
+/// The header is written up front with empty sizes and patched by
+/// [`AudioSink::finish`].
+pub struct WavSink<W: Write + Seek> {
+    out: W,
+    wave: SquareWave,
+    samples: Vec<i16>,
+    data_size: u32,
+}
+
+impl<W: Write + Seek> WavSink<W> {
+    pub fn new(mut out: W, tone: Tone) -> io::Result<Self> {
+        write_header(&mut out, 0)?;
+        Ok(WavSink {
+            out,
+            wave: SquareWave::new(tone),
+            samples: Vec::new(),
+            data_size: 0,
+        })
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+fn write_header<W: Write>(out: &mut W, data_size: u32) -> io::Result<()> {
+    let byte_rate = SAMPLE_RATE * 2;
+    out.write_all(b"RIFF")?;
+    out.write_all(&(HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+    out.write_all(b"fmt ")?;
+    out.write_all(&16u32.to_le_bytes())?; // fmt chunk size
+    out.write_all(&1u16.to_le_bytes())?; // PCM
+    out.write_all(&1u16.to_le_bytes())?; // mono
+    out.write_all(&SAMPLE_RATE.to_le_bytes())?;
+    out.write_all(&byte_rate.to_le_bytes())?;
+    out.write_all(&2u16.to_le_bytes())?; // block align
+    out.write_all(&16u16.to_le_bytes())?; // bits per sample
+    out.write_all(b"data")?;
+    out.write_all(&data_size.to_le_bytes())
+}
+
+impl<W: Write + Seek> AudioSink for WavSink<W> {
+    fn frame(&mut self, buzzer: bool, registers: &AudioRegisters) -> io::Result<()> {
+        self.samples.clear();
+        self.wave.frame(buzzer, registers, &mut self.samples);
+
+        let bytes: Vec<u8> = self.samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        self.out.write_all(&bytes)?;
+        self.data_size += bytes.len() as u32;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.seek(SeekFrom::Start(0))?;
+        write_header(&mut self.out, self.data_size)?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_header_sizes_patched() {
+        let mut sink = WavSink::new(Cursor::new(Vec::new()), Tone::default()).unwrap();
+        let registers = AudioRegisters::new();
+        sink.frame(true, &registers).unwrap();
+        sink.frame(false, &registers).unwrap();
+        sink.finish().unwrap();
+
+        let wav = sink.into_inner().into_inner();
+        let data_size = 2 * 735 * 2;
+        assert_eq!(wav.len(), 44 + data_size);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(wav[4..8], ((36 + data_size) as u32).to_le_bytes());
+        assert_eq!(wav[40..44], (data_size as u32).to_le_bytes());
+    }
+}
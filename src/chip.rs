@@ -1,10 +1,19 @@
+use crate::audio::registers::AudioRegisters;
 use crate::controls::{Chip8Key, Keypad};
 use crate::display::display_trait::Ch8Display;
+use crate::error::{Chip8Error, Fault, MachineState};
+use crate::font::{BIG_FONT_DATA, BIG_FONT_START, FONT_DATA, FONT_START};
 use crate::memory::Memory;
+use crate::nibble::Nibble;
+use crate::opcode::Opcode;
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use crate::random::Random;
 use crate::registers::Registers;
 use crate::stack::Stack;
 use crate::timers::Timers;
-use crate::*;
+use crate::trace::Tracer;
+use log::debug;
 
 pub struct Chip {
     pub memory: Memory,
@@ -15,26 +24,194 @@ pub struct Chip {
     pub display: Box<dyn Ch8Display>,
     pub keypad: Keypad,
     pub wait_for_input: Option<u8>,
+    pub quirks: Quirks,
+    pub platform: Platform,
+    pub audio: AudioRegisters,
+    /// Source of CXNN random numbers, seeded from entropy by default
+    pub random: Random,
+    /// Receives every instruction before it executes, when set
+    pub tracer: Option<Tracer>,
+    /// Set by the SUPER-CHIP 00FD exit instruction
+    pub halted: bool,
 }
 
-const FONT_START: usize = 0x50;
+/// Where ROMs are loaded and execution starts
+pub const PROGRAM_START: usize = 0x200;
+
+/// Outcome of a single [`Chip::step`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepResult {
+    /// Opcode that was executed, `None` while blocked on FX0A
+    pub opcode: Option<Opcode>,
+    /// Whether the framebuffer changed
+    pub drew: bool,
+    /// Whether the chip is waiting for a key press
+    pub waiting_for_key: bool,
+    /// Whether the program has exited
+    pub halted: bool,
+}
+
+/// Outcome of a [`Chip::run_frame`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameResult {
+    /// Number of instructions executed during the frame
+    pub executed: usize,
+    /// Whether any instruction changed the framebuffer
+    pub drew: bool,
+    /// Whether the frame ended waiting for a key press
+    pub waiting_for_key: bool,
+    /// Whether the program has exited
+    pub halted: bool,
+}
 
 impl Chip {
     pub fn new(display: impl Ch8Display + 'static) -> Self {
+        Self::with_platform(display, Platform::default())
+    }
+
+    /// Chip implementing `platform`, with its memory size and default quirks
+    pub fn with_platform(display: impl Ch8Display + 'static, platform: Platform) -> Self {
         let mut chip = Chip {
-            memory: Memory::new(),
-            program_counter: 512, // 512th position
+            memory: Memory::with_size(platform.memory_size()),
+            program_counter: PROGRAM_START,
             stack: Stack::new(),
             timers: Timers::new(),
             registers: Registers::new(),
             display: Box::new(display),
             keypad: Keypad::new(),
             wait_for_input: None,
+            quirks: platform.default_quirks(),
+            platform,
+            audio: AudioRegisters::new(),
+            random: Random::from_entropy(),
+            tracer: None,
+            halted: false,
         };
-        chip.set_memory_at_position(FONT_START, &FONT_DATA);
+        chip.set_memory_at_position(FONT_START, &FONT_DATA)
+            .expect("font fits in memory");
+        chip.set_memory_at_position(BIG_FONT_START, &BIG_FONT_DATA)
+            .expect("font fits in memory");
         chip
     }
 
+    /// Fetch, decode and execute one instruction.
+    ///
+    /// While FX0A is pending nothing is executed until a key has been
+    /// pressed through [`Chip::try_press`]. Once the program has exited
+    /// nothing is executed at all.
+    pub fn step(&mut self) -> Result<StepResult, Chip8Error> {
+        if self.halted {
+            return Ok(StepResult {
+                opcode: None,
+                drew: false,
+                waiting_for_key: false,
+                halted: true,
+            });
+        }
+
+        if let Some(vx) = self.wait_for_input {
+            match self.wait_for_key() {
+                Some(key) => {
+                    self.registers.set(Nibble::from_low(vx), key);
+                    self.wait_for_input = None;
+                }
+                None => {
+                    return Ok(StepResult {
+                        opcode: None,
+                        drew: false,
+                        waiting_for_key: true,
+                        halted: false,
+                    });
+                }
+            }
+        }
+
+        let pc = self.program_counter;
+        let raw = self.next_u16().map_err(|f| self.fault(pc, None, f))?;
+        let mut opcode = Opcode::decode(raw);
+        if opcode.platform() > self.platform {
+            return Err(self.fault(pc, Some(raw), Fault::InvalidOpcode));
+        }
+        if let Opcode::LDILong { addr } = &mut opcode {
+            *addr = self.next_u16().map_err(|f| self.fault(pc, Some(raw), f))?;
+        }
+        debug!("{:04X} | {}", raw, opcode);
+        if let Some(mut tracer) = self.tracer.take() {
+            tracer.record(self, pc, &opcode);
+            self.tracer = Some(tracer);
+        }
+        self.execute(opcode)
+            .map_err(|f| self.fault(pc, Some(raw), f))?;
+
+        Ok(StepResult {
+            opcode: Some(opcode),
+            drew: matches!(
+                opcode,
+                Opcode::CLS
+                    | Opcode::DRW { .. }
+                    | Opcode::SCD { .. }
+                    | Opcode::SCR
+                    | Opcode::SCL
+                    | Opcode::LOW
+                    | Opcode::HIGH
+                    | Opcode::SCU { .. }
+            ),
+            waiting_for_key: self.wait_for_input.is_some(),
+            halted: self.halted,
+        })
+    }
+
+    /// Attach the faulting instruction and machine state to a fault
+    fn fault(&self, pc: usize, opcode: Option<u16>, fault: Fault) -> Chip8Error {
+        Chip8Error::Execution {
+            pc,
+            opcode,
+            fault,
+            state: Box::new(MachineState::capture(self)),
+        }
+    }
+
+    /// Tick the timers once and execute up to `instructions` instructions,
+    /// stopping early when the chip exits, starts waiting for a key or, with the
+    /// display wait quirk, after drawing a sprite.
+    pub fn run_frame(&mut self, instructions: usize) -> Result<FrameResult, Chip8Error> {
+        self.run_frame_until(instructions, |_| false)
+    }
+
+    /// [`Chip::run_frame`], also stopping before any instruction for which
+    /// `stop` returns true, which debuggers use for breakpoints.
+    pub fn run_frame_until(
+        &mut self,
+        instructions: usize,
+        mut stop: impl FnMut(&Chip) -> bool,
+    ) -> Result<FrameResult, Chip8Error> {
+        self.timers.tick();
+
+        let mut frame = FrameResult {
+            executed: 0,
+            drew: false,
+            waiting_for_key: false,
+            halted: false,
+        };
+        for _ in 0..instructions {
+            if stop(self) {
+                break;
+            }
+            let step = self.step()?;
+            frame.executed += step.opcode.is_some() as usize;
+            frame.drew |= step.drew;
+            frame.waiting_for_key = step.waiting_for_key;
+            frame.halted = step.halted;
+            if step.waiting_for_key || step.halted {
+                break;
+            }
+            if self.quirks.display_wait && matches!(step.opcode, Some(Opcode::DRW { .. })) {
+                break;
+            }
+        }
+        Ok(frame)
+    }
+
     // EX9E — skip if key in Vx is pressed
     pub fn skip_if_pressed(&mut self, vx: u8) {
         self.skip_if(
@@ -55,29 +232,52 @@ impl Chip {
     pub fn try_press(&mut self, ch: char) -> Option<Chip8Key> {
         if let Some(key) = self.keypad.lookup(ch) {
             // println!("Pressed '{}' → CHIP-8 key {:X}", ch, key.as_u8());
-            self.keypad.clear();
-            self.keypad.press(key);
+            self.press(key);
             return Some(key);
         }
         None
     }
 
+    /// Press `key`, releasing any other held key
+    pub fn press(&mut self, key: Chip8Key) {
+        self.keypad.clear();
+        self.keypad.press(key);
+    }
+
+    /// Hold `key` down, along with any other held key
+    pub fn key_down(&mut self, key: Chip8Key) {
+        self.keypad.press(key);
+    }
+
+    pub fn key_up(&mut self, key: Chip8Key) {
+        self.keypad.release(key);
+    }
+
     // FX0A — wait for key press (non-blocking)
     pub fn wait_for_key(&mut self) -> Option<u8> {
         self.keypad.take_last_pressed().map(|k| k.as_u8())
     }
 
-    pub fn opcode_dxyn(&mut self, x: Nibble, y: Nibble, n: Nibble) {
+    pub fn opcode_dxyn(&mut self, x: Nibble, y: Nibble, n: Nibble) -> Result<(), Fault> {
         let vx = self.registers.get(x);
         let vy = self.registers.get(y);
 
         let i = self.registers.get_i() as usize;
-        let sprite = &self.memory.slice(i, i + n.as_usize());
+        let clip = self.quirks.clipping;
+        // XO-CHIP stores the sprite once per selected plane, back to back
+        let planes = self.display.framebuffer().planes().count_ones() as usize;
 
-        let collision = self.display.draw_sprite(vx, vy, sprite);
-        self.display.render();
+        // DXY0 draws a 16x16 SUPER-CHIP sprite
+        let collision = if n.as_u8() == 0 && self.platform >= Platform::SuperChip {
+            let sprite = self.memory.slice(i, i + 32 * planes)?;
+            self.display.draw_large_sprite(vx, vy, sprite, clip)
+        } else {
+            let sprite = self.memory.slice(i, i + n.as_usize() * planes)?;
+            self.display.draw_sprite(vx, vy, sprite, clip)
+        };
 
         self.registers.set(Nibble::from_low(0xF), collision as u8);
+        Ok(())
     }
 
     pub fn increment_counter(&mut self, n: usize) {
@@ -91,28 +291,183 @@ impl Chip {
         let idx = Nibble::from_opcode(value, 4);
         self.registers.get(idx)
     }
-    pub fn next(&mut self) -> u8 {
-        let result = self.memory.read(self.program_counter);
+    pub fn next_u8(&mut self) -> Result<u8, Fault> {
+        let result = self.memory.read(self.program_counter)?;
         self.increment_counter(1);
-        result
+        Ok(result)
     }
-    pub fn next_u16(&mut self) -> u16 {
-        let result = self.memory.read_u16(self.program_counter);
+    pub fn next_u16(&mut self) -> Result<u16, Fault> {
+        let result = self.memory.read_u16(self.program_counter)?;
         self.increment_counter(2);
-        result
+        Ok(result)
     }
 
-    pub fn load_rom(&mut self, bytes: &[u8]) {
-        self.set_memory_at_position(512, bytes)
-    }
-    pub fn set_memory_at_position(&mut self, idx: usize, bytes: &[u8]) {
-        for (i, &byte) in bytes.iter().enumerate() {
-            self.memory.write(idx + i, byte);
+    pub fn load_rom(&mut self, bytes: &[u8]) -> Result<(), Chip8Error> {
+        let max = self.memory.len() - PROGRAM_START;
+        if bytes.len() > max {
+            return Err(Chip8Error::RomTooLarge {
+                size: bytes.len(),
+                max,
+            });
         }
+        self.set_memory_at_position(PROGRAM_START, bytes)
+            .map_err(|f| self.fault(self.program_counter, None, f))
+    }
+    pub fn set_memory_at_position(&mut self, idx: usize, bytes: &[u8]) -> Result<(), Fault> {
+        self.memory.load(idx, bytes)
     }
+    /// Skip the next instruction, which is four bytes long for the
+    /// XO-CHIP F000 NNNN long index load.
     pub fn skip_if(&mut self, condition: bool) {
         if condition {
-            self.increment_counter(2);
+            let next = self.memory.read_u16(self.program_counter).unwrap_or(0);
+            if self.platform >= Platform::XoChip && next == 0xF000 {
+                self.increment_counter(4);
+            } else {
+                self.increment_counter(2);
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::headless::HeadlessDisplay;
+
+    fn chip_with_rom(rom: &[u8]) -> Chip {
+        let mut chip = Chip::new(HeadlessDisplay::new());
+        chip.load_rom(rom).unwrap();
+        chip
+    }
+
+    #[test]
+    fn test_step_executes_one_instruction() {
+        // LD V3, 0x05
+        let mut chip = chip_with_rom(&[0x63, 0x05]);
+
+        let step = chip.step().unwrap();
+        assert_eq!(
+            step.opcode,
+            Some(Opcode::LDByte {
+                x: Nibble::from_low(3),
+                byte: 0x05
+            })
+        );
+        assert!(!step.drew);
+        assert!(!step.waiting_for_key);
+        assert_eq!(chip.registers.get(Nibble::from_low(3)), 0x05);
+        assert_eq!(chip.program_counter, 514);
+    }
+
+    #[test]
+    fn test_step_reports_draw() {
+        // LD F, V0 ; DRW V0, V0, 5
+        let mut chip = chip_with_rom(&[0xF0, 0x29, 0xD0, 0x05]);
+
+        assert!(!chip.step().unwrap().drew);
+        assert!(chip.step().unwrap().drew);
+    }
+
+    #[test]
+    fn test_step_waits_for_key() {
+        // LD V2, K ; LD V3, 0x01
+        let mut chip = chip_with_rom(&[0xF2, 0x0A, 0x63, 0x01]);
+
+        assert!(chip.step().unwrap().waiting_for_key);
+        let blocked = chip.step().unwrap();
+        assert_eq!(blocked.opcode, None);
+        assert!(blocked.waiting_for_key);
+
+        chip.try_press('w');
+        let resumed = chip.step().unwrap();
+        assert!(!resumed.waiting_for_key);
+        assert_eq!(chip.registers.get(Nibble::from_low(2)), 0x5);
+        assert_eq!(chip.registers.get(Nibble::from_low(3)), 0x1);
+    }
+
+    #[test]
+    fn test_run_frame_stops_when_waiting() {
+        // LD V0, 0x01 ; LD V1, K ; LD V2, 0x01
+        let mut chip = chip_with_rom(&[0x60, 0x01, 0xF1, 0x0A, 0x62, 0x01]);
+        chip.timers.set_delay(2);
+
+        let frame = chip.run_frame(10).unwrap();
+        assert_eq!(frame.executed, 2);
+        assert!(frame.waiting_for_key);
+        assert_eq!(chip.timers.get_delay(), 1);
+        assert_eq!(chip.registers.get(Nibble::from_low(2)), 0);
+    }
+
+    #[test]
+    fn test_step_reports_fault_with_context() {
+        // LD V1, 0x07 ; RET
+        let mut chip = chip_with_rom(&[0x61, 0x07, 0x00, 0xEE]);
+        chip.step().unwrap();
+
+        match chip.step() {
+            Err(Chip8Error::Execution {
+                pc,
+                opcode,
+                fault,
+                state,
+            }) => {
+                assert_eq!(pc, 0x202);
+                assert_eq!(opcode, Some(0x00EE));
+                assert_eq!(fault, Fault::StackUnderflow);
+                assert_eq!(state.v[1], 0x07);
+            }
+            other => panic!("expected a stack underflow, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_step_rejects_invalid_opcode() {
+        let mut chip = chip_with_rom(&[0xFF, 0xFF]);
+        assert!(matches!(
+            chip.step(),
+            Err(Chip8Error::Execution {
+                fault: Fault::InvalidOpcode,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_store_out_of_bounds() {
+        // LD I, 0xFFE ; LD [I], V3
+        let mut chip = chip_with_rom(&[0xAF, 0xFE, 0xF3, 0x55]);
+        chip.step().unwrap();
+        assert!(matches!(
+            chip.step(),
+            Err(Chip8Error::Execution {
+                fault: Fault::MemoryOutOfBounds { addr: 0x1000 },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_load_rom_too_large() {
+        let mut chip = Chip::new(HeadlessDisplay::new());
+        let rom = vec![0; crate::MEMORY_SIZE - PROGRAM_START + 1];
+        assert_eq!(
+            chip.load_rom(&rom),
+            Err(Chip8Error::RomTooLarge {
+                size: rom.len(),
+                max: crate::MEMORY_SIZE - PROGRAM_START
+            })
+        );
+    }
+
+    #[test]
+    fn test_display_wait_ends_frame() {
+        // DRW V0, V0, 1 ; DRW V0, V0, 1
+        let mut chip = chip_with_rom(&[0xD0, 0x01, 0xD0, 0x01]);
+        chip.quirks = Quirks::COSMAC_VIP;
+
+        let frame = chip.run_frame(10).unwrap();
+        assert_eq!(frame.executed, 1);
+        assert!(frame.drew);
+    }
+}
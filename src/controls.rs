@@ -27,37 +27,80 @@ pub struct KeyMap {
     bindings: HashMap<char, Chip8Key>,
 }
 
+/// CHIP 8 keys row by row as they sit on the keypad
+const KEYPAD_ORDER: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC, //
+    0x4, 0x5, 0x6, 0xD, //
+    0x7, 0x8, 0x9, 0xE, //
+    0xA, 0x0, 0xB, 0xF,
+];
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        Self::qwerty()
+    }
+}
+
 impl KeyMap {
-    pub fn default() -> Self {
-        use Chip8Key as K;
+    /// Map binding nothing
+    pub fn empty() -> Self {
+        Self {
+            bindings: HashMap::new(),
+        }
+    }
 
-        let mut bindings = HashMap::new();
+    /// Bind 16 keys to the keypad, given row by row starting with `1 2 3 C`
+    pub fn from_layout(keys: [char; 16]) -> Self {
+        let mut map = Self::empty();
+        for (key, n) in keys.into_iter().zip(KEYPAD_ORDER) {
+            map.bind(key, Chip8Key(n));
+        }
+        map
+    }
 
-        // 1 2 3 C
-        bindings.insert('1', K::new(0x1).unwrap());
-        bindings.insert('2', K::new(0x2).unwrap());
-        bindings.insert('3', K::new(0x3).unwrap());
-        bindings.insert('4', K::new(0xC).unwrap());
+    /// The left of a QWERTY keyboard, `1234`, `qwer`, `asdf` and `zxcv`
+    pub fn qwerty() -> Self {
+        Self::from_layout([
+            '1', '2', '3', '4', //
+            'q', 'w', 'e', 'r', //
+            'a', 's', 'd', 'f', //
+            'z', 'x', 'c', 'v',
+        ])
+    }
 
-        // 4 5 6 D
-        bindings.insert('q', K::new(0x4).unwrap());
-        bindings.insert('w', K::new(0x5).unwrap());
-        bindings.insert('e', K::new(0x6).unwrap());
-        bindings.insert('r', K::new(0xD).unwrap());
+    /// The same keys on an AZERTY keyboard, whose digit row types
+    /// `&é"'` without shift
+    pub fn azerty() -> Self {
+        Self::from_layout([
+            '&', 'é', '"', '\'', //
+            'a', 'z', 'e', 'r', //
+            'q', 's', 'd', 'f', //
+            'w', 'x', 'c', 'v',
+        ])
+    }
 
-        // 7 8 9 E
-        bindings.insert('a', K::new(0x7).unwrap());
-        bindings.insert('s', K::new(0x8).unwrap());
-        bindings.insert('d', K::new(0x9).unwrap());
-        bindings.insert('f', K::new(0xE).unwrap());
+    /// The same keys on a Dvorak keyboard
+    pub fn dvorak() -> Self {
+        Self::from_layout([
+            '1', '2', '3', '4', //
+            '\'', ',', '.', 'p', //
+            'a', 'o', 'e', 'u', //
+            ';', 'q', 'j', 'k',
+        ])
+    }
 
-        // A 0 B F
-        bindings.insert('z', K::new(0xA).unwrap());
-        bindings.insert('x', K::new(0x0).unwrap());
-        bindings.insert('c', K::new(0xB).unwrap());
-        bindings.insert('v', K::new(0xF).unwrap());
+    pub fn bind(&mut self, key: char, chip8: Chip8Key) {
+        self.bindings.insert(key, chip8);
+    }
 
-        Self { bindings }
+    /// Remove every key bound to `chip8`
+    pub fn unbind(&mut self, chip8: Chip8Key) {
+        self.bindings.retain(|_, bound| *bound != chip8);
+    }
+
+    /// Every bound key with its CHIP 8 key
+    pub fn bindings(&self) -> impl Iterator<Item = (char, Chip8Key)> + '_ {
+        self.bindings.iter().map(|(&key, &chip8)| (key, chip8))
     }
 
     pub fn lookup(&self, key: char) -> Option<Chip8Key> {
@@ -106,7 +149,62 @@ impl Keypad {
         self.last_pressed.take()
     }
 
+    pub fn last_pressed(&self) -> Option<Chip8Key> {
+        self.last_pressed
+    }
+
+    /// Held keys as a bitmask, bit n being key n
+    pub fn pressed_mask(&self) -> u16 {
+        self.keys
+            .iter()
+            .enumerate()
+            .fold(0, |mask, (n, &down)| mask | ((down as u16) << n))
+    }
+
+    /// Restore the held keys and FX0A latch, e.g. from a save state
+    pub fn restore(&mut self, mask: u16, last_pressed: Option<Chip8Key>) {
+        for (n, key) in self.keys.iter_mut().enumerate() {
+            *key = mask & (1 << n) != 0;
+        }
+        self.last_pressed = last_pressed;
+    }
+
     pub fn lookup(&self, key: char) -> Option<Chip8Key> {
         self.keymap.bindings.get(&key).copied()
     }
 }
+
+impl Default for Keypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layouts_bind_the_same_keypad() {
+        let k = |n| Chip8Key::new(n);
+        assert_eq!(KeyMap::qwerty().lookup('4'), k(0xC));
+        assert_eq!(KeyMap::qwerty().lookup('x'), k(0x0));
+        assert_eq!(KeyMap::azerty().lookup('é'), k(0x2));
+        assert_eq!(KeyMap::azerty().lookup('q'), k(0x7));
+        assert_eq!(KeyMap::dvorak().lookup('o'), k(0x8));
+        assert_eq!(KeyMap::dvorak().lookup('k'), k(0xF));
+        for map in [KeyMap::qwerty(), KeyMap::azerty(), KeyMap::dvorak()] {
+            assert_eq!(map.bindings().count(), 16);
+        }
+    }
+
+    #[test]
+    fn test_rebind() {
+        let mut map = KeyMap::qwerty();
+        let five = Chip8Key::new(5).unwrap();
+        map.unbind(five);
+        map.bind(' ', five);
+        assert_eq!(map.lookup('w'), None);
+        assert_eq!(map.lookup(' '), Some(five));
+    }
+}
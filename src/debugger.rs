@@ -0,0 +1,274 @@
+//! Breakpoints and stepping on top of [`Chip::run_frame_until`].
+//!
+//! The debugger only decides when execution stops, showing the machine and
+//! reading commands is up to the frontend.
+
+use std::collections::BTreeSet;
+
+use crate::chip::{Chip, FrameResult, StepResult};
+use crate::error::{Chip8Error, Fault};
+use crate::memory::Memory;
+use crate::opcode::Opcode;
+
+/// How far execution goes before the debugger pauses it again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Paused,
+    /// Until a breakpoint is hit
+    Running,
+    /// Until PC reaches `addr` with at most `depth` return addresses on the
+    /// stack, which skips over recursive calls.
+    RunTo {
+        addr: usize,
+        depth: usize,
+    },
+}
+
+pub struct Debugger {
+    mode: Mode,
+    breakpoints: BTreeSet<usize>,
+    /// PC execution was resumed from, not checked against breakpoints so
+    /// continuing from a breakpoint does not stop right away.
+    resumed_at: Option<usize>,
+}
+
+impl Debugger {
+    /// Debugger that starts out paused
+    pub fn new() -> Self {
+        Debugger {
+            mode: Mode::Paused,
+            breakpoints: BTreeSet::new(),
+            resumed_at: None,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.mode == Mode::Paused
+    }
+
+    pub fn pause(&mut self) {
+        self.mode = Mode::Paused;
+    }
+
+    /// Run until a breakpoint is hit
+    pub fn resume(&mut self, chip: &Chip) {
+        self.mode = Mode::Running;
+        self.resumed_at = Some(chip.program_counter);
+    }
+
+    /// Run until PC reaches `addr` or a breakpoint is hit
+    pub fn run_to(&mut self, chip: &Chip, addr: usize) {
+        self.mode = Mode::RunTo {
+            addr,
+            depth: usize::MAX,
+        };
+        self.resumed_at = Some(chip.program_counter);
+    }
+
+    /// Add a breakpoint at `addr` or remove the one there, returning
+    /// whether there is one now.
+    pub fn toggle_breakpoint(&mut self, addr: usize) -> bool {
+        if self.breakpoints.remove(&addr) {
+            false
+        } else {
+            self.breakpoints.insert(addr);
+            true
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, addr: usize) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: usize) {
+        self.breakpoints.remove(&addr);
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Execute the instruction at PC
+    pub fn step(&mut self, chip: &mut Chip) -> Result<StepResult, Chip8Error> {
+        self.mode = Mode::Paused;
+        chip.step()
+    }
+
+    /// Execute the instruction at PC, running a CALL through to its return.
+    ///
+    /// Returns true when the step completed, false when a CALL was started
+    /// and will finish over the coming frames.
+    pub fn step_over(&mut self, chip: &mut Chip) -> Result<bool, Chip8Error> {
+        let pc = chip.program_counter;
+        match decode_at(&chip.memory, pc) {
+            Ok((Opcode::CALL { .. }, size)) => {
+                self.mode = Mode::RunTo {
+                    addr: pc + size,
+                    depth: chip.stack.as_slice().len(),
+                };
+                self.resumed_at = Some(pc);
+                Ok(false)
+            }
+            _ => self.step(chip).map(|_| true),
+        }
+    }
+
+    /// Run until the current subroutine returns, returning false when not
+    /// inside one.
+    pub fn step_out(&mut self, chip: &Chip) -> bool {
+        let stack = chip.stack.as_slice();
+        let Some(&ret) = stack.last() else {
+            return false;
+        };
+        self.mode = Mode::RunTo {
+            addr: ret as usize,
+            depth: stack.len() - 1,
+        };
+        self.resumed_at = Some(chip.program_counter);
+        true
+    }
+
+    /// Like [`Chip::run_frame`], pausing before an instruction on a
+    /// breakpoint or at the end of a run-to. Nothing runs, and the timers
+    /// do not tick, while paused.
+    pub fn run_frame(
+        &mut self,
+        chip: &mut Chip,
+        instructions: usize,
+    ) -> Result<FrameResult, Chip8Error> {
+        if self.is_paused() {
+            return Ok(FrameResult {
+                executed: 0,
+                drew: false,
+                waiting_for_key: chip.wait_for_input.is_some(),
+                halted: chip.halted,
+            });
+        }
+        chip.run_frame_until(instructions, |chip| self.should_stop(chip))
+    }
+
+    fn should_stop(&mut self, chip: &Chip) -> bool {
+        let pc = chip.program_counter;
+        if self.resumed_at.take() == Some(pc) {
+            return false;
+        }
+        let reached = match self.mode {
+            Mode::Paused => true,
+            Mode::Running => false,
+            Mode::RunTo { addr, depth } => pc == addr && chip.stack.as_slice().len() <= depth,
+        };
+        if reached || self.breakpoints.contains(&pc) {
+            self.mode = Mode::Paused;
+        }
+        self.is_paused()
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Decode the instruction at `addr` along with its size in bytes
+pub fn decode_at(memory: &Memory, addr: usize) -> Result<(Opcode, usize), Fault> {
+    let mut opcode = Opcode::decode(memory.read_u16(addr)?);
+    if let Opcode::LDILong { addr: long } = &mut opcode {
+        *long = memory.read_u16(addr + 2)?;
+    }
+    Ok((opcode, opcode.size()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::headless::HeadlessDisplay;
+
+    // 200: CALL 208 ; 202: ADD V0, 1 ; 204: JP 202 ; 206: (unused)
+    // 208: ADD V1, 1 ; 20A: RET
+    const ROM: [u8; 12] = [
+        0x22, 0x08, 0x70, 0x01, 0x12, 0x02, 0x00, 0x00, 0x71, 0x01, 0x00, 0xEE,
+    ];
+
+    fn chip() -> Chip {
+        let mut chip = Chip::new(HeadlessDisplay::new());
+        chip.load_rom(&ROM).unwrap();
+        chip
+    }
+
+    #[test]
+    fn test_paused_runs_nothing() {
+        let mut chip = chip();
+        let mut debugger = Debugger::new();
+        chip.timers.set_delay(5);
+
+        let frame = debugger.run_frame(&mut chip, 10).unwrap();
+        assert_eq!(frame.executed, 0);
+        assert_eq!(chip.program_counter, 0x200);
+        assert_eq!(chip.timers.get_delay(), 5);
+    }
+
+    #[test]
+    fn test_breakpoint_stops_and_continue_passes_it() {
+        let mut chip = chip();
+        let mut debugger = Debugger::new();
+        debugger.toggle_breakpoint(0x202);
+
+        debugger.resume(&chip);
+        debugger.run_frame(&mut chip, 10).unwrap();
+        assert!(debugger.is_paused());
+        assert_eq!(chip.program_counter, 0x202);
+        assert_eq!(chip.registers.all()[1], 1);
+
+        // Continuing runs the loop once round and stops at the breakpoint again
+        debugger.resume(&chip);
+        let frame = debugger.run_frame(&mut chip, 10).unwrap();
+        assert_eq!(frame.executed, 2);
+        assert_eq!(chip.program_counter, 0x202);
+        assert_eq!(chip.registers.all()[0], 1);
+
+        assert!(!debugger.toggle_breakpoint(0x202));
+        assert_eq!(debugger.breakpoints().count(), 0);
+    }
+
+    #[test]
+    fn test_step_over_call() {
+        let mut chip = chip();
+        let mut debugger = Debugger::new();
+
+        assert!(!debugger.step_over(&mut chip).unwrap());
+        debugger.run_frame(&mut chip, 10).unwrap();
+        assert!(debugger.is_paused());
+        assert_eq!(chip.program_counter, 0x202);
+        assert_eq!(chip.registers.all()[1], 1);
+
+        assert!(debugger.step_over(&mut chip).unwrap());
+        assert_eq!(chip.program_counter, 0x204);
+    }
+
+    #[test]
+    fn test_step_out() {
+        let mut chip = chip();
+        let mut debugger = Debugger::new();
+        assert!(!debugger.step_out(&chip));
+
+        debugger.step(&mut chip).unwrap();
+        assert_eq!(chip.program_counter, 0x208);
+        assert!(debugger.step_out(&chip));
+        debugger.run_frame(&mut chip, 10).unwrap();
+        assert!(debugger.is_paused());
+        assert_eq!(chip.program_counter, 0x202);
+        assert!(chip.stack.is_empty());
+    }
+
+    #[test]
+    fn test_run_to() {
+        let mut chip = chip();
+        let mut debugger = Debugger::new();
+
+        debugger.run_to(&chip, 0x204);
+        debugger.run_frame(&mut chip, 10).unwrap();
+        assert!(debugger.is_paused());
+        assert_eq!(chip.program_counter, 0x204);
+    }
+}
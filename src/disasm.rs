@@ -0,0 +1,276 @@
+//! Disassembly of whole ROMs.
+//!
+//! Code is found by following control flow from [`PROGRAM_START`] through
+//! jumps, calls and both outcomes of skips. Branch targets get labels and
+//! bytes never reached are listed as data, since CHIP 8 ROMs freely mix
+//! sprites in with their code.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::chip::PROGRAM_START;
+use crate::opcode::Opcode;
+use crate::platform::Platform;
+
+/// Data bytes per `DB` line
+const DATA_PER_LINE: usize = 8;
+
+/// What a label marks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabelKind {
+    /// Target of a JP
+    Jump,
+    /// Target of a CALL
+    Subroutine,
+}
+
+/// One line of a disassembly
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line {
+    Instruction { addr: usize, opcode: Opcode },
+    Data { addr: usize, bytes: Vec<u8> },
+}
+
+pub struct Disassembly {
+    lines: Vec<Line>,
+    labels: BTreeMap<usize, LabelKind>,
+    /// Raw bytes of the ROM, for the listing comments
+    rom: Vec<u8>,
+}
+
+impl Disassembly {
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+
+    /// Name of the label at `addr`, if there is one
+    pub fn label(&self, addr: usize) -> Option<String> {
+        self.labels.get(&addr).map(|kind| match kind {
+            LabelKind::Jump => format!("label_{:03X}", addr),
+            LabelKind::Subroutine => format!("sub_{:03X}", addr),
+        })
+    }
+
+    /// Instruction text, with branch targets replaced by their labels
+    fn instruction(&self, opcode: &Opcode) -> String {
+        let target = |addr: &u16| {
+            self.label(*addr as usize)
+                .unwrap_or_else(|| format!("{:#05X}", addr))
+        };
+        match opcode {
+            Opcode::JP { addr } => format!("JP {}", target(addr)),
+            Opcode::CALL { addr } => format!("CALL {}", target(addr)),
+            Opcode::JPPlusV0 { addr } => format!("JP V0, {}", target(addr)),
+            _ => opcode.to_string(),
+        }
+    }
+
+    fn bytes(&self, addr: usize, len: usize) -> &[u8] {
+        &self.rom[addr - PROGRAM_START..addr - PROGRAM_START + len]
+    }
+}
+
+/// Listing that assembles back to the ROM, with the address and bytes of
+/// each line in a comment.
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            let (addr, text, len) = match line {
+                Line::Instruction { addr, opcode } => {
+                    (*addr, self.instruction(opcode), opcode.size())
+                }
+                Line::Data { addr, bytes } => {
+                    let values: Vec<String> = bytes.iter().map(|b| format!("{:#04X}", b)).collect();
+                    (*addr, format!("DB {}", values.join(", ")), bytes.len())
+                }
+            };
+            if let Some(label) = self.label(addr) {
+                writeln!(f, "{}:", label)?;
+            }
+            let hex: String = self
+                .bytes(addr, len)
+                .iter()
+                .map(|b| format!("{:02X}", b))
+                .collect();
+            writeln!(f, "    {:<32}; {:03X}: {}", text, addr, hex)?;
+        }
+        Ok(())
+    }
+}
+
+/// Disassemble a ROM loaded at [`PROGRAM_START`] for `platform`
+pub fn disassemble(rom: &[u8], platform: Platform) -> Disassembly {
+    let end = PROGRAM_START + rom.len();
+    let decode = |addr: usize| -> Option<Opcode> {
+        if addr < PROGRAM_START || addr + 2 > end {
+            return None;
+        }
+        let offset = addr - PROGRAM_START;
+        let raw = u16::from_be_bytes([rom[offset], rom[offset + 1]]);
+        let mut opcode = Opcode::decode(raw);
+        if opcode.platform() > platform || matches!(opcode, Opcode::Sys { .. } | Opcode::Unknown(_))
+        {
+            return None;
+        }
+        if let Opcode::LDILong { addr: long } = &mut opcode {
+            if offset + 4 > rom.len() {
+                return None;
+            }
+            *long = u16::from_be_bytes([rom[offset + 2], rom[offset + 3]]);
+        }
+        Some(opcode)
+    };
+
+    let mut code: BTreeMap<usize, Opcode> = BTreeMap::new();
+    let mut labels = BTreeMap::new();
+    let mut pending = vec![PROGRAM_START];
+    let mut seen = BTreeSet::new();
+
+    while let Some(addr) = pending.pop() {
+        if !seen.insert(addr) {
+            continue;
+        }
+        let Some(opcode) = decode(addr) else {
+            continue;
+        };
+        code.insert(addr, opcode);
+        let next = addr + opcode.size();
+
+        match opcode {
+            Opcode::JP { addr: target } | Opcode::JPPlusV0 { addr: target } => {
+                // For BNNN only the first entry of the jump table is known
+                labels.entry(target as usize).or_insert(LabelKind::Jump);
+                pending.push(target as usize);
+            }
+            Opcode::CALL { addr: target } => {
+                labels.insert(target as usize, LabelKind::Subroutine);
+                pending.push(target as usize);
+                pending.push(next);
+            }
+            Opcode::RET | Opcode::EXIT => {}
+            Opcode::SEByte { .. }
+            | Opcode::SNEByte { .. }
+            | Opcode::SEReg { .. }
+            | Opcode::SNEReg { .. }
+            | Opcode::SKP { .. }
+            | Opcode::SKNP { .. } => {
+                pending.push(next);
+                let skipped = decode(next).map_or(2, |op| op.size());
+                pending.push(next + skipped);
+            }
+            _ => pending.push(next),
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut addr = PROGRAM_START;
+    while addr < end {
+        if let Some(opcode) = code.get(&addr) {
+            lines.push(Line::Instruction {
+                addr,
+                opcode: *opcode,
+            });
+            addr += opcode.size();
+            continue;
+        }
+        // Data runs up to the next instruction or label, so labels always
+        // start a line of their own.
+        let start = addr;
+        let mut bytes = Vec::new();
+        while addr < end && bytes.len() < DATA_PER_LINE {
+            if addr != start && (code.contains_key(&addr) || labels.contains_key(&addr)) {
+                break;
+            }
+            bytes.push(rom[addr - PROGRAM_START]);
+            addr += 1;
+        }
+        lines.push(Line::Data { addr: start, bytes });
+    }
+
+    // Targets inside another instruction or outside the ROM cannot be
+    // labelled in the listing.
+    let starts: BTreeSet<usize> = lines
+        .iter()
+        .map(|line| match line {
+            Line::Instruction { addr, .. } | Line::Data { addr, .. } => *addr,
+        })
+        .collect();
+    labels.retain(|addr, _| starts.contains(addr));
+
+    Disassembly {
+        lines,
+        labels,
+        rom: rom.to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_mnemonics() {
+        let text = |raw| Opcode::decode(raw).to_string();
+        assert_eq!(text(0x6305), "LD V3, 0x05");
+        assert_eq!(text(0xA2F0), "LD I, 0x2F0");
+        assert_eq!(text(0xD01F), "DRW V0, V1, 15");
+        assert_eq!(text(0x8AB6), "SHR VA, VB");
+        assert_eq!(text(0xF265), "LD V2, [I]");
+        assert_eq!(text(0x5132), "LD [I], V1-V3");
+    }
+
+    #[test]
+    fn test_follows_control_flow() {
+        let rom = [
+            0x22, 0x08, // 200: CALL 208
+            0x12, 0x02, // 202: JP 202
+            0xFF, 0xFF, // 204: data
+            0x3F, 0x00, // 206: data
+            0x30, 0x01, // 208: SE V0, 1
+            0x60, 0x01, // 20A: LD V0, 1
+            0x00, 0xEE, // 20C: RET
+            0x80, 0x80, // 20E: sprite
+        ];
+        let dis = disassemble(&rom, Platform::Chip8);
+
+        assert_eq!(dis.label(0x208).as_deref(), Some("sub_208"));
+        assert_eq!(dis.label(0x202).as_deref(), Some("label_202"));
+        assert_eq!(
+            dis.lines()[2],
+            Line::Data {
+                addr: 0x204,
+                bytes: vec![0xFF, 0xFF, 0x3F, 0x00]
+            }
+        );
+        assert_eq!(
+            dis.lines().last(),
+            Some(&Line::Data {
+                addr: 0x20E,
+                bytes: vec![0x80, 0x80]
+            })
+        );
+
+        let listing = dis.to_string();
+        assert!(listing.contains("    CALL sub_208"));
+        assert!(listing.contains("label_202:\n    JP label_202"));
+        assert!(listing.contains("    DB 0xFF, 0xFF, 0x3F, 0x00"));
+        assert!(listing.contains("; 20A: 6001"));
+    }
+
+    #[test]
+    fn test_newer_platform_instructions_are_data() {
+        // 00FF (HIGH) then an endless loop
+        let rom = [0x00, 0xFF, 0x12, 0x02];
+        let chip8 = disassemble(&rom, Platform::Chip8);
+        assert!(matches!(chip8.lines()[0], Line::Data { .. }));
+
+        let schip = disassemble(&rom, Platform::SuperChip);
+        assert!(matches!(
+            schip.lines()[0],
+            Line::Instruction {
+                opcode: Opcode::HIGH,
+                ..
+            }
+        ));
+    }
+}
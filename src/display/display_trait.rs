@@ -1,36 +1,35 @@
-use crate::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use crate::display::framebuffer::Framebuffer;
 
 pub trait Ch8Display {
     /// Implementor must provide mutable access to the display buffer
-    fn buffer(&mut self) -> &mut [[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+    fn buffer(&mut self) -> &mut Framebuffer;
 
-    fn clear(&mut self);
+    /// Read-only access to the display buffer
+    fn framebuffer(&self) -> &Framebuffer;
 
-    fn render(&self);
-
-    /// Default CHIP-8 sprite drawing (XOR + collision)
-    fn draw_sprite(&mut self, x: u8, y: u8, sprite: &[u8]) -> bool {
-        let buffer = self.buffer();
-        let mut collision = false;
-
-        for (row, byte) in sprite.iter().enumerate() {
-            let py = (y as usize + row) % DISPLAY_HEIGHT;
-
-            for bit in 0..8 {
-                let px = (x as usize + bit) % DISPLAY_WIDTH;
-
-                if (byte & (0x80 >> bit)) != 0 {
-                    let pixel = &mut buffer[py][px];
+    fn clear(&mut self) {
+        self.buffer().clear();
+    }
 
-                    if *pixel {
-                        collision = true;
-                    }
+    /// Show the framebuffer, frontends call this at most once per frame
+    /// rather than after every change
+    fn render(&mut self);
 
-                    *pixel ^= true;
-                }
-            }
-        }
+    /// Default CHIP-8 sprite drawing (XOR + collision)
+    ///
+    /// The start position always wraps around the screen, the rest of the
+    /// sprite wraps as well unless `clip` is set.
+    fn draw_sprite(&mut self, x: u8, y: u8, sprite: &[u8], clip: bool) -> bool {
+        let rows: Vec<u16> = sprite.iter().map(|&b| b as u16).collect();
+        self.buffer().draw(x, y, &rows, 8, clip)
+    }
 
-        collision
+    /// SUPER-CHIP 16x16 sprite drawing (DXY0), two bytes per row
+    fn draw_large_sprite(&mut self, x: u8, y: u8, sprite: &[u8], clip: bool) -> bool {
+        let rows: Vec<u16> = sprite
+            .chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]))
+            .collect();
+        self.buffer().draw(x, y, &rows, 16, clip)
     }
 }
@@ -0,0 +1,295 @@
+use crate::{DISPLAY_HEIGHT, DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT, HIRES_DISPLAY_WIDTH};
+
+/// Number of XO-CHIP bitplanes
+pub const PLANES: usize = 2;
+
+/// Screen with two bitplanes, 64x32 in low resolution and 128x64 in the
+/// SUPER-CHIP high resolution mode.
+///
+/// Each pixel holds a colour index 0–3, bit 0 being the first plane and
+/// bit 1 the second. Plain CHIP 8 and SUPER-CHIP programs only ever touch
+/// the first plane. The pixel storage is always large enough for high
+/// resolution, only the top left `width() x height()` part is in use.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Framebuffer {
+    pixels: [[u8; HIRES_DISPLAY_WIDTH]; HIRES_DISPLAY_HEIGHT],
+    hires: bool,
+    planes: u8,
+}
+
+impl Framebuffer {
+    pub fn new() -> Self {
+        Framebuffer {
+            pixels: [[0; HIRES_DISPLAY_WIDTH]; HIRES_DISPLAY_HEIGHT],
+            hires: false,
+            planes: 0b01,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_DISPLAY_WIDTH
+        } else {
+            DISPLAY_WIDTH
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_DISPLAY_HEIGHT
+        } else {
+            DISPLAY_HEIGHT
+        }
+    }
+
+    pub fn hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Switch resolution (00FE/00FF), which also clears every plane
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.pixels = [[0; HIRES_DISPLAY_WIDTH]; HIRES_DISPLAY_HEIGHT];
+    }
+
+    /// Bitmask of the planes affected by drawing, clearing and scrolling
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    /// Select the planes to draw on (FN01)
+    pub fn set_planes(&mut self, mask: u8) {
+        self.planes = mask & 0b11;
+    }
+
+    /// Clear the selected planes
+    pub fn clear(&mut self) {
+        let keep = !self.planes;
+        for row in self.pixels.iter_mut() {
+            for pixel in row.iter_mut() {
+                *pixel &= keep;
+            }
+        }
+    }
+
+    /// Whether the pixel is lit on any plane
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.pixels[y][x] != 0
+    }
+
+    /// Colour index 0–3 of the pixel
+    pub fn color(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y][x]
+    }
+
+    /// Overwrite the colour index of the pixel on every plane
+    pub fn set_color(&mut self, x: usize, y: usize, color: u8) {
+        self.pixels[y][x] = color & 0b11;
+    }
+
+    /// Light or unlight the pixel on the selected planes
+    pub fn set(&mut self, x: usize, y: usize, on: bool) {
+        if on {
+            self.pixels[y][x] |= self.planes;
+        } else {
+            self.pixels[y][x] &= !self.planes;
+        }
+    }
+
+    /// Rows currently on screen as colour indices, each trimmed to `width()`
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        let width = self.width();
+        self.pixels[..self.height()]
+            .iter()
+            .map(move |row| &row[..width])
+    }
+
+    /// XOR a sprite onto the selected planes, returning whether any pixel
+    /// was erased.
+    ///
+    /// Each entry of `rows` holds `width` pixels, most significant bit first.
+    /// With both planes selected the first half of `rows` goes to the first
+    /// plane and the second half to the second. The start position always
+    /// wraps around the screen, the rest of the sprite wraps as well unless
+    /// `clip` is set.
+    pub fn draw(&mut self, x: u8, y: u8, rows: &[u16], width: usize, clip: bool) -> bool {
+        let selected: Vec<u8> = (0..PLANES as u8)
+            .map(|plane| 1 << plane)
+            .filter(|bit| self.planes & bit != 0)
+            .collect();
+        if selected.is_empty() {
+            return false;
+        }
+
+        let per_plane = rows.len() / selected.len();
+        let mut collision = false;
+        for (plane, chunk) in selected.iter().zip(rows.chunks(per_plane.max(1))) {
+            collision |= self.draw_plane(*plane, x, y, chunk, width, clip);
+        }
+        collision
+    }
+
+    fn draw_plane(
+        &mut self,
+        plane: u8,
+        x: u8,
+        y: u8,
+        rows: &[u16],
+        width: usize,
+        clip: bool,
+    ) -> bool {
+        let (w, h) = (self.width(), self.height());
+        let x = x as usize % w;
+        let y = y as usize % h;
+        let mut collision = false;
+
+        for (row, bits) in rows.iter().enumerate() {
+            if clip && y + row >= h {
+                break;
+            }
+            let py = (y + row) % h;
+
+            for bit in 0..width {
+                if clip && x + bit >= w {
+                    break;
+                }
+                let px = (x + bit) % w;
+
+                if (bits >> (width - 1 - bit)) & 1 != 0 {
+                    let pixel = &mut self.pixels[py][px];
+
+                    if *pixel & plane != 0 {
+                        collision = true;
+                    }
+
+                    *pixel ^= plane;
+                }
+            }
+        }
+
+        collision
+    }
+
+    /// Move the selected planes by `dx`, `dy` pixels, filling with blanks
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (w, h) = (self.width() as isize, self.height() as isize);
+        let mask = self.planes;
+        let before = self.pixels;
+
+        for y in 0..h {
+            for x in 0..w {
+                let (sx, sy) = (x - dx, y - dy);
+                let moved = if (0..w).contains(&sx) && (0..h).contains(&sy) {
+                    before[sy as usize][sx as usize]
+                } else {
+                    0
+                };
+                let pixel = &mut self.pixels[y as usize][x as usize];
+                *pixel = (*pixel & !mask) | (moved & mask);
+            }
+        }
+    }
+
+    /// Scroll the screen down by `n` pixels (00CN)
+    pub fn scroll_down(&mut self, n: usize) {
+        self.scroll(0, n as isize);
+    }
+
+    /// Scroll the screen up by `n` pixels (00DN)
+    pub fn scroll_up(&mut self, n: usize) {
+        self.scroll(0, -(n as isize));
+    }
+
+    /// Scroll the screen right by `n` pixels (00FB)
+    pub fn scroll_right(&mut self, n: usize) {
+        self.scroll(n as isize, 0);
+    }
+
+    /// Scroll the screen left by `n` pixels (00FC)
+    pub fn scroll_left(&mut self, n: usize) {
+        self.scroll(-(n as isize), 0);
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolution_switch_clears() {
+        let mut fb = Framebuffer::new();
+        assert_eq!((fb.width(), fb.height()), (64, 32));
+
+        fb.set(1, 1, true);
+        fb.set_hires(true);
+        assert_eq!((fb.width(), fb.height()), (128, 64));
+        assert!(!fb.get(1, 1));
+    }
+
+    #[test]
+    fn test_draw_wide_sprite() {
+        let mut fb = Framebuffer::new();
+        fb.set_hires(true);
+
+        assert!(!fb.draw(0, 0, &[0x8001], 16, true));
+        assert!(fb.get(0, 0));
+        assert!(fb.get(15, 0));
+        assert!(!fb.get(1, 0));
+
+        assert!(fb.draw(0, 0, &[0x8000], 16, true));
+        assert!(!fb.get(0, 0));
+    }
+
+    #[test]
+    fn test_scrolling() {
+        let mut fb = Framebuffer::new();
+        fb.set(10, 10, true);
+
+        fb.scroll_down(3);
+        assert!(fb.get(10, 13));
+        assert!(!fb.get(10, 10));
+
+        fb.scroll_right(4);
+        assert!(fb.get(14, 13));
+
+        fb.scroll_left(4);
+        assert!(fb.get(10, 13));
+        assert!(!fb.get(14, 13));
+
+        fb.scroll_up(3);
+        assert!(fb.get(10, 10));
+
+        // Pixels pushed off the edge are gone
+        fb.set(63, 0, true);
+        fb.scroll_right(4);
+        fb.scroll_left(4);
+        assert!(!fb.get(63, 0));
+    }
+
+    #[test]
+    fn test_bitplanes() {
+        let mut fb = Framebuffer::new();
+        fb.set_planes(0b11);
+
+        // First row goes to plane 1, second row to plane 2
+        fb.draw(0, 0, &[0x80, 0xC0], 8, false);
+        assert_eq!(fb.color(0, 0), 0b11);
+        assert_eq!(fb.color(1, 0), 0b10);
+
+        // Only the selected plane is cleared and scrolled
+        fb.set_planes(0b10);
+        fb.scroll_down(1);
+        assert_eq!(fb.color(0, 0), 0b01);
+        assert_eq!(fb.color(0, 1), 0b10);
+
+        fb.clear();
+        assert_eq!(fb.color(0, 1), 0);
+        assert_eq!(fb.color(0, 0), 0b01);
+    }
+}
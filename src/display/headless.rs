@@ -0,0 +1,31 @@
+use crate::display::display_trait::Ch8Display;
+use crate::display::framebuffer::Framebuffer;
+
+/// Display that only keeps the framebuffer, for embedding and tests.
+pub struct HeadlessDisplay {
+    display_buffer: Framebuffer,
+}
+
+impl HeadlessDisplay {
+    pub fn new() -> Self {
+        HeadlessDisplay {
+            display_buffer: Framebuffer::new(),
+        }
+    }
+}
+
+impl Default for HeadlessDisplay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ch8Display for HeadlessDisplay {
+    fn buffer(&mut self) -> &mut Framebuffer {
+        &mut self.display_buffer
+    }
+    fn framebuffer(&self) -> &Framebuffer {
+        &self.display_buffer
+    }
+    fn render(&mut self) {}
+}
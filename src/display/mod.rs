@@ -1,3 +1,5 @@
 pub mod display_trait;
-pub mod terminal;
+pub mod framebuffer;
+pub mod headless;
+pub mod palette;
 pub mod termion;
@@ -0,0 +1,157 @@
+/// 24-bit colour
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+/// Channel levels of the 6x6x6 colour cube of 256 colour terminals
+const CUBE_LEVELS: [u8; 6] = [0x00, 0x5F, 0x87, 0xAF, 0xD7, 0xFF];
+
+/// The 16 ANSI colours as xterm shows them, normal then bright
+const ANSI_16: [Rgb; 16] = [
+    Rgb(0x00, 0x00, 0x00),
+    Rgb(0xCD, 0x00, 0x00),
+    Rgb(0x00, 0xCD, 0x00),
+    Rgb(0xCD, 0xCD, 0x00),
+    Rgb(0x00, 0x00, 0xEE),
+    Rgb(0xCD, 0x00, 0xCD),
+    Rgb(0x00, 0xCD, 0xCD),
+    Rgb(0xE5, 0xE5, 0xE5),
+    Rgb(0x7F, 0x7F, 0x7F),
+    Rgb(0xFF, 0x00, 0x00),
+    Rgb(0x00, 0xFF, 0x00),
+    Rgb(0xFF, 0xFF, 0x00),
+    Rgb(0x5C, 0x5C, 0xFF),
+    Rgb(0xFF, 0x00, 0xFF),
+    Rgb(0x00, 0xFF, 0xFF),
+    Rgb(0xFF, 0xFF, 0xFF),
+];
+
+impl Rgb {
+    /// Squared distance between two colours
+    fn distance(self, other: Rgb) -> u32 {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+        d(self.0, other.0) + d(self.1, other.1) + d(self.2, other.2)
+    }
+
+    /// Closest colour of the 256 colour palette, from its colour cube or
+    /// its grey ramp
+    pub fn ansi256(self) -> u8 {
+        let level = |v: u8| {
+            (0..CUBE_LEVELS.len())
+                .min_by_key(|&n| (CUBE_LEVELS[n] as i32 - v as i32).abs())
+                .unwrap_or(0)
+        };
+        let (r, g, b) = (level(self.0), level(self.1), level(self.2));
+        let cube = Rgb(CUBE_LEVELS[r], CUBE_LEVELS[g], CUBE_LEVELS[b]);
+
+        // Greys run from 8 to 238 in steps of 10
+        let average = (self.0 as u32 + self.1 as u32 + self.2 as u32) / 3;
+        let step = (average.saturating_sub(3) / 10).min(23) as u8;
+        let level = 8 + 10 * step;
+        let grey = Rgb(level, level, level);
+
+        if grey.distance(self) < cube.distance(self) {
+            232 + step
+        } else {
+            16 + 36 * r as u8 + 6 * g as u8 + b as u8
+        }
+    }
+
+    /// Closest of the 16 ANSI colours, 0–7 normal and 8–15 bright
+    pub fn ansi16(self) -> u8 {
+        (0..ANSI_16.len())
+            .min_by_key(|&n| ANSI_16[n].distance(self))
+            .unwrap_or(0) as u8
+    }
+}
+
+/// Colours for the four framebuffer colour indices.
+///
+/// Index 0 is the background, 1 the first plane, 2 the second plane and
+/// 3 where both planes overlap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [Rgb; 4],
+}
+
+impl Palette {
+    /// Green phosphor monitor
+    pub const GREEN_PHOSPHOR: Palette = Palette {
+        colors: [
+            Rgb(0x0A, 0x14, 0x0A),
+            Rgb(0x33, 0xFF, 0x66),
+            Rgb(0x22, 0xAA, 0x44),
+            Rgb(0x11, 0x55, 0x22),
+        ],
+    };
+
+    /// Amber monochrome monitor
+    pub const AMBER: Palette = Palette {
+        colors: [
+            Rgb(0x14, 0x0C, 0x00),
+            Rgb(0xFF, 0xB0, 0x00),
+            Rgb(0xAA, 0x75, 0x00),
+            Rgb(0x55, 0x3B, 0x00),
+        ],
+    };
+
+    /// Greenish LCD with dark pixels, darkest where the planes overlap
+    pub const LCD: Palette = Palette {
+        colors: [
+            Rgb(0x9B, 0xBC, 0x0F),
+            Rgb(0x30, 0x62, 0x30),
+            Rgb(0x62, 0x8B, 0x1F),
+            Rgb(0x0F, 0x38, 0x0F),
+        ],
+    };
+
+    pub fn color(&self, index: u8) -> Rgb {
+        self.colors[(index & 0b11) as usize]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            colors: [
+                Rgb(0x00, 0x00, 0x00),
+                Rgb(0xFF, 0xFF, 0xFF),
+                Rgb(0xAA, 0xAA, 0xAA),
+                Rgb(0x55, 0x55, 0x55),
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ansi256() {
+        assert_eq!(Rgb(0, 0, 0).ansi256(), 16);
+        assert_eq!(Rgb(0xFF, 0, 0).ansi256(), 196);
+        assert_eq!(Rgb(0xFF, 0xB0, 0).ansi256(), 214);
+        // Greys use the finer grey ramp
+        assert_eq!(Rgb(0x80, 0x80, 0x80).ansi256(), 244);
+        assert_eq!(Rgb(0x12, 0x12, 0x12).ansi256(), 233);
+    }
+
+    #[test]
+    fn test_ansi16() {
+        assert_eq!(Rgb(0, 0, 0).ansi16(), 0);
+        assert_eq!(Rgb(0xFF, 0xFF, 0xFF).ansi16(), 15);
+        assert_eq!(Palette::GREEN_PHOSPHOR.colors[1].ansi16(), 10);
+        assert_eq!(Palette::AMBER.colors[1].ansi16(), 3);
+    }
+
+    #[test]
+    fn test_presets_tell_every_color_apart() {
+        for palette in [Palette::GREEN_PHOSPHOR, Palette::AMBER, Palette::LCD] {
+            for (n, a) in palette.colors.iter().enumerate() {
+                for b in &palette.colors[n + 1..] {
+                    assert!(a.distance(*b) > 50 * 50, "{:?} and {:?}", a, b);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::fmt;
+
+use crate::chip::Chip;
+
+/// What went wrong inside one of the chip components
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The word does not decode to any known instruction
+    InvalidOpcode,
+    /// CALL with all 16 stack levels in use
+    StackOverflow,
+    /// RET or peek with an empty stack
+    StackUnderflow,
+    /// Access outside of the addressable memory
+    MemoryOutOfBounds { addr: usize },
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::InvalidOpcode => write!(f, "invalid opcode"),
+            Fault::StackOverflow => write!(f, "stack overflow"),
+            Fault::StackUnderflow => write!(f, "stack underflow"),
+            Fault::MemoryOutOfBounds { addr } => {
+                write!(f, "memory access out of bounds at {:#05X}", addr)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Fault {}
+
+/// Snapshot of the machine taken when a fault occurred
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineState {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub stack: Vec<u16>,
+    pub delay: u8,
+    pub sound: u8,
+}
+
+impl MachineState {
+    pub fn capture(chip: &Chip) -> Self {
+        MachineState {
+            v: chip.registers.all(),
+            i: chip.registers.get_i(),
+            stack: chip.stack.as_slice().to_vec(),
+            delay: chip.timers.get_delay(),
+            sound: chip.timers.get_sound(),
+        }
+    }
+}
+
+impl fmt::Display for MachineState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, v) in self.v.iter().enumerate() {
+            if idx > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "V{:X}={:02X}", idx, v)?;
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "I={:03X} DT={:02X} ST={:02X}",
+            self.i, self.delay, self.sound
+        )?;
+        write!(f, "Stack: [")?;
+        for (idx, addr) in self.stack.iter().enumerate() {
+            if idx > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{:03X}", addr)?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// Error returned by the emulator core
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chip8Error {
+    /// Executing the instruction at `pc` failed
+    Execution {
+        pc: usize,
+        /// Raw instruction word, `None` when it could not be fetched
+        opcode: Option<u16>,
+        fault: Fault,
+        state: Box<MachineState>,
+    },
+    /// The ROM does not fit between 0x200 and the end of memory
+    RomTooLarge { size: usize, max: usize },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::Execution {
+                pc,
+                opcode,
+                fault,
+                state,
+            } => {
+                write!(f, "{} at PC {:03X}", fault, pc)?;
+                if let Some(opcode) = opcode {
+                    write!(f, " (opcode {:04X})", opcode)?;
+                }
+                writeln!(f)?;
+                write!(f, "{}", state)
+            }
+            Chip8Error::RomTooLarge { size, max } => {
+                write!(f, "ROM is {} bytes, at most {} bytes fit", size, max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {}
@@ -1,7 +1,14 @@
-use crate::{chip::Chip, font::FONT_START, nibble::Nibble, opcode::Opcode};
+use crate::{
+    chip::Chip,
+    error::Fault,
+    font::{BIG_FONT_START, FONT_START},
+    nibble::Nibble,
+    opcode::Opcode,
+    quirks::LoadStore,
+};
 
 impl Chip {
-    pub fn execute(&mut self, opcode: Opcode) {
+    pub fn execute(&mut self, opcode: Opcode) -> Result<(), Fault> {
         match opcode {
             // ──────────────────────────────────────────
             // System
@@ -11,7 +18,7 @@ impl Chip {
             }
 
             Opcode::RET => {
-                self.program_counter = self.stack.pop() as usize;
+                self.program_counter = self.stack.pop()? as usize;
             }
 
             // ──────────────────────────────────────────
@@ -22,12 +29,16 @@ impl Chip {
             }
 
             Opcode::JPPlusV0 { addr } => {
-                let v0 = self.registers.get(Nibble::from_low(0));
-                self.program_counter = addr as usize + v0 as usize;
+                let reg = if self.quirks.jump {
+                    Nibble::from_opcode(addr, 8)
+                } else {
+                    Nibble::from_low(0)
+                };
+                self.program_counter = addr as usize + self.registers.get(reg) as usize;
             }
 
             Opcode::CALL { addr } => {
-                self.stack.push(self.program_counter as u16);
+                self.stack.push(self.program_counter as u16)?;
                 self.program_counter = addr as usize;
             }
 
@@ -75,16 +86,25 @@ impl Chip {
             Opcode::OR { x, y } => {
                 let v = self.registers.get(x) | self.registers.get(y);
                 self.registers.set(x, v);
+                if self.quirks.vf_reset {
+                    self.registers.set_carry(0);
+                }
             }
 
             Opcode::AND { x, y } => {
                 let v = self.registers.get(x) & self.registers.get(y);
                 self.registers.set(x, v);
+                if self.quirks.vf_reset {
+                    self.registers.set_carry(0);
+                }
             }
 
             Opcode::XOR { x, y } => {
                 let v = self.registers.get(x) ^ self.registers.get(y);
                 self.registers.set(x, v);
+                if self.quirks.vf_reset {
+                    self.registers.set_carry(0);
+                }
             }
 
             Opcode::ADD { x, y } => {
@@ -105,16 +125,16 @@ impl Chip {
                 self.registers.set_carry((!borrow) as u8);
             }
 
-            Opcode::SHR { x } => {
-                let vx = self.registers.get(x);
-                self.registers.set_carry(vx & 0x01);
+            Opcode::SHR { x, y } => {
+                let vx = self.shift_source(x, y);
                 self.registers.set(x, vx >> 1);
+                self.registers.set_carry(vx & 0x01);
             }
 
-            Opcode::SHL { x } => {
-                let vx = self.registers.get(x);
-                self.registers.set_carry((vx & 0x80) >> 7);
+            Opcode::SHL { x, y } => {
+                let vx = self.shift_source(x, y);
                 self.registers.set(x, vx << 1);
+                self.registers.set_carry((vx & 0x80) >> 7);
             }
 
             // ──────────────────────────────────────────
@@ -139,38 +159,40 @@ impl Chip {
                 let value = self.registers.get(x);
                 let i = self.registers.get_i() as usize;
 
-                self.memory.write(i, value / 100);
-                self.memory.write(i + 1, (value / 10) % 10);
-                self.memory.write(i + 2, value % 10);
+                self.memory.write(i, value / 100)?;
+                self.memory.write(i + 1, (value / 10) % 10)?;
+                self.memory.write(i + 2, value % 10)?;
             }
 
             Opcode::LDIStore { x } => {
                 let i = self.registers.get_i() as usize;
                 for idx in 0..=x.as_u8() {
                     let n = Nibble::from_low(idx);
-                    self.memory.write(i + n.as_usize(), self.registers.get(n));
+                    self.memory.write(i + n.as_usize(), self.registers.get(n))?;
                 }
+                self.advance_index(x);
             }
 
             Opcode::LDIRead { x } => {
                 let i = self.registers.get_i() as usize;
                 for idx in 0..=x.as_u8() {
                     let n = Nibble::from_low(idx);
-                    let v = self.memory.read(i + n.as_usize());
+                    let v = self.memory.read(i + n.as_usize())?;
                     self.registers.set(n, v);
                 }
+                self.advance_index(x);
             }
 
             // ──────────────────────────────────────────
             // Random & drawing
             // ──────────────────────────────────────────
             Opcode::RND { x, byte } => {
-                let r: u8 = rand::random();
+                let r = self.random.next_byte();
                 self.registers.set(x, r & byte);
             }
 
             Opcode::DRW { x, y, n } => {
-                self.opcode_dxyn(x, y, n);
+                self.opcode_dxyn(x, y, n)?;
             }
 
             // ──────────────────────────────────────────
@@ -200,8 +222,353 @@ impl Chip {
             }
 
             Opcode::LDxK { x } => {
+                // Only a key pressed after this point may satisfy the wait
+                self.keypad.take_last_pressed();
                 self.wait_for_input = Some(x.as_u8());
             }
+
+            // ──────────────────────────────────────────
+            // SUPER-CHIP
+            // ──────────────────────────────────────────
+            Opcode::SCD { n } => {
+                self.display.buffer().scroll_down(n.as_usize());
+            }
+
+            Opcode::SCR => {
+                self.display.buffer().scroll_right(4);
+            }
+
+            Opcode::SCL => {
+                self.display.buffer().scroll_left(4);
+            }
+
+            Opcode::EXIT => {
+                self.halted = true;
+            }
+
+            Opcode::LOW => {
+                self.display.buffer().set_hires(false);
+            }
+
+            Opcode::HIGH => {
+                self.display.buffer().set_hires(true);
+            }
+
+            Opcode::LDHF { x } => {
+                let digit = (self.registers.get(x) & 0xF) as u16;
+                self.registers.set_i(BIG_FONT_START as u16 + digit * 10);
+            }
+
+            Opcode::LDRStore { x } => {
+                self.registers.store_flags(x);
+            }
+
+            Opcode::LDRRead { x } => {
+                self.registers.load_flags(x);
+            }
+
+            // ──────────────────────────────────────────
+            // XO-CHIP
+            // ──────────────────────────────────────────
+            Opcode::SCU { n } => {
+                self.display.buffer().scroll_up(n.as_usize());
+            }
+
+            Opcode::LDRangeStore { x, y } => {
+                let i = self.registers.get_i() as usize;
+                for (offset, n) in register_range(x, y).enumerate() {
+                    self.memory.write(i + offset, self.registers.get(n))?;
+                }
+            }
+
+            Opcode::LDRangeRead { x, y } => {
+                let i = self.registers.get_i() as usize;
+                for (offset, n) in register_range(x, y).enumerate() {
+                    let v = self.memory.read(i + offset)?;
+                    self.registers.set(n, v);
+                }
+            }
+
+            Opcode::LDILong { addr } => {
+                self.registers.set_i(addr);
+            }
+
+            Opcode::PLANE { n } => {
+                self.display.buffer().set_planes(n.as_u8());
+            }
+
+            Opcode::AUDIO => {
+                let i = self.registers.get_i() as usize;
+                let mut pattern = [0u8; 16];
+                pattern.copy_from_slice(self.memory.slice(i, i + 16)?);
+                self.audio.pattern = Some(pattern);
+            }
+
+            Opcode::PITCH { x } => {
+                self.audio.pitch = self.registers.get(x);
+            }
+
+            // Machine code routines cannot run on an interpreter
+            Opcode::Sys { .. } | Opcode::Unknown(_) => return Err(Fault::InvalidOpcode),
+        }
+        Ok(())
+    }
+
+    /// Value shifted by 8XY6/8XYE
+    fn shift_source(&self, x: Nibble, y: Nibble) -> u8 {
+        if self.quirks.shift {
+            self.registers.get(x)
+        } else {
+            self.registers.get(y)
+        }
+    }
+
+    /// Move I past the registers touched by FX55/FX65
+    fn advance_index(&mut self, x: Nibble) {
+        let step = match self.quirks.load_store {
+            LoadStore::Increment => x.as_u8() as u16 + 1,
+            LoadStore::IncrementByX => x.as_u8() as u16,
+            LoadStore::Unchanged => return,
+        };
+        let i = self.registers.get_i();
+        self.registers.set_i(i.wrapping_add(step));
+    }
+}
+
+/// Registers Vx to Vy for 5XY2/5XY3, in descending order when x > y
+fn register_range(x: Nibble, y: Nibble) -> impl Iterator<Item = Nibble> {
+    let (x, y) = (x.as_u8(), y.as_u8());
+    let range: Box<dyn Iterator<Item = u8>> = if x <= y {
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
+    };
+    range.map(Nibble::from_low)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::headless::HeadlessDisplay;
+    use crate::platform::Platform;
+    use crate::quirks::Quirks;
+
+    fn chip_with(quirks: Quirks) -> Chip {
+        let mut chip = Chip::new(HeadlessDisplay::new());
+        chip.quirks = quirks;
+        chip
+    }
+
+    fn chip_for(platform: Platform) -> Chip {
+        Chip::with_platform(HeadlessDisplay::new(), platform)
+    }
+
+    fn v(n: u8) -> Nibble {
+        Nibble::from_low(n)
+    }
+
+    #[test]
+    fn test_shift_quirk() {
+        let mut chip = chip_with(Quirks::COSMAC_VIP);
+        chip.registers.set(v(1), 0x02);
+        chip.registers.set(v(2), 0x81);
+        chip.execute(Opcode::SHR { x: v(1), y: v(2) }).unwrap();
+        assert_eq!(chip.registers.get(v(1)), 0x40);
+        assert_eq!(chip.registers.get_carry(), 1);
+
+        let mut chip = chip_with(Quirks::SUPER_CHIP);
+        chip.registers.set(v(1), 0x02);
+        chip.registers.set(v(2), 0x81);
+        chip.execute(Opcode::SHR { x: v(1), y: v(2) }).unwrap();
+        assert_eq!(chip.registers.get(v(1)), 0x01);
+        assert_eq!(chip.registers.get_carry(), 0);
+    }
+
+    #[test]
+    fn test_load_store_quirk() {
+        let mut chip = chip_with(Quirks::COSMAC_VIP);
+        chip.registers.set_i(0x300);
+        chip.execute(Opcode::LDIStore { x: v(3) }).unwrap();
+        assert_eq!(chip.registers.get_i(), 0x304);
+
+        let mut chip = chip_with(Quirks::CHIP_48);
+        chip.registers.set_i(0x300);
+        chip.execute(Opcode::LDIStore { x: v(3) }).unwrap();
+        assert_eq!(chip.registers.get_i(), 0x303);
+
+        let mut chip = chip_with(Quirks::SUPER_CHIP);
+        chip.registers.set_i(0x300);
+        chip.execute(Opcode::LDIRead { x: v(3) }).unwrap();
+        assert_eq!(chip.registers.get_i(), 0x300);
+    }
+
+    #[test]
+    fn test_jump_quirk() {
+        let mut chip = chip_with(Quirks::COSMAC_VIP);
+        chip.registers.set(v(0), 0x01);
+        chip.registers.set(v(3), 0x10);
+        chip.execute(Opcode::JPPlusV0 { addr: 0x320 }).unwrap();
+        assert_eq!(chip.program_counter, 0x321);
+
+        let mut chip = chip_with(Quirks::SUPER_CHIP);
+        chip.registers.set(v(0), 0x01);
+        chip.registers.set(v(3), 0x10);
+        chip.execute(Opcode::JPPlusV0 { addr: 0x320 }).unwrap();
+        assert_eq!(chip.program_counter, 0x330);
+    }
+
+    #[test]
+    fn test_vf_reset_quirk() {
+        let mut chip = chip_with(Quirks::COSMAC_VIP);
+        chip.registers.set_carry(1);
+        chip.execute(Opcode::OR { x: v(0), y: v(1) }).unwrap();
+        assert_eq!(chip.registers.get_carry(), 0);
+
+        let mut chip = chip_with(Quirks::XO_CHIP);
+        chip.registers.set_carry(1);
+        chip.execute(Opcode::OR { x: v(0), y: v(1) }).unwrap();
+        assert_eq!(chip.registers.get_carry(), 1);
+    }
+
+    #[test]
+    fn test_clipping_quirk() {
+        // Draw the "0" glyph one pixel from the bottom right corner
+        for (quirks, wrapped) in [(Quirks::COSMAC_VIP, false), (Quirks::XO_CHIP, true)] {
+            let mut chip = chip_with(quirks);
+            chip.registers.set(v(0), 63);
+            chip.registers.set(v(1), 31);
+            chip.registers.set_i(FONT_START as u16);
+            chip.execute(Opcode::DRW {
+                x: v(0),
+                y: v(1),
+                n: v(5),
+            })
+            .unwrap();
+
+            let buffer = chip.display.framebuffer();
+            assert!(buffer.get(63, 31));
+            assert_eq!(buffer.get(63, 0), wrapped);
+            assert_eq!(buffer.get(0, 31), wrapped);
         }
     }
+
+    #[test]
+    fn test_super_chip_large_sprite() {
+        let mut chip = chip_for(Platform::SuperChip);
+        chip.execute(Opcode::HIGH).unwrap();
+        chip.registers.set(v(0), 120);
+        chip.memory.load(0x300usize, &[0xFF; 32]).unwrap();
+        chip.registers.set_i(0x300);
+
+        chip.execute(Opcode::DRW {
+            x: v(0),
+            y: v(0),
+            n: v(0),
+        })
+        .unwrap();
+
+        let buffer = chip.display.framebuffer();
+        assert_eq!(buffer.width(), 128);
+        // Starts at (120, 56) and is clipped at the right and bottom edges
+        assert!(buffer.get(120, 56));
+        assert!(buffer.get(127, 63));
+        assert!(!buffer.get(0, 56));
+        assert!(!buffer.get(120, 0));
+        assert_eq!(chip.registers.get_carry(), 0);
+    }
+
+    #[test]
+    fn test_super_chip_flags_and_font() {
+        let mut chip = chip_for(Platform::SuperChip);
+        chip.registers.set(v(0), 0x0A);
+        chip.registers.set(v(1), 0x42);
+        chip.execute(Opcode::LDRStore { x: v(1) }).unwrap();
+        chip.registers.set(v(0), 0);
+        chip.registers.set(v(1), 0);
+        chip.execute(Opcode::LDRRead { x: v(1) }).unwrap();
+        assert_eq!(chip.registers.get(v(0)), 0x0A);
+        assert_eq!(chip.registers.get(v(1)), 0x42);
+
+        chip.execute(Opcode::LDHF { x: v(0) }).unwrap();
+        assert_eq!(chip.registers.get_i() as usize, BIG_FONT_START + 100);
+    }
+
+    #[test]
+    fn test_super_chip_exit() {
+        let mut chip = chip_for(Platform::SuperChip);
+        chip.load_rom(&[0x00, 0xFD, 0x60, 0x01]).unwrap();
+        assert!(chip.step().unwrap().halted);
+        assert!(chip.step().unwrap().halted);
+        assert_eq!(chip.registers.get(v(0)), 0);
+    }
+
+    #[test]
+    fn test_super_chip_rejected_on_chip8() {
+        let mut chip = chip_for(Platform::Chip8);
+        chip.load_rom(&[0x00, 0xFF]).unwrap();
+        assert!(matches!(
+            chip.step(),
+            Err(crate::error::Chip8Error::Execution {
+                fault: Fault::InvalidOpcode,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_xo_chip_long_index_and_skip() {
+        // SE V0, 0 ; i := long 0xABCD ; i := long 0x1234
+        let mut chip = chip_for(Platform::XoChip);
+        chip.load_rom(&[0x30, 0x00, 0xF0, 0x00, 0xAB, 0xCD, 0xF0, 0x00, 0x12, 0x34])
+            .unwrap();
+
+        chip.step().unwrap();
+        assert_eq!(chip.program_counter, 0x206);
+        chip.step().unwrap();
+        assert_eq!(chip.registers.get_i(), 0x1234);
+        assert_eq!(chip.program_counter, 0x20A);
+    }
+
+    #[test]
+    fn test_xo_chip_register_ranges() {
+        let mut chip = chip_for(Platform::XoChip);
+        assert_eq!(chip.memory.len(), crate::XO_CHIP_MEMORY_SIZE);
+        chip.registers.set(v(2), 0x22);
+        chip.registers.set(v(3), 0x33);
+        chip.registers.set_i(0xF000);
+
+        chip.execute(Opcode::LDRangeStore { x: v(3), y: v(2) })
+            .unwrap();
+        assert_eq!(chip.memory.read(0xF000usize), Ok(0x33));
+        assert_eq!(chip.memory.read(0xF001usize), Ok(0x22));
+        assert_eq!(chip.registers.get_i(), 0xF000);
+
+        chip.execute(Opcode::LDRangeRead { x: v(5), y: v(6) })
+            .unwrap();
+        assert_eq!(chip.registers.get(v(5)), 0x33);
+        assert_eq!(chip.registers.get(v(6)), 0x22);
+    }
+
+    #[test]
+    fn test_xo_chip_planes_and_audio() {
+        let mut chip = chip_for(Platform::XoChip);
+        chip.memory.load(0x300usize, &[0x80, 0x80]).unwrap();
+        chip.registers.set_i(0x300);
+
+        chip.execute(Opcode::PLANE { n: v(3) }).unwrap();
+        chip.execute(Opcode::DRW {
+            x: v(0),
+            y: v(0),
+            n: v(1),
+        })
+        .unwrap();
+        assert_eq!(chip.display.framebuffer().color(0, 0), 0b11);
+
+        chip.registers.set(v(1), 112);
+        chip.execute(Opcode::PITCH { x: v(1) }).unwrap();
+        chip.execute(Opcode::AUDIO).unwrap();
+        assert_eq!(chip.audio.pitch, 112);
+        assert_eq!(chip.audio.playback_rate(), 8000.0);
+        assert!(chip.audio.pattern.is_some());
+    }
 }
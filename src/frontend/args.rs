@@ -0,0 +1,455 @@
+use crate::frontend::terminal::{ColorDepth, Renderer};
+use chip_eight::audio::square::Tone;
+use chip_eight::display::palette::{Palette, Rgb};
+use chip_eight::platform::Platform;
+use chip_eight::quirks::Quirks;
+use chip_eight::random::Random;
+use chip_eight::rewind::DEFAULT_INTERVAL;
+use chip_eight::scheduler::FRAME_RATE;
+use chip_eight::screenshot::{ImageFormat, Screenshot};
+use chip_eight::trace::TraceFormat;
+use chip_eight::video::VideoFormat;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::path::{Path, PathBuf};
+
+/// Instruction sets the emulator can run
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum PlatformArg {
+    /// Original CHIP 8
+    Chip8,
+    /// SUPER-CHIP 1.1
+    Schip,
+    /// XO-CHIP
+    XoChip,
+}
+
+impl From<PlatformArg> for Platform {
+    fn from(platform: PlatformArg) -> Self {
+        match platform {
+            PlatformArg::Chip8 => Platform::Chip8,
+            PlatformArg::Schip => Platform::SuperChip,
+            PlatformArg::XoChip => Platform::XoChip,
+        }
+    }
+}
+
+/// Where the buzzer goes
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum AudioOutput {
+    /// No sound
+    None,
+    /// Terminal bell at the start of every beep
+    Bell,
+    /// Square wave recorded to a WAV file given by --audio-out
+    Wav,
+    /// Raw signed 16-bit 44.1 kHz mono PCM to --audio-out, `-` for stdout
+    Pcm,
+}
+
+/// Formats `--record-video` can write
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum VideoFormatArg {
+    /// Animated GIF
+    Gif,
+    /// Uncompressed YUV4MPEG2
+    Y4m,
+}
+
+impl From<VideoFormatArg> for VideoFormat {
+    fn from(format: VideoFormatArg) -> Self {
+        match format {
+            VideoFormatArg::Gif => VideoFormat::Gif,
+            VideoFormatArg::Y4m => VideoFormat::Y4m,
+        }
+    }
+}
+
+/// Formats `--trace` can write
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum TraceFormatArg {
+    /// key=value pairs
+    #[default]
+    Text,
+    /// One JSON object per line
+    Jsonl,
+}
+
+impl From<TraceFormatArg> for TraceFormat {
+    fn from(format: TraceFormatArg) -> Self {
+        match format {
+            TraceFormatArg::Text => TraceFormat::Text,
+            TraceFormatArg::Jsonl => TraceFormat::JsonLines,
+        }
+    }
+}
+
+/// Named quirk presets, see [`Quirks`]
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum QuirksPreset {
+    /// Original COSMAC VIP interpreter
+    Vip,
+    /// CHIP-48 for the HP-48 calculators
+    Chip48,
+    /// SUPER-CHIP 1.1
+    Schip,
+    /// XO-CHIP as implemented by Octo
+    XoChip,
+}
+
+impl From<QuirksPreset> for Quirks {
+    fn from(preset: QuirksPreset) -> Self {
+        match preset {
+            QuirksPreset::Vip => Quirks::COSMAC_VIP,
+            QuirksPreset::Chip48 => Quirks::CHIP_48,
+            QuirksPreset::Schip => Quirks::SUPER_CHIP,
+            QuirksPreset::XoChip => Quirks::XO_CHIP,
+        }
+    }
+}
+
+/// Named terminal colour schemes, see [`Palette`]
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Theme {
+    /// Green phosphor monitor
+    GreenPhosphor,
+    /// Amber monochrome monitor
+    Amber,
+    /// Greenish LCD with dark pixels
+    Lcd,
+}
+
+impl From<Theme> for Palette {
+    fn from(theme: Theme) -> Self {
+        match theme {
+            Theme::GreenPhosphor => Palette::GREEN_PHOSPHOR,
+            Theme::Amber => Palette::AMBER,
+            Theme::Lcd => Palette::LCD,
+        }
+    }
+}
+
+/// Tools run instead of the emulator
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Print an assembly listing of a ROM
+    Disasm {
+        /// Path to a CHIP 8 ROM file.
+        rom: PathBuf,
+
+        /// Platform the ROM was written for, guessed from the file extension
+        /// when omitted.
+        #[arg(long, value_enum)]
+        platform: Option<PlatformArg>,
+    },
+
+    /// Assemble a source file into a ROM
+    Asm {
+        /// Assembly source, in the syntax printed by disasm.
+        source: PathBuf,
+
+        /// ROM to write, the source with a .ch8 extension when omitted.
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+
+    /// Report where two --trace files first differ, exiting with 1 if they do
+    TraceDiff { left: PathBuf, right: PathBuf },
+
+    /// Serve the Debug Adapter Protocol over stdin and stdout, for editors
+    Dap,
+
+    /// Run a ROM without a screen under a GDB remote serial protocol stub
+    Gdb {
+        /// Path to a CHIP 8 ROM file.
+        rom: PathBuf,
+
+        /// Local TCP port to listen on.
+        #[arg(long, default_value_t = 1234)]
+        port: u16,
+
+        /// Instructions executed per 60 Hz frame while continuing.
+        #[arg(long, default_value_t = 10)]
+        ipf: usize,
+
+        /// Platform the ROM was written for, guessed from the file extension
+        /// when omitted.
+        #[arg(long, value_enum)]
+        platform: Option<PlatformArg>,
+
+        /// Quirks of the platform the ROM was written for, defaults to the
+        /// quirks of the platform.
+        #[arg(long, value_enum)]
+        quirks: Option<QuirksPreset>,
+    },
+}
+
+/// CHIP 8 Emulator
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    subcommand_negates_reqs = true,
+    args_conflicts_with_subcommands = true
+)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Path to a CHIP 8 ROM file.
+    #[arg(long, required = true)]
+    rom: Option<PathBuf>,
+
+    /// Instructions executed per 60 Hz frame, the timers always tick at 60 Hz.
+    #[arg(long, default_value_t = 10)]
+    pub ipf: usize,
+
+    /// Platform the ROM was written for, guessed from the file extension
+    /// (.sc8, .xo8) when omitted.
+    #[arg(long, value_enum)]
+    pub platform: Option<PlatformArg>,
+
+    /// Quirks of the platform the ROM was written for, defaults to the
+    /// quirks of the platform.
+    #[arg(long, value_enum)]
+    pub quirks: Option<QuirksPreset>,
+
+    /// How to play the buzzer driven by the sound timer.
+    #[arg(long, value_enum, default_value_t = AudioOutput::Bell)]
+    pub audio: AudioOutput,
+
+    /// File the wav and pcm audio outputs write to.
+    #[arg(long)]
+    pub audio_out: Option<PathBuf>,
+
+    /// Frequency of the buzzer in Hz.
+    #[arg(long, default_value_t = 440.0)]
+    pub tone_hz: f64,
+
+    /// Volume of the buzzer, between 0.0 and 1.0.
+    #[arg(long, default_value_t = 0.25)]
+    pub volume: f64,
+
+    /// Save state file to resume from instead of starting the ROM afresh.
+    #[arg(long, conflicts_with_all = ["record_movie", "play_movie"])]
+    pub load_state: Option<PathBuf>,
+
+    /// Record every key press to a movie file, written when the emulator exits.
+    #[arg(long, conflicts_with = "play_movie")]
+    pub record_movie: Option<PathBuf>,
+
+    /// Replay a movie recorded with --record-movie. Its platform, quirks,
+    /// seed and instructions per frame replace the ones given here.
+    #[arg(long)]
+    pub play_movie: Option<PathBuf>,
+
+    /// Seconds of gameplay kept for rewinding, 0 turns rewinding off.
+    #[arg(long, default_value_t = 20)]
+    pub rewind_seconds: u32,
+
+    /// Seed for the CXNN random numbers, random when omitted. The same seed
+    /// and the same input replay a game exactly.
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Generate random numbers the way the COSMAC VIP interpreter does.
+    #[arg(long)]
+    pub vip_rng: bool,
+
+    /// Write every executed instruction with the machine state before it to
+    /// a trace file.
+    #[arg(long)]
+    pub trace: Option<PathBuf>,
+
+    /// Format of --trace.
+    #[arg(long, value_enum, default_value_t, requires = "trace")]
+    pub trace_format: TraceFormatArg,
+
+    /// How pixels are drawn in the terminal.
+    #[arg(long, value_enum, default_value_t = Renderer::Block)]
+    pub renderer: Renderer,
+
+    /// Colour scheme of the screen. Without a theme, --fg or --bg the screen
+    /// uses the terminal colours.
+    #[arg(long, value_enum)]
+    pub theme: Option<Theme>,
+
+    /// Colour of lit pixels in the terminal, as RRGGBB.
+    #[arg(long, value_parser = parse_rgb)]
+    pub fg: Option<Rgb>,
+
+    /// Colour of unlit pixels in the terminal, as RRGGBB.
+    #[arg(long, value_parser = parse_rgb)]
+    pub bg: Option<Rgb>,
+
+    /// Colours the terminal supports, detected from COLORTERM and TERM when
+    /// omitted. Colours are matched to the closest ones available.
+    #[arg(long, value_enum)]
+    pub colors: Option<ColorDepth>,
+
+    /// Key bindings file, $XDG_CONFIG_HOME/chip_eight/config.toml or
+    /// ~/.config/chip_eight/config.toml when it exists.
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// How long a keypad key stays down after its last press, on terminals
+    /// that do not report keys going up. Held keys repeat, which keeps them
+    /// down.
+    #[arg(long, default_value_t = 500)]
+    pub key_hold_ms: u64,
+
+    /// Start paused in the debugger, ` pauses and resumes. Not while
+    /// recording a movie, single steps are not part of its frames.
+    #[arg(long, conflicts_with = "record_movie")]
+    pub debug: bool,
+
+    /// Run without the terminal for FRAMES frames as fast as possible, then
+    /// exit.
+    #[arg(long, value_name = "FRAMES", conflicts_with_all = ["debug", "record_movie"])]
+    pub headless: Option<u64>,
+
+    /// Record the screen to a .gif animation or a .y4m video, drawn with the
+    /// screenshot scale and colours. `-` writes to stdout under --headless.
+    #[arg(long)]
+    pub record_video: Option<PathBuf>,
+
+    /// Format of --record-video, taken from its extension when omitted.
+    #[arg(long, value_enum, requires = "record_video")]
+    pub video_format: Option<VideoFormatArg>,
+
+    /// Save a screenshot to FILE (.png, .pbm or .ppm) after FRAMES frames.
+    /// Runs without the terminal, for at least FRAMES frames.
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["FRAMES", "FILE"],
+        conflicts_with_all = ["debug", "record_movie"]
+    )]
+    screenshot_after_frames: Option<Vec<String>>,
+
+    /// Size of a CHIP 8 pixel in screenshots.
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u16).range(1..))]
+    pub screenshot_scale: u16,
+
+    /// Colour of lit pixels in screenshots, as RRGGBB.
+    #[arg(long, default_value = "ffffff", value_parser = parse_rgb)]
+    pub screenshot_on: Rgb,
+
+    /// Colour of unlit pixels in screenshots, as RRGGBB.
+    #[arg(long, default_value = "000000", value_parser = parse_rgb)]
+    pub screenshot_off: Rgb,
+}
+
+impl Args {
+    pub fn tone(&self) -> Tone {
+        Tone {
+            frequency: self.tone_hz,
+            volume: self.volume,
+        }
+    }
+
+    /// Random number generator picked by `--seed` and `--vip-rng`, with
+    /// the seed it was started from
+    pub fn random(&self) -> (Random, u64) {
+        let seed = self.seed.unwrap_or_else(rand::random);
+        if self.vip_rng {
+            (Random::vip(seed), seed)
+        } else {
+            (Random::seeded(seed), seed)
+        }
+    }
+
+    /// `--key-hold-ms` in frames
+    pub fn key_hold_frames(&self) -> u64 {
+        (self.key_hold_ms * FRAME_RATE as u64).div_ceil(1000)
+    }
+
+    /// Snapshots needed to rewind `--rewind-seconds`
+    pub fn rewind_capacity(&self) -> usize {
+        (self.rewind_seconds * FRAME_RATE / DEFAULT_INTERVAL) as usize
+    }
+
+    /// ROM to run, always given when there is no subcommand
+    pub fn rom(&self) -> &Path {
+        self.rom.as_deref().expect("--rom is required")
+    }
+
+    pub fn platform(&self) -> Platform {
+        platform_for(self.rom(), self.platform)
+    }
+
+    /// Frames to run and where to save the screenshot for
+    /// `--screenshot-after-frames`
+    pub fn screenshot_after_frames(&self) -> Result<Option<(u64, PathBuf)>, String> {
+        let Some(values) = &self.screenshot_after_frames else {
+            return Ok(None);
+        };
+        let frames = values[0]
+            .parse()
+            .map_err(|_| format!("{:?} is not a number of frames", values[0]))?;
+        let path = PathBuf::from(&values[1]);
+        if ImageFormat::from_path(&path).is_none() {
+            return Err(format!("{:?} is not a .png, .pbm or .ppm file", path));
+        }
+        Ok(Some((frames, path)))
+    }
+
+    /// Frames to run without the terminal, if `--headless` or
+    /// `--screenshot-after-frames` ask for it
+    pub fn headless_frames(&self) -> Result<Option<u64>, String> {
+        let shot = self.screenshot_after_frames()?.map(|(frames, _)| frames);
+        Ok(self.headless.max(shot))
+    }
+
+    /// Palette of the terminal screen and how to draw it, `None` when no
+    /// colours were asked for or the terminal has none
+    pub fn terminal_colors(&self) -> Option<(Palette, ColorDepth)> {
+        if self.theme.is_none() && self.fg.is_none() && self.bg.is_none() {
+            return None;
+        }
+        let mut palette: Palette = self.theme.map(Palette::from).unwrap_or_default();
+        if let Some(bg) = self.bg {
+            palette.colors[0] = bg;
+        }
+        if let Some(fg) = self.fg {
+            palette.colors[1] = fg;
+        }
+        let depth = self.colors.or_else(ColorDepth::detect)?;
+        Some((palette, depth))
+    }
+
+    /// Screenshot settings from `--screenshot-scale`, `--screenshot-on` and
+    /// `--screenshot-off`
+    pub fn screenshot(&self) -> Screenshot {
+        let mut palette = Palette::default();
+        palette.colors[0] = self.screenshot_off;
+        palette.colors[1] = self.screenshot_on;
+        Screenshot {
+            scale: self.screenshot_scale as usize,
+            palette,
+        }
+    }
+}
+
+/// Colour written as RRGGBB, optionally starting with #
+fn parse_rgb(text: &str) -> Result<Rgb, String> {
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    let value = u32::from_str_radix(hex, 16)
+        .ok()
+        .filter(|_| hex.len() == 6)
+        .ok_or_else(|| format!("{:?} is not an RRGGBB colour", text))?;
+    let [_, r, g, b] = value.to_be_bytes();
+    Ok(Rgb(r, g, b))
+}
+
+/// Platform picked with `--platform`, or else guessed from the extension
+pub fn platform_for(rom: &Path, platform: Option<PlatformArg>) -> Platform {
+    if let Some(platform) = platform {
+        return platform.into();
+    }
+    match rom.extension().and_then(|ext| ext.to_str()) {
+        Some("sc8") => Platform::SuperChip,
+        Some("xo8") => Platform::XoChip,
+        _ => Platform::Chip8,
+    }
+}
@@ -0,0 +1,77 @@
+use crate::frontend::args::{platform_for, Command};
+use crate::frontend::dap;
+use chip_eight::asm::assemble_file;
+use chip_eight::chip::Chip;
+use chip_eight::disasm::disassemble;
+use chip_eight::display::headless::HeadlessDisplay;
+use chip_eight::{gdb, trace};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{stdout, BufReader, Write};
+use std::net::TcpListener;
+use std::path::Path;
+
+/// Run a subcommand to completion
+pub fn run(command: &Command) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::Disasm { rom, platform } => {
+            let bytes = fs::read(rom)?;
+            let listing = disassemble(&bytes, platform_for(rom, *platform));
+            write!(stdout().lock(), "{}", listing)?;
+            Ok(())
+        }
+        Command::Asm { source, output } => {
+            let output = output
+                .clone()
+                .unwrap_or_else(|| source.with_extension("ch8"));
+            if same_file(&output, source) {
+                return Err(
+                    format!("{:?} would overwrite the source, pick --output", output).into(),
+                );
+            }
+            let rom = assemble_file(source)?;
+            fs::write(&output, rom)?;
+            Ok(())
+        }
+        Command::TraceDiff { left, right } => {
+            let left = BufReader::new(File::open(left)?);
+            let right = BufReader::new(File::open(right)?);
+            match trace::diff(left, right)? {
+                Some(divergence) => {
+                    print!("{}", divergence);
+                    std::process::exit(1);
+                }
+                None => println!("Traces match"),
+            }
+            Ok(())
+        }
+        Command::Dap => dap::run(),
+        Command::Gdb {
+            rom,
+            port,
+            ipf,
+            platform,
+            quirks,
+        } => {
+            let mut chip =
+                Chip::with_platform(HeadlessDisplay::new(), platform_for(rom, *platform));
+            if let Some(preset) = quirks {
+                chip.quirks = (*preset).into();
+            }
+            chip.load_rom(&fs::read(rom)?)?;
+            let listener = TcpListener::bind(("127.0.0.1", *port))?;
+            println!("Waiting for GDB on {}", listener.local_addr()?);
+            gdb::serve(&mut chip, &listener, *ipf)?;
+            Ok(())
+        }
+    }
+}
+
+/// Whether `a` and `b` name the same file, comparing the paths when they
+/// do not exist yet
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
@@ -0,0 +1,465 @@
+//! Key bindings read from a TOML file.
+//!
+//! ```toml
+//! layout = "azerty"
+//!
+//! # CHIP 8 key = every terminal key that presses it
+//! [keypad]
+//! 5 = "z "
+//!
+//! [hotkeys]
+//! quit = "t"
+//! rewind = "b"
+//!
+//! # Overrides for one ROM, by the hash logged at start and kept in movies
+//! [rom.1a2b3c4d]
+//! layout = "dvorak"
+//! keypad = { 0 = "x" }
+//! ```
+
+use chip_eight::controls::{Chip8Key, KeyMap};
+use chip_eight::movie::rom_hash;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Keyboard layouts with a built-in keypad
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    Qwerty,
+    Azerty,
+    Dvorak,
+}
+
+impl From<Layout> for KeyMap {
+    fn from(layout: Layout) -> Self {
+        match layout {
+            Layout::Qwerty => KeyMap::qwerty(),
+            Layout::Azerty => KeyMap::azerty(),
+            Layout::Dvorak => KeyMap::dvorak(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    /// The file parses but does not make sense
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "{}", err),
+            ConfigError::Parse(err) => write!(f, "{}", err),
+            ConfigError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> Self {
+        ConfigError::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(err: toml::de::Error) -> Self {
+        ConfigError::Parse(err)
+    }
+}
+
+/// What an emulator hotkey does
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hotkey {
+    Quit,
+    SaveState,
+    LoadState,
+    PreviousSlot,
+    NextSlot,
+    Rewind,
+    Screenshot,
+    /// Pauses and resumes under --debug
+    Debug,
+}
+
+impl fmt::Display for Hotkey {
+    /// Name of the hotkey in the config file
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Hotkey::Quit => "quit",
+            Hotkey::SaveState => "save-state",
+            Hotkey::LoadState => "load-state",
+            Hotkey::PreviousSlot => "previous-slot",
+            Hotkey::NextSlot => "next-slot",
+            Hotkey::Rewind => "rewind",
+            Hotkey::Screenshot => "screenshot",
+            Hotkey::Debug => "debug",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Every hotkey, in the order [`Hotkeys::new`] takes their keys
+const HOTKEYS: [Hotkey; 8] = [
+    Hotkey::Quit,
+    Hotkey::SaveState,
+    Hotkey::LoadState,
+    Hotkey::PreviousSlot,
+    Hotkey::NextSlot,
+    Hotkey::Rewind,
+    Hotkey::Screenshot,
+    Hotkey::Debug,
+];
+
+/// Keys of the emulator hotkeys
+#[derive(Clone, Debug)]
+pub struct Hotkeys {
+    keys: [(Hotkey, char); 8],
+}
+
+impl Default for Hotkeys {
+    fn default() -> Self {
+        Hotkeys::new(['t', 'o', 'p', '[', ']', 'b', 'y', '`'])
+    }
+}
+
+impl Hotkeys {
+    fn new(keys: [char; 8]) -> Self {
+        Hotkeys {
+            keys: std::array::from_fn(|n| (HOTKEYS[n], keys[n])),
+        }
+    }
+
+    /// Hotkeys clear of the keypad of the layout. They sit on the keys the
+    /// QWERTY ones do, except where AZERTY needs AltGr or a dead key there:
+    /// its slot keys are `(` and `)` instead.
+    fn for_layout(layout: Layout) -> Self {
+        match layout {
+            Layout::Qwerty => Hotkeys::default(),
+            Layout::Azerty => Hotkeys::new(['t', 'o', 'p', '(', ')', 'b', 'y', '²']),
+            Layout::Dvorak => Hotkeys::new(['y', 'r', 'l', '/', '=', 'x', 'f', '`']),
+        }
+    }
+
+    pub fn lookup(&self, key: char) -> Option<Hotkey> {
+        self.keys
+            .iter()
+            .find(|(_, bound)| *bound == key)
+            .map(|(hotkey, _)| *hotkey)
+    }
+
+    fn set(&mut self, hotkey: Hotkey, key: char) {
+        for (bound, old) in &mut self.keys {
+            if *bound == hotkey {
+                *old = key;
+            }
+        }
+    }
+}
+
+/// Hotkeys as written in the file, missing ones keep their key
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct HotkeyBindings {
+    quit: Option<char>,
+    save_state: Option<char>,
+    load_state: Option<char>,
+    previous_slot: Option<char>,
+    next_slot: Option<char>,
+    rewind: Option<char>,
+    screenshot: Option<char>,
+    debug: Option<char>,
+}
+
+impl HotkeyBindings {
+    fn apply(&self, hotkeys: &mut Hotkeys) {
+        let keys = [
+            (Hotkey::Quit, self.quit),
+            (Hotkey::SaveState, self.save_state),
+            (Hotkey::LoadState, self.load_state),
+            (Hotkey::PreviousSlot, self.previous_slot),
+            (Hotkey::NextSlot, self.next_slot),
+            (Hotkey::Rewind, self.rewind),
+            (Hotkey::Screenshot, self.screenshot),
+            (Hotkey::Debug, self.debug),
+        ];
+        for (hotkey, key) in keys {
+            if let Some(key) = key {
+                hotkeys.set(hotkey, key);
+            }
+        }
+    }
+}
+
+/// Bindings of the whole file or of one ROM
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct Bindings {
+    layout: Option<Layout>,
+    /// CHIP 8 key in hex to the terminal keys pressing it
+    keypad: BTreeMap<String, String>,
+    hotkeys: HotkeyBindings,
+    /// Overrides by ROM hash, only at the top of the file
+    rom: BTreeMap<String, Bindings>,
+}
+
+impl Bindings {
+    fn apply_keypad(&self, keymap: &mut KeyMap) -> Result<(), ConfigError> {
+        for (key, terminal_keys) in &self.keypad {
+            let chip8 = u8::from_str_radix(key, 16)
+                .ok()
+                .filter(|_| key.len() == 1)
+                .and_then(Chip8Key::new)
+                .ok_or_else(|| {
+                    ConfigError::Invalid(format!("{:?} is not a keypad key 0-F", key))
+                })?;
+            keymap.unbind(chip8);
+            for terminal_key in terminal_keys.chars() {
+                keymap.bind(terminal_key, chip8);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Key bindings the session runs with
+pub struct Controls {
+    pub keymap: KeyMap,
+    pub hotkeys: Hotkeys,
+}
+
+impl Controls {
+    /// Refuse keys doing two things, only one of which would ever happen
+    fn check(&self) -> Result<(), ConfigError> {
+        for (n, &(hotkey, key)) in self.hotkeys.keys.iter().enumerate() {
+            if let Some((other, _)) = self.hotkeys.keys[n + 1..].iter().find(|(_, k)| *k == key) {
+                return Err(ConfigError::Invalid(format!(
+                    "{:?} is the hotkey for both {} and {}",
+                    key, hotkey, other
+                )));
+            }
+            if let Some(chip8) = self.keymap.lookup(key) {
+                return Err(ConfigError::Invalid(format!(
+                    "{:?} is the hotkey for {} and keypad key {:X}",
+                    key,
+                    hotkey,
+                    chip8.as_u8()
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Contents of the config file
+#[derive(Default, Debug)]
+pub struct Config {
+    bindings: Bindings,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/chip_eight/config.toml`, or under `~/.config`
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(dir.join("chip_eight").join("config.toml"))
+    }
+
+    /// Read `path`, or the default path if it exists
+    pub fn load(path: Option<&Path>) -> Result<Config, ConfigError> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match Self::default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Config, ConfigError> {
+        let bindings: Bindings = toml::from_str(text)?;
+        for (hash, rom) in &bindings.rom {
+            if hash.len() != 8 || u32::from_str_radix(hash, 16).is_err() {
+                return Err(ConfigError::Invalid(format!(
+                    "[rom.{}] is not named by an 8 digit hex ROM hash",
+                    hash
+                )));
+            }
+            if !rom.rom.is_empty() {
+                return Err(ConfigError::Invalid(format!(
+                    "[rom.{}] cannot have ROM overrides of its own",
+                    hash
+                )));
+            }
+        }
+        Ok(Config { bindings })
+    }
+
+    /// Bindings for `rom`, with its overrides on top of the rest of the file
+    pub fn controls(&self, rom: &[u8]) -> Result<Controls, ConfigError> {
+        let global = &self.bindings;
+        let hash = format!("{:08x}", rom_hash(rom));
+        let overrides = global
+            .rom
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(&hash))
+            .map(|(_, rom)| rom);
+
+        let layout = overrides.and_then(|rom| rom.layout).or(global.layout);
+        let mut keymap = layout.map(KeyMap::from).unwrap_or_default();
+        let mut hotkeys = layout.map(Hotkeys::for_layout).unwrap_or_default();
+        for bindings in std::iter::once(global).chain(overrides) {
+            bindings.apply_keypad(&mut keymap)?;
+            bindings.hotkeys.apply(&mut hotkeys);
+        }
+
+        let controls = Controls { keymap, hotkeys };
+        controls.check()?;
+        Ok(controls)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: [u8; 2] = [0x00, 0xE0];
+
+    fn key(n: u8) -> Option<Chip8Key> {
+        Chip8Key::new(n)
+    }
+
+    fn invalid(result: Result<impl fmt::Debug, ConfigError>) -> String {
+        match result {
+            Err(ConfigError::Invalid(message)) => message,
+            other => panic!("expected an invalid config, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_defaults_without_a_file() {
+        let controls = Config::parse("").unwrap().controls(&ROM).unwrap();
+        assert_eq!(controls.keymap.lookup('w'), key(5));
+        assert_eq!(controls.hotkeys.lookup('t'), Some(Hotkey::Quit));
+    }
+
+    #[test]
+    fn test_layout_keypad_and_hotkeys() {
+        let config = Config::parse(
+            "layout = \"azerty\"\n\
+             [keypad]\n\
+             5 = \"z \"\n\
+             [hotkeys]\n\
+             quit = \"Q\"\n\
+             rewind = \"n\"\n",
+        )
+        .unwrap();
+        let controls = config.controls(&ROM).unwrap();
+        assert_eq!(controls.keymap.lookup('a'), key(4));
+        assert_eq!(controls.keymap.lookup(' '), key(5));
+        assert_eq!(controls.keymap.lookup('z'), key(5));
+        assert_eq!(controls.hotkeys.lookup('Q'), Some(Hotkey::Quit));
+        assert_eq!(controls.hotkeys.lookup('t'), None);
+        assert_eq!(controls.hotkeys.lookup('n'), Some(Hotkey::Rewind));
+        assert_eq!(controls.hotkeys.lookup('('), Some(Hotkey::PreviousSlot));
+    }
+
+    #[test]
+    fn test_every_layout_is_free_of_conflicts() {
+        for layout in ["qwerty", "azerty", "dvorak"] {
+            let config = Config::parse(&format!("layout = \"{}\"", layout)).unwrap();
+            assert!(config.controls(&ROM).is_ok(), "{}", layout);
+        }
+    }
+
+    #[test]
+    fn test_rom_overrides_by_hash() {
+        let hash = format!("{:08X}", rom_hash(&ROM));
+        let config = Config::parse(&format!(
+            "[keypad]\n\
+             0 = \"m\"\n\
+             [rom.{}]\n\
+             layout = \"dvorak\"\n\
+             keypad = {{ 1 = \"!\" }}\n\
+             hotkeys = {{ screenshot = \"S\" }}\n",
+            hash
+        ))
+        .unwrap();
+
+        let controls = config.controls(&ROM).unwrap();
+        // Dvorak, with the keypad of the whole file and of the ROM on top
+        assert_eq!(controls.keymap.lookup('o'), key(8));
+        assert_eq!(controls.keymap.lookup('m'), key(0));
+        assert_eq!(controls.keymap.lookup('!'), key(1));
+        assert_eq!(controls.keymap.lookup('1'), None);
+        assert_eq!(controls.hotkeys.lookup('S'), Some(Hotkey::Screenshot));
+        assert_eq!(controls.hotkeys.lookup('y'), Some(Hotkey::Quit));
+
+        let other = config.controls(&[0x12, 0x00]).unwrap();
+        assert_eq!(other.keymap.lookup('s'), key(8));
+        assert_eq!(other.keymap.lookup('m'), key(0));
+        assert_eq!(other.keymap.lookup('!'), None);
+        assert_eq!(other.hotkeys.lookup('y'), Some(Hotkey::Screenshot));
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let config = Config::parse("[hotkeys]\nquit = \"w\"\n").unwrap();
+        assert_eq!(
+            invalid(config.controls(&ROM).map(|_| ())),
+            "'w' is the hotkey for quit and keypad key 5"
+        );
+
+        let config = Config::parse("[hotkeys]\nrewind = \"o\"\n").unwrap();
+        assert_eq!(
+            invalid(config.controls(&ROM).map(|_| ())),
+            "'o' is the hotkey for both save-state and rewind"
+        );
+
+        // Moving the keypad key away resolves it
+        let config = Config::parse("[keypad]\n5 = \"i\"\n[hotkeys]\nquit = \"w\"\n").unwrap();
+        assert!(config.controls(&ROM).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        assert!(invalid(Config::parse("[rom.nothex12]\n")).contains("8 digit hex"));
+        assert!(invalid(Config::parse("[rom.1234]\n")).contains("8 digit hex"));
+        assert!(invalid(Config::parse("[rom.00000000.rom.00000001]\n"))
+            .contains("ROM overrides of its own"));
+
+        let config = Config::parse("[keypad]\nG = \"g\"\n").unwrap();
+        assert_eq!(
+            invalid(config.controls(&ROM).map(|_| ())),
+            "\"G\" is not a keypad key 0-F"
+        );
+        let config = Config::parse("[keypad]\n10 = \"g\"\n").unwrap();
+        assert!(config.controls(&ROM).is_err());
+
+        for text in [
+            "layuot = \"qwerty\"",
+            "layout = \"colemak\"",
+            "[hotkeys]\nquit = \"tt\"",
+            "[hotkeys]\npause = \"p\"",
+            "keypad = 5",
+        ] {
+            assert!(
+                matches!(Config::parse(text), Err(ConfigError::Parse(_))),
+                "{}",
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,1132 @@
+//! Debug Adapter Protocol server of `chip_eight dap`, for debugging ROMs
+//! from an editor. Messages are read from stdin and written to stdout.
+//!
+//! The `launch` request takes `program`, a ROM or an assembler source
+//! (`.asm`, assembled on launch so breakpoints can be set on its lines),
+//! and optionally `platform`, `quirks`, `ipf` and `stopOnEntry`. The
+//! machine runs without a screen, the Screen scope shows the display and
+//! `key N` / `release N` in the debug console hold a key down and let it go,
+//! `release` lets go of every key.
+
+use crate::frontend::args::{platform_for, PlatformArg, QuirksPreset};
+use chip_eight::asm::{assemble_file_with_map, SourceMap};
+use chip_eight::chip::Chip;
+use chip_eight::controls::Chip8Key;
+use chip_eight::debugger::{decode_at, Debugger};
+use chip_eight::display::headless::HeadlessDisplay;
+use chip_eight::error::Chip8Error;
+use chip_eight::nibble::Nibble;
+use chip_eight::scheduler::Scheduler;
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+/// The only thread there is
+const THREAD_ID: u64 = 1;
+
+/// Variable references of the scopes, memory pages follow [`MEMORY_PAGES`]
+const REGISTERS: u64 = 1;
+const STACK: u64 = 2;
+const SCREEN: u64 = 3;
+const MEMORY: u64 = 4;
+const MEMORY_PAGES: u64 = 0x100;
+
+/// Bytes per memory page and per row within a page
+const PAGE_SIZE: usize = 0x100;
+const ROW_SIZE: usize = 16;
+
+type Reply = Result<Value, String>;
+
+/// Machine launched by the editor
+struct Target {
+    chip: Chip,
+    debugger: Debugger,
+    source_map: SourceMap,
+    instructions_per_frame: usize,
+    stop_on_entry: bool,
+    /// Breakpoint addresses set per source file
+    source_breakpoints: HashMap<PathBuf, Vec<usize>>,
+    /// Breakpoint addresses set as instruction and function breakpoints
+    address_breakpoints: Vec<usize>,
+    address_function_breakpoints: Vec<usize>,
+    /// Running to the end of a step rather than to a breakpoint
+    stepping: bool,
+    /// Running at all, false before configurationDone and after exiting
+    started: bool,
+    /// Events for the session to send
+    events: Vec<(&'static str, Value)>,
+}
+
+struct Session<W: Write> {
+    out: W,
+    seq: u64,
+    target: Option<Target>,
+    /// Events to send after the response to the current request
+    events: Vec<(&'static str, Value)>,
+}
+
+/// Serve one editor until it disconnects
+pub fn run() -> Result<(), Box<dyn Error>> {
+    let requests = read_messages(BufReader::new(io::stdin()));
+    serve(requests, io::stdout())?;
+    Ok(())
+}
+
+/// Answer `requests` on `out` until the editor disconnects
+fn serve(requests: Receiver<Value>, out: impl Write) -> io::Result<()> {
+    let mut session = Session {
+        out,
+        seq: 0,
+        target: None,
+        events: Vec::new(),
+    };
+    let mut scheduler = Scheduler::new();
+
+    loop {
+        let request = if session.running() {
+            match requests.try_recv() {
+                Ok(request) => Some(request),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => break,
+            }
+        } else {
+            match requests.recv() {
+                Ok(request) => {
+                    // Do not catch up on the time spent stopped
+                    scheduler = Scheduler::new();
+                    Some(request)
+                }
+                Err(_) => break,
+            }
+        };
+
+        match request {
+            Some(request) => {
+                if !session.handle(&request)? {
+                    break;
+                }
+            }
+            None => {
+                for _ in 0..scheduler.wait() {
+                    session.run_frame();
+                    if !session.running() {
+                        break;
+                    }
+                }
+                session.flush_events()?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read messages on a thread of their own, so they arrive while running
+fn read_messages(mut input: impl BufRead + Send + 'static) -> Receiver<Value> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        while let Ok(Some(message)) = read_message(&mut input) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Next `Content-Length` framed message, `None` at the end of input
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+impl<W: Write> Session<W> {
+    fn running(&self) -> bool {
+        self.target
+            .as_ref()
+            .is_some_and(|target| target.started && !target.debugger.is_paused())
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let body = message.to_string();
+        write!(self.out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.out.flush()
+    }
+
+    fn event(&mut self, event: &'static str, body: Value) {
+        self.events.push((event, body));
+    }
+
+    fn flush_events(&mut self) -> io::Result<()> {
+        for (event, body) in std::mem::take(&mut self.events) {
+            let mut message = json!({"type": "event", "event": event});
+            if !body.is_null() {
+                message["body"] = body;
+            }
+            self.send(message)?;
+        }
+        Ok(())
+    }
+
+    /// Answer a request, returning false once the session is over
+    fn handle(&mut self, request: &Value) -> io::Result<bool> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let args = &request["arguments"];
+        let reply = match command {
+            "initialize" => Ok(capabilities()),
+            "launch" => self.launch(args),
+            "disconnect" | "terminate" => Ok(Value::Null),
+            "threads" => Ok(json!({"threads": [{"id": THREAD_ID, "name": "CHIP-8"}]})),
+            _ => match &mut self.target {
+                Some(target) => {
+                    let reply = target.handle(command, args);
+                    self.events.append(&mut target.take_events());
+                    reply
+                }
+                None => Err("no program has been launched".to_string()),
+            },
+        };
+
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": reply.is_ok(),
+        });
+        match reply {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response)?;
+        self.flush_events()?;
+
+        if command == "terminate" {
+            self.send(json!({"type": "event", "event": "terminated"}))?;
+        }
+        Ok(command != "disconnect" && command != "terminate")
+    }
+
+    fn launch(&mut self, args: &Value) -> Reply {
+        let program = PathBuf::from(args["program"].as_str().ok_or("launch needs a program")?);
+        let platform = match args["platform"].as_str() {
+            Some(name) => Some(PlatformArg::from_str(name, true)?),
+            None => None,
+        };
+
+        let (rom, source_map) = if program.extension().is_some_and(|ext| ext == "asm") {
+            assemble_file_with_map(&program).map_err(|err| err.to_string())?
+        } else {
+            let rom = fs::read(&program).map_err(|err| format!("{:?}: {}", program, err))?;
+            (rom, SourceMap::default())
+        };
+
+        let mut chip =
+            Chip::with_platform(HeadlessDisplay::new(), platform_for(&program, platform));
+        if let Some(name) = args["quirks"].as_str() {
+            chip.quirks = QuirksPreset::from_str(name, true)?.into();
+        }
+        chip.load_rom(&rom).map_err(|err| err.to_string())?;
+
+        self.target = Some(Target {
+            chip,
+            debugger: Debugger::new(),
+            source_map,
+            instructions_per_frame: args["ipf"].as_u64().unwrap_or(10) as usize,
+            stop_on_entry: args["stopOnEntry"].as_bool().unwrap_or(false),
+            source_breakpoints: HashMap::new(),
+            address_breakpoints: Vec::new(),
+            address_function_breakpoints: Vec::new(),
+            stepping: false,
+            started: false,
+            events: Vec::new(),
+        });
+        // Breakpoints need the program, so configuration starts only now
+        self.event("initialized", Value::Null);
+        Ok(Value::Null)
+    }
+
+    fn run_frame(&mut self) {
+        if let Some(target) = &mut self.target {
+            target.run_frame();
+            self.events.append(&mut target.take_events());
+        }
+    }
+}
+
+fn capabilities() -> Value {
+    json!({
+        "supportsConfigurationDoneRequest": true,
+        "supportsFunctionBreakpoints": true,
+        "supportsInstructionBreakpoints": true,
+        "supportsReadMemoryRequest": true,
+        "supportsWriteMemoryRequest": true,
+        "supportsDisassembleRequest": true,
+        "supportsSetVariable": true,
+        "supportsEvaluateForHovers": true,
+        "supportsTerminateRequest": true,
+    })
+}
+
+impl Target {
+    fn take_events(&mut self) -> Vec<(&'static str, Value)> {
+        std::mem::take(&mut self.events)
+    }
+
+    fn handle(&mut self, command: &str, args: &Value) -> Reply {
+        match command {
+            "configurationDone" => {
+                self.started = true;
+                if self.stop_on_entry {
+                    self.stopped("entry", None);
+                } else {
+                    self.debugger.resume(&self.chip);
+                }
+                Ok(Value::Null)
+            }
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setInstructionBreakpoints" => {
+                let (addrs, breakpoints) = address_breakpoints(args, "instructionReference");
+                self.address_breakpoints = addrs;
+                self.update_breakpoints();
+                Ok(json!({ "breakpoints": breakpoints }))
+            }
+            "setFunctionBreakpoints" => {
+                let (addrs, breakpoints) = address_breakpoints(args, "name");
+                self.address_function_breakpoints = addrs;
+                self.update_breakpoints();
+                Ok(json!({ "breakpoints": breakpoints }))
+            }
+            "continue" => {
+                self.stepping = false;
+                self.debugger.resume(&self.chip);
+                Ok(json!({"allThreadsContinued": true}))
+            }
+            "next" => {
+                match self.debugger.step_over(&mut self.chip) {
+                    Ok(true) => self.after_step(),
+                    Ok(false) => self.stepping = true,
+                    Err(err) => self.fault(err),
+                }
+                Ok(Value::Null)
+            }
+            "stepIn" => {
+                self.step();
+                Ok(Value::Null)
+            }
+            "stepOut" => {
+                if self.debugger.step_out(&self.chip) {
+                    self.stepping = true;
+                } else {
+                    self.step();
+                }
+                Ok(Value::Null)
+            }
+            "pause" => {
+                self.debugger.pause();
+                self.stopped("pause", None);
+                Ok(Value::Null)
+            }
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(scopes()),
+            "variables" => self.variables(args["variablesReference"].as_u64().unwrap_or(0)),
+            "setVariable" => self.set_variable(args),
+            "evaluate" => self.evaluate(args["expression"].as_str().unwrap_or_default()),
+            "readMemory" => self.read_memory(args),
+            "writeMemory" => self.write_memory(args),
+            "disassemble" => self.disassemble(args),
+            _ => Err(format!("{} is not supported", command)),
+        }
+    }
+
+    fn run_frame(&mut self) {
+        let result = self
+            .debugger
+            .run_frame(&mut self.chip, self.instructions_per_frame);
+        match result {
+            Ok(frame) if frame.halted => self.exited(),
+            Ok(_) if self.debugger.is_paused() => {
+                let reason = if self.stepping { "step" } else { "breakpoint" };
+                self.stopped(reason, None);
+            }
+            Ok(_) => {}
+            Err(err) => self.fault(err),
+        }
+    }
+
+    fn step(&mut self) {
+        match self.debugger.step(&mut self.chip) {
+            Ok(_) => self.after_step(),
+            Err(err) => self.fault(err),
+        }
+    }
+
+    fn after_step(&mut self) {
+        if self.chip.halted {
+            self.exited();
+        } else {
+            self.stopped("step", None);
+        }
+    }
+
+    fn fault(&mut self, err: Chip8Error) {
+        self.debugger.pause();
+        self.stopped("exception", Some(err.to_string()));
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) {
+        self.stepping = false;
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(text) = text {
+            body["text"] = json!(text);
+        }
+        self.events.push(("stopped", body));
+    }
+
+    fn exited(&mut self) {
+        self.debugger.pause();
+        self.started = false;
+        self.events.push(("exited", json!({"exitCode": 0})));
+        self.events.push(("terminated", Value::Null));
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Reply {
+        let path = PathBuf::from(args["source"]["path"].as_str().unwrap_or_default());
+        let file = self.map_file(&path);
+        let mut addrs = Vec::new();
+        let mut breakpoints = Vec::new();
+        for breakpoint in args["breakpoints"].as_array().into_iter().flatten() {
+            let line = breakpoint["line"].as_u64().unwrap_or(0) as usize;
+            let found = file
+                .as_deref()
+                .and_then(|file| self.source_map.find_line(file, line));
+            breakpoints.push(match found {
+                Some(found) => {
+                    addrs.push(found.addr);
+                    json!({
+                        "verified": true,
+                        "line": found.line,
+                        "instructionReference": format!("0x{:04X}", found.addr),
+                    })
+                }
+                None => json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "no instruction on or after this line",
+                }),
+            });
+        }
+        self.source_breakpoints.insert(path, addrs);
+        self.update_breakpoints();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    /// Name `path` goes by in the source map
+    fn map_file(&self, path: &Path) -> Option<String> {
+        let path = fs::canonicalize(path).ok()?;
+        self.source_map
+            .lines()
+            .iter()
+            .map(|line| &line.file)
+            .find(|file| fs::canonicalize(file).is_ok_and(|file| file == path))
+            .cloned()
+    }
+
+    fn update_breakpoints(&mut self) {
+        let old: Vec<usize> = self.debugger.breakpoints().collect();
+        for addr in old {
+            self.debugger.remove_breakpoint(addr);
+        }
+        let new = self
+            .source_breakpoints
+            .values()
+            .flatten()
+            .chain(&self.address_breakpoints)
+            .chain(&self.address_function_breakpoints);
+        for &addr in new {
+            self.debugger.add_breakpoint(addr);
+        }
+    }
+
+    /// `source` and `line` of a stack frame or instruction at `addr`
+    fn location(&self, addr: usize, into: &mut Value) {
+        match self.source_map.find_addr(addr) {
+            Some(found) => {
+                let path =
+                    fs::canonicalize(&found.file).unwrap_or_else(|_| found.file.clone().into());
+                into["source"] = json!({ "path": path });
+                into["line"] = json!(found.line);
+            }
+            None => into["line"] = json!(0),
+        }
+        into["column"] = json!(0);
+    }
+
+    fn disassembly(&self, addr: usize) -> String {
+        match decode_at(&self.chip.memory, addr) {
+            Ok((opcode, _)) => opcode.to_string(),
+            Err(_) => "??".to_string(),
+        }
+    }
+
+    /// PC on top, then the CALL of every return address on the stack
+    fn stack_trace(&self) -> Value {
+        let pcs = std::iter::once(self.chip.program_counter).chain(
+            self.chip
+                .stack
+                .as_slice()
+                .iter()
+                .rev()
+                .map(|&ret| (ret as usize).saturating_sub(2)),
+        );
+        let frames: Vec<Value> = pcs
+            .enumerate()
+            .map(|(id, pc)| {
+                let mut frame = json!({
+                    "id": id,
+                    "name": format!("{:04X}: {}", pc, self.disassembly(pc)),
+                    "instructionPointerReference": format!("0x{:04X}", pc),
+                });
+                self.location(pc, &mut frame);
+                frame
+            })
+            .collect();
+        json!({ "totalFrames": frames.len(), "stackFrames": frames })
+    }
+
+    fn variables(&self, reference: u64) -> Reply {
+        let variables = match reference {
+            REGISTERS => self.registers(),
+            STACK => self
+                .chip
+                .stack
+                .as_slice()
+                .iter()
+                .enumerate()
+                .rev()
+                .map(|(idx, ret)| variable(&format!("[{}]", idx), format!("0x{:04X}", ret)))
+                .collect(),
+            SCREEN => {
+                let screen = self.chip.display.framebuffer();
+                (0..screen.height())
+                    .map(|y| {
+                        let row: String = (0..screen.width())
+                            .map(|x| if screen.get(x, y) { '█' } else { '·' })
+                            .collect();
+                        variable(&format!("{:02}", y), row)
+                    })
+                    .collect()
+            }
+            MEMORY => (0..self.chip.memory.len().div_ceil(PAGE_SIZE))
+                .map(|page| {
+                    let mut page_variable =
+                        variable(&format!("0x{:04X}", page * PAGE_SIZE), String::new());
+                    page_variable["variablesReference"] = json!(MEMORY_PAGES + page as u64);
+                    page_variable["memoryReference"] = json!(format!("0x{:04X}", page * PAGE_SIZE));
+                    page_variable
+                })
+                .collect(),
+            page => {
+                let start = (page.checked_sub(MEMORY_PAGES).ok_or("unknown variables")? as usize)
+                    * PAGE_SIZE;
+                let end = (start + PAGE_SIZE).min(self.chip.memory.len());
+                let bytes = self
+                    .chip
+                    .memory
+                    .slice(start, end)
+                    .map_err(|err| err.to_string())?;
+                bytes
+                    .chunks(ROW_SIZE)
+                    .enumerate()
+                    .map(|(row, bytes)| {
+                        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+                        variable(&format!("0x{:04X}", start + row * ROW_SIZE), hex.join(" "))
+                    })
+                    .collect()
+            }
+        };
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn registers(&self) -> Vec<Value> {
+        let mut registers: Vec<Value> = self
+            .chip
+            .registers
+            .all()
+            .iter()
+            .enumerate()
+            .map(|(x, v)| variable(&format!("V{:X}", x), format!("0x{:02X}", v)))
+            .collect();
+        let i = self.chip.registers.get_i();
+        let mut i_variable = variable("I", format!("0x{:04X}", i));
+        i_variable["memoryReference"] = json!(format!("0x{:04X}", i));
+        registers.push(i_variable);
+        registers.push(variable(
+            "PC",
+            format!("0x{:04X}", self.chip.program_counter),
+        ));
+        registers.push(variable("SP", self.chip.stack.as_slice().len().to_string()));
+        registers.push(variable(
+            "DT",
+            format!("0x{:02X}", self.chip.timers.get_delay()),
+        ));
+        registers.push(variable(
+            "ST",
+            format!("0x{:02X}", self.chip.timers.get_sound()),
+        ));
+        registers
+    }
+
+    /// Value of a register by name
+    fn register(&self, name: &str) -> Option<Value> {
+        let name = name.to_ascii_uppercase();
+        self.registers()
+            .into_iter()
+            .find(|register| register["name"] == name.as_str())
+    }
+
+    fn set_register(&mut self, name: &str, value: u64) -> Result<(), String> {
+        let name = name.to_ascii_uppercase();
+        let byte = u8::try_from(value).map_err(|_| format!("{} does not fit {}", value, name));
+        match name.as_str() {
+            "I" => self.chip.registers.set_i(value as u16),
+            "PC" => self.chip.program_counter = value as usize,
+            "DT" => self.chip.timers.set_delay(byte?),
+            "ST" => self.chip.timers.set_sound(byte?),
+            _ => {
+                let x = name
+                    .strip_prefix('V')
+                    .filter(|x| x.len() == 1)
+                    .and_then(|x| u8::from_str_radix(x, 16).ok())
+                    .ok_or_else(|| format!("{} cannot be changed", name))?;
+                self.chip.registers.set(Nibble::from_low(x), byte?);
+            }
+        }
+        Ok(())
+    }
+
+    fn set_variable(&mut self, args: &Value) -> Reply {
+        if args["variablesReference"].as_u64() != Some(REGISTERS) {
+            return Err("only registers can be changed".to_string());
+        }
+        let name = args["name"].as_str().unwrap_or_default();
+        let value = args["value"].as_str().unwrap_or_default();
+        self.set_register(name, parse_number(value)?)?;
+        let register = self.register(name).ok_or("unknown register")?;
+        Ok(json!({ "value": register["value"] }))
+    }
+
+    /// Registers by name, and `key N` / `release [N]` for the keypad
+    fn evaluate(&mut self, expression: &str) -> Reply {
+        let expression = expression.trim();
+        let keypad_key = |key: &str| {
+            parse_number(key)
+                .ok()
+                .and_then(|n| u8::try_from(n).ok())
+                .and_then(Chip8Key::new)
+                .ok_or("keys are 0 to 0xF")
+        };
+        if let Some(key) = expression.strip_prefix("key ") {
+            let key = keypad_key(key)?;
+            self.chip.key_down(key);
+            return Ok(
+                json!({"result": format!("key {:X} held", key.as_u8()), "variablesReference": 0}),
+            );
+        }
+        if let Some(key) = expression.strip_prefix("release ") {
+            let key = keypad_key(key)?;
+            self.chip.key_up(key);
+            return Ok(
+                json!({"result": format!("key {:X} released", key.as_u8()), "variablesReference": 0}),
+            );
+        }
+        if expression == "release" {
+            self.chip.keypad.clear();
+            return Ok(json!({"result": "keys released", "variablesReference": 0}));
+        }
+        let register = self
+            .register(expression)
+            .ok_or_else(|| format!("unknown register {}", expression))?;
+        Ok(json!({"result": register["value"], "variablesReference": 0}))
+    }
+
+    /// Start address of a request from `memoryReference` and `offset`
+    fn memory_address(args: &Value) -> Result<usize, String> {
+        let reference = parse_number(args["memoryReference"].as_str().unwrap_or_default())?;
+        let offset = args["offset"].as_i64().unwrap_or(0);
+        usize::try_from(reference as i64 + offset).map_err(|_| "address before memory".to_string())
+    }
+
+    fn read_memory(&self, args: &Value) -> Reply {
+        let addr = Self::memory_address(args)?;
+        let count = args["count"].as_u64().unwrap_or(0) as usize;
+        let start = addr.min(self.chip.memory.len());
+        let end = addr.saturating_add(count).min(self.chip.memory.len());
+        let bytes = self
+            .chip
+            .memory
+            .slice(start, end)
+            .map_err(|err| err.to_string())?;
+        Ok(json!({
+            "address": format!("0x{:04X}", addr),
+            "data": base64_encode(bytes),
+            "unreadableBytes": count - bytes.len(),
+        }))
+    }
+
+    fn write_memory(&mut self, args: &Value) -> Reply {
+        let addr = Self::memory_address(args)?;
+        let data =
+            base64_decode(args["data"].as_str().unwrap_or_default()).ok_or("data is not base64")?;
+        self.chip
+            .memory
+            .load(addr, &data)
+            .map_err(|err| err.to_string())?;
+        Ok(json!({ "bytesWritten": data.len() }))
+    }
+
+    /// Instructions decoded one after another from the requested address.
+    /// Instructions before it are assumed to be two bytes long.
+    fn disassemble(&self, args: &Value) -> Reply {
+        let start = parse_number(args["memoryReference"].as_str().unwrap_or_default())? as i64
+            + args["offset"].as_i64().unwrap_or(0)
+            + args["instructionOffset"].as_i64().unwrap_or(0) * 2;
+        // No more than memory can hold, the count comes from the client
+        let count = args["instructionCount"]
+            .as_u64()
+            .unwrap_or(0)
+            .min(self.chip.memory.len() as u64 / 2) as usize;
+
+        let mut instructions = Vec::with_capacity(count);
+        let mut addr = start;
+        for _ in 0..count {
+            let mut instruction = json!({ "address": format!("0x{:04X}", addr.max(0)) });
+            let decoded = usize::try_from(addr)
+                .ok()
+                .and_then(|addr| decode_at(&self.chip.memory, addr).ok().map(|d| (addr, d)));
+            match decoded {
+                Some((at, (opcode, size))) => {
+                    let bytes = self.chip.memory.slice(at, at + size).unwrap_or_default();
+                    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+                    instruction["instructionBytes"] = json!(hex.join(" "));
+                    instruction["instruction"] = json!(opcode.to_string());
+                    if self.source_map.find_addr(at).is_some() {
+                        self.location(at, &mut instruction);
+                    }
+                    addr += size as i64;
+                }
+                None => {
+                    instruction["instruction"] = json!("??");
+                    addr += 2;
+                }
+            }
+            instructions.push(instruction);
+        }
+        Ok(json!({ "instructions": instructions }))
+    }
+}
+
+fn scopes() -> Value {
+    let scope = |name: &str, reference: u64, expensive: bool| json!({"name": name, "variablesReference": reference, "expensive": expensive});
+    json!({
+        "scopes": [
+            scope("Registers", REGISTERS, false),
+            scope("Stack", STACK, false),
+            scope("Screen", SCREEN, false),
+            scope("Memory", MEMORY, true),
+        ]
+    })
+}
+
+fn variable(name: &str, value: String) -> Value {
+    json!({"name": name, "value": value, "variablesReference": 0})
+}
+
+/// Breakpoints whose `field` holds an address, with the reply for each
+fn address_breakpoints(args: &Value, field: &str) -> (Vec<usize>, Vec<Value>) {
+    let mut addrs = Vec::new();
+    let mut breakpoints = Vec::new();
+    for breakpoint in args["breakpoints"].as_array().into_iter().flatten() {
+        let addr = parse_number(breakpoint[field].as_str().unwrap_or_default())
+            .ok()
+            .and_then(|addr| {
+                usize::try_from(addr as i64 + breakpoint["offset"].as_i64().unwrap_or(0)).ok()
+            });
+        breakpoints.push(match addr {
+            Some(addr) => {
+                addrs.push(addr);
+                json!({"verified": true, "instructionReference": format!("0x{:04X}", addr)})
+            }
+            None => json!({"verified": false, "message": "expected an address like 0x200"}),
+        });
+    }
+    (addrs, breakpoints)
+}
+
+/// Decimal, or hex with `0x` or `$`
+fn parse_number(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let parsed = match text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .or_else(|| text.strip_prefix('$'))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("{:?} is not a number", text))
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (idx, &byte)| n | (byte as u32) << (16 - 8 * idx));
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * idx)) as usize & 0x3F] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    // Padding only at the end, and never after a lone character
+    let data = text.trim_end_matches('=');
+    if text.len() - data.len() > 2 || data.len() % 4 == 1 {
+        return None;
+    }
+    let mut out = Vec::new();
+    let mut n = 0u32;
+    let mut bits = 0;
+    for ch in data.bytes() {
+        let value = BASE64.iter().position(|&c| c == ch)? as u32;
+        n = (n << 6) | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((n >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::io::{Cursor, PipeReader};
+    use std::sync::mpsc::Sender;
+
+    // 1: start:
+    // 2:   LD V0, 5
+    // 3: loop:
+    // 4:   ADD V0, 1
+    // 5:   JP loop
+    const SOURCE: &str = "start:\n  LD V0, 5\nloop:\n  ADD V0, 1\n  JP loop\n";
+
+    fn framed(body: &str) -> String {
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    /// Scripted editor
+    struct Client {
+        requests: Sender<Value>,
+        replies: BufReader<PipeReader>,
+        seq: u64,
+        /// Events that arrived while waiting for a response
+        events: VecDeque<Value>,
+    }
+
+    impl Client {
+        /// Send a request and wait for its response
+        fn request(&mut self, command: &str, arguments: Value) -> Value {
+            self.seq += 1;
+            let request = json!({
+                "seq": self.seq,
+                "type": "request",
+                "command": command,
+                "arguments": arguments,
+            });
+            self.requests.send(request).unwrap();
+            loop {
+                let message = read_message(&mut self.replies).unwrap().unwrap();
+                if message["type"] == "event" {
+                    self.events.push_back(message);
+                    continue;
+                }
+                assert_eq!(message["request_seq"], self.seq);
+                assert_eq!(message["command"], command);
+                return message;
+            }
+        }
+
+        /// Next event, waiting for it to be sent
+        fn event(&mut self) -> Value {
+            if let Some(event) = self.events.pop_front() {
+                return event;
+            }
+            let message = read_message(&mut self.replies).unwrap().unwrap();
+            assert_eq!(message["type"], "event");
+            message
+        }
+    }
+
+    /// Serve `script` with [`SOURCE`] to launch
+    fn session(name: &str, script: impl FnOnce(&mut Client, &Path)) {
+        let dir = std::env::temp_dir().join(format!("chip8-dap-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("main.asm");
+        fs::write(&source, SOURCE).unwrap();
+
+        let (requests, receiver) = mpsc::channel();
+        let (replies, out) = io::pipe().unwrap();
+        let server = thread::spawn(move || serve(receiver, out));
+        let mut client = Client {
+            requests,
+            replies: BufReader::new(replies),
+            seq: 0,
+            events: VecDeque::new(),
+        };
+        script(&mut client, &source);
+        let response = client.request("disconnect", Value::Null);
+        assert_eq!(response["success"], true);
+        server.join().unwrap().unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn launch(client: &mut Client, source: &Path) {
+        let response = client.request("initialize", json!({"adapterID": "chip8"}));
+        assert_eq!(response["body"]["supportsConfigurationDoneRequest"], true);
+        let response = client.request("launch", json!({"program": source}));
+        assert_eq!(response["success"], true);
+        assert_eq!(client.event()["event"], "initialized");
+    }
+
+    #[test]
+    fn test_breakpoint_continue_stopped() {
+        session("breakpoints", |client, source| {
+            launch(client, source);
+
+            let response = client.request(
+                "setBreakpoints",
+                json!({
+                    "source": {"path": source},
+                    "breakpoints": [{"line": 3}, {"line": 9}],
+                }),
+            );
+            let breakpoints = &response["body"]["breakpoints"];
+            assert_eq!(breakpoints[0]["verified"], true);
+            assert_eq!(breakpoints[0]["line"], 4);
+            assert_eq!(breakpoints[0]["instructionReference"], "0x0202");
+            assert_eq!(breakpoints[1]["verified"], false);
+
+            client.request("configurationDone", Value::Null);
+            let stopped = client.event();
+            assert_eq!(stopped["event"], "stopped");
+            assert_eq!(stopped["body"]["reason"], "breakpoint");
+
+            let response = client.request("stackTrace", json!({"threadId": THREAD_ID}));
+            let frame = &response["body"]["stackFrames"][0];
+            assert_eq!(frame["instructionPointerReference"], "0x0202");
+            assert_eq!(frame["line"], 4);
+            let response = client.request("evaluate", json!({"expression": "V0"}));
+            assert_eq!(response["body"]["result"], "0x05");
+
+            let response = client.request("continue", json!({"threadId": THREAD_ID}));
+            assert_eq!(response["success"], true);
+            assert_eq!(client.event()["body"]["reason"], "breakpoint");
+            let response = client.request("evaluate", json!({"expression": "V0"}));
+            assert_eq!(response["body"]["result"], "0x06");
+        });
+    }
+
+    #[test]
+    fn test_memory_pages_and_reads() {
+        session("memory", |client, source| {
+            launch(client, source);
+            client.request("configurationDone", Value::Null);
+            client.request("pause", json!({"threadId": THREAD_ID}));
+
+            let response = client.request("variables", json!({"variablesReference": MEMORY}));
+            let pages = response["body"]["variables"].as_array().unwrap();
+            assert_eq!(pages.len(), 0x1000 / PAGE_SIZE);
+            assert_eq!(pages[2]["name"], "0x0200");
+            let page = pages[2]["variablesReference"].clone();
+
+            let response = client.request("variables", json!({"variablesReference": page}));
+            let rows = response["body"]["variables"].as_array().unwrap();
+            assert_eq!(rows.len(), PAGE_SIZE / ROW_SIZE);
+            assert!(rows[0]["value"]
+                .as_str()
+                .unwrap()
+                .starts_with("60 05 70 01 12 02"));
+
+            let response = client.request(
+                "readMemory",
+                json!({"memoryReference": "0x200", "offset": 2, "count": 2}),
+            );
+            assert_eq!(response["body"]["data"], "cAE=");
+            let response = client.request(
+                "readMemory",
+                json!({"memoryReference": "0xFFE", "count": 4}),
+            );
+            assert_eq!(response["body"]["unreadableBytes"], 2);
+
+            let response = client.request(
+                "writeMemory",
+                json!({"memoryReference": "0x300", "data": "q80="}),
+            );
+            assert_eq!(response["body"]["bytesWritten"], 2);
+            let response = client.request(
+                "readMemory",
+                json!({"memoryReference": "0x300", "count": 2}),
+            );
+            assert_eq!(response["body"]["data"], "q80=");
+        });
+    }
+
+    /// Target of `rom` without an editor around it
+    fn target(rom: &[u8]) -> Target {
+        let mut chip = Chip::new(HeadlessDisplay::new());
+        chip.load_rom(rom).unwrap();
+        Target {
+            chip,
+            debugger: Debugger::new(),
+            source_map: SourceMap::default(),
+            instructions_per_frame: 10,
+            stop_on_entry: false,
+            source_breakpoints: HashMap::new(),
+            address_breakpoints: Vec::new(),
+            address_function_breakpoints: Vec::new(),
+            stepping: false,
+            started: false,
+            events: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_console_keys() {
+        let mut target = target(&[0x12, 0x00]);
+        let held = |target: &Target| {
+            (0..16)
+                .filter(|&n| target.chip.keypad.is_pressed(Chip8Key::new(n).unwrap()))
+                .collect::<Vec<_>>()
+        };
+
+        target.chip.key_down(Chip8Key::new(1).unwrap());
+        assert_eq!(target.evaluate("key 5").unwrap()["result"], "key 5 held");
+        target.evaluate("key 0xA").unwrap();
+        assert_eq!(held(&target), [1, 5, 0xA]);
+        assert_eq!(
+            target.evaluate("release 5").unwrap()["result"],
+            "key 5 released"
+        );
+        assert_eq!(held(&target), [1, 0xA]);
+        target.evaluate("release").unwrap();
+        assert!(held(&target).is_empty());
+
+        assert!(target.evaluate("key 16").is_err());
+        assert!(target.evaluate("release x").is_err());
+    }
+
+    #[test]
+    fn test_disassemble_count_is_bounded_by_memory() {
+        let target = target(&[0x60, 0x05, 0x12, 0x00]);
+        let response = target
+            .disassemble(&json!({"memoryReference": "0x200", "instructionCount": 1u64 << 40}))
+            .unwrap();
+        let instructions = response["instructions"].as_array().unwrap();
+        assert_eq!(instructions.len(), target.chip.memory.len() / 2);
+        assert_eq!(instructions[0]["instruction"], "LD V0, 0x05");
+    }
+
+    #[test]
+    fn test_read_message_framing() {
+        let mut input = Cursor::new(format!(
+            "{}content-length: 2\r\nX-Other: 1\r\n\r\n{{}}",
+            framed(r#"{"seq":1}"#)
+        ));
+        assert_eq!(read_message(&mut input).unwrap().unwrap()["seq"], 1);
+        assert_eq!(read_message(&mut input).unwrap().unwrap(), json!({}));
+        assert!(read_message(&mut input).unwrap().is_none());
+
+        // The input ends inside the header
+        let mut input = Cursor::new("Content-Len");
+        assert!(read_message(&mut input).unwrap().is_none());
+
+        let mut input = Cursor::new("Content-Length: ten\r\n\r\n{}");
+        let err = read_message(&mut input).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut input = Cursor::new("Content-Length: 10\r\n\r\n{}");
+        let err = read_message(&mut input).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut input = Cursor::new(framed("{nope"));
+        let err = read_message(&mut input).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_base64_round_trip() {
+        let cases: [(&[u8], &str); 5] = [
+            (b"", ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (&[0xFB, 0xFF, 0x00, 0x3E], "+/8APg=="),
+        ];
+        for (bytes, text) in cases {
+            assert_eq!(base64_encode(bytes), text);
+            assert_eq!(base64_decode(text).unwrap(), bytes);
+        }
+        let bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(base64_decode(&base64_encode(&bytes)).unwrap(), bytes);
+
+        assert_eq!(base64_decode("Zm9v!"), None);
+        assert_eq!(base64_decode("Zm 9v"), None);
+        assert_eq!(base64_decode("Zg=v"), None);
+        assert_eq!(base64_decode("Zm9vZ"), None);
+        assert_eq!(base64_decode("Zg==="), None);
+    }
+}
@@ -0,0 +1,186 @@
+use chip_eight::chip::Chip;
+use chip_eight::debugger::{decode_at, Debugger};
+use chip_eight::error::Chip8Error;
+use std::io::{self, Write};
+
+/// Instructions listed before and after PC
+const LISTING_CONTEXT: usize = 4;
+
+/// Longest disassembly shown for one instruction
+const LISTING_WIDTH: usize = 48;
+
+const ENTER: u8 = b'\r';
+const ESCAPE: u8 = 0x1B;
+const BACKSPACE: u8 = 0x7F;
+
+/// What an address typed at the prompt is for
+#[derive(Clone, Copy)]
+enum Prompt {
+    Breakpoint,
+    RunTo,
+}
+
+/// Keyboard commands and machine view of `--debug`.
+///
+/// While paused, keys are debugger commands:
+///
+/// - `s`: step one instruction
+/// - `n`: step over CALL
+/// - `c`: continue until a breakpoint
+/// - `g`: run to an address
+/// - `b`: toggle a breakpoint, at PC when no address is typed
+pub struct DebugConsole {
+    pub debugger: Debugger,
+    prompt: Option<(Prompt, String)>,
+    message: String,
+}
+
+impl DebugConsole {
+    pub fn new() -> Self {
+        DebugConsole {
+            debugger: Debugger::new(),
+            prompt: None,
+            message: String::new(),
+        }
+    }
+
+    /// Act on a key pressed while paused
+    pub fn handle_key(&mut self, key: u8, chip: &mut Chip) -> Result<(), Chip8Error> {
+        if let Some((prompt, text)) = &mut self.prompt {
+            match key {
+                ENTER => {
+                    let prompt = *prompt;
+                    let text = std::mem::take(text);
+                    self.prompt = None;
+                    self.submit(prompt, &text, chip);
+                }
+                ESCAPE => self.prompt = None,
+                BACKSPACE => {
+                    text.pop();
+                }
+                _ if (key as char).is_ascii_hexdigit() && text.len() < 4 => {
+                    text.push((key as char).to_ascii_uppercase())
+                }
+                _ => {}
+            }
+            return Ok(());
+        }
+
+        self.message.clear();
+        match key {
+            b's' => {
+                self.debugger.step(chip)?;
+            }
+            b'n' => {
+                self.debugger.step_over(chip)?;
+            }
+            b'c' => self.debugger.resume(chip),
+            b'g' => self.prompt = Some((Prompt::RunTo, String::new())),
+            b'b' => self.prompt = Some((Prompt::Breakpoint, String::new())),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn submit(&mut self, prompt: Prompt, text: &str, chip: &Chip) {
+        let addr = if text.is_empty() {
+            Some(chip.program_counter)
+        } else {
+            usize::from_str_radix(text, 16).ok()
+        };
+        let Some(addr) = addr else {
+            return;
+        };
+        match prompt {
+            Prompt::Breakpoint => {
+                let set = self.debugger.toggle_breakpoint(addr);
+                self.message = format!(
+                    "Breakpoint {} at {:03X}",
+                    if set { "set" } else { "removed" },
+                    addr
+                );
+            }
+            Prompt::RunTo => self.debugger.run_to(chip, addr),
+        }
+    }
+
+    /// Draw the machine state starting at terminal row `top`
+    pub fn draw(&self, out: &mut impl Write, chip: &Chip, top: u16) -> io::Result<()> {
+        let mut lines: Vec<String> = Vec::new();
+
+        let state = if self.debugger.is_paused() {
+            "paused"
+        } else {
+            "running"
+        };
+        lines.push(format!(
+            "[debug, {}] PC={:03X} I={:03X} DT={:02X} ST={:02X}",
+            state,
+            chip.program_counter,
+            chip.registers.get_i(),
+            chip.timers.get_delay(),
+            chip.timers.get_sound()
+        ));
+
+        for (n, values) in chip.registers.all().chunks(8).enumerate() {
+            let regs: Vec<String> = values
+                .iter()
+                .enumerate()
+                .map(|(i, v)| format!("V{:X}={:02X}", n * 8 + i, v))
+                .collect();
+            lines.push(regs.join(" "));
+        }
+
+        let stack: Vec<String> = chip
+            .stack
+            .as_slice()
+            .iter()
+            .map(|addr| format!("{:03X}", addr))
+            .collect();
+        lines.push(format!("Stack: [{}]", stack.join(", ")));
+
+        let breakpoints: Vec<String> = self
+            .debugger
+            .breakpoints()
+            .map(|addr| format!("{:03X}", addr))
+            .collect();
+        lines.push(format!("Breakpoints: [{}]", breakpoints.join(", ")));
+
+        let pc = chip.program_counter;
+        let start = pc.saturating_sub(2 * LISTING_CONTEXT);
+        for addr in (start..=pc + 2 * LISTING_CONTEXT).step_by(2) {
+            let marker = if addr == pc { '>' } else { ' ' };
+            let text = match (chip.memory.read_u16(addr), decode_at(&chip.memory, addr)) {
+                (Ok(raw), Ok((opcode, _))) => format!("{:04X}  {}", raw, opcode),
+                (Ok(raw), Err(_)) => format!("{:04X}  ???", raw),
+                (Err(_), _) => continue,
+            };
+            let text: String = text.chars().take(LISTING_WIDTH).collect();
+            lines.push(format!("{} {:03X}  {}", marker, addr, text));
+        }
+
+        lines.push(match &self.prompt {
+            Some((Prompt::Breakpoint, text)) => format!("Breakpoint address: {}_", text),
+            Some((Prompt::RunTo, text)) => format!("Run to address: {}_", text),
+            None if !self.message.is_empty() => self.message.clone(),
+            None => "s step  n step over  c continue  g run to  b breakpoint  ` pause".to_string(),
+        });
+
+        for (row, line) in lines.iter().enumerate() {
+            write!(
+                out,
+                "{}{}{}",
+                termion::cursor::Goto(1, top + row as u16),
+                termion::clear::CurrentLine,
+                line
+            )?;
+        }
+        out.flush()
+    }
+}
+
+impl Default for DebugConsole {
+    fn default() -> Self {
+        Self::new()
+    }
+}
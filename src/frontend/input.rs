@@ -0,0 +1,447 @@
+//! Keyboard input from the terminal.
+//!
+//! Terminals normally only send the text a key types, so a key going up is
+//! never seen. Terminals speaking the kitty keyboard protocol can report
+//! presses, repeats and releases as `CSI code ; modifiers:event ; text u`,
+//! which is turned on when the terminal answers the protocol query.
+//! Elsewhere [`HoldTimer`] lets keypad keys go a while after their last
+//! press. xterm's modifyOtherKeys has no release events, so it does not
+//! help here.
+
+use chip_eight::controls::Chip8Key;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+use termion::AsyncReader;
+
+/// Disambiguate escape codes (1), report event types (2), report every key
+/// as an escape code (8) and report the text of the key (16)
+const KITTY_FLAGS: u8 = 1 | 2 | 8 | 16;
+
+/// How long to wait for the terminal to answer the protocol query
+const QUERY_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Longest escape sequence waited for, longer ones are dropped
+const MAX_SEQUENCE: usize = 64;
+
+/// Alt (2), Ctrl (4), Super (8), Hyper (16) and Meta (32) of the modifier
+/// bits of a kitty key event
+const COMMAND_MODIFIERS: u32 = 2 | 4 | 8 | 16 | 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyKind {
+    Press,
+    /// The key is held and repeating, only with the kitty protocol
+    Repeat,
+    /// Only with the kitty protocol
+    Release,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub key: char,
+    pub kind: KeyKind,
+}
+
+impl KeyEvent {
+    fn press(key: char) -> Self {
+        KeyEvent {
+            key,
+            kind: KeyKind::Press,
+        }
+    }
+}
+
+/// Decodes the keys arriving on stdin.
+///
+/// With the kitty protocol on, dropping the reader turns it off again, so
+/// the shell is not left with escape codes for keys after an error or a
+/// panic.
+pub struct KeyReader {
+    kitty: bool,
+    /// Whether the protocol is on until the reader turns it off
+    pushed: bool,
+    /// Text of the keys held down by kitty key code, releases carry none
+    held: HashMap<u32, char>,
+    /// Bytes read but not decoded yet, the start of a key whose rest has
+    /// not arrived
+    pending: Vec<u8>,
+}
+
+impl KeyReader {
+    /// Reader turning on the kitty protocol if the terminal knows it
+    pub fn new(stdout: &mut impl Write, stdin: &mut AsyncReader) -> io::Result<Self> {
+        // Every terminal answers the device attributes query, so its answer
+        // ends the wait. The protocol query is only answered before it by
+        // terminals that support the protocol.
+        write!(stdout, "\x1b[?u\x1b[c")?;
+        stdout.flush()?;
+
+        let deadline = Instant::now() + QUERY_TIMEOUT;
+        let mut reply = Vec::new();
+        while Instant::now() < deadline && !has_reply(&reply, b'c') {
+            let mut buf = [0u8; 64];
+            match stdin.read(&mut buf) {
+                Ok(n) if n > 0 => reply.extend_from_slice(&buf[..n]),
+                _ => thread::sleep(Duration::from_millis(5)),
+            }
+        }
+
+        // Nothing can fail once the protocol is on, the reader owning it
+        // turns it off
+        let kitty = has_reply(&reply, b'u');
+        if kitty {
+            write!(stdout, "\x1b[>{}u", KITTY_FLAGS)?;
+            stdout.flush()?;
+        }
+        let mut reader = KeyReader::with_protocol(kitty);
+        reader.pushed = kitty;
+        Ok(reader)
+    }
+
+    fn with_protocol(kitty: bool) -> Self {
+        KeyReader {
+            kitty,
+            pushed: false,
+            held: HashMap::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Whether keys going up are reported
+    pub fn reports_releases(&self) -> bool {
+        self.kitty
+    }
+
+    /// Leave the terminal reporting keys as it did before
+    pub fn restore(&mut self, stdout: &mut impl Write) -> io::Result<()> {
+        if self.pushed {
+            write!(stdout, "\x1b[<u")?;
+            stdout.flush()?;
+            self.pushed = false;
+        }
+        Ok(())
+    }
+
+    /// Next pending key, if any
+    pub fn read(&mut self, stdin: &mut AsyncReader) -> Option<KeyEvent> {
+        let mut buf = [0u8; 64];
+        while let Ok(n @ 1..) = stdin.read(&mut buf) {
+            self.pending.extend_from_slice(&buf[..n]);
+        }
+        self.decode()
+    }
+
+    /// Next key of the pending bytes. A key whose bytes have not all
+    /// arrived stays pending for the next call.
+    fn decode(&mut self) -> Option<KeyEvent> {
+        loop {
+            let first = *self.pending.first()?;
+            if !self.kitty || first != 0x1B {
+                let (key, len) = read_char(&self.pending)?;
+                self.pending.drain(..len);
+                return Some(KeyEvent::press(key));
+            }
+            // Every key arrives as an escape code with the protocol on, so
+            // a lone ESC is the start of one
+            match self.pending.get(1) {
+                None => return None,
+                Some(b'[') => {}
+                Some(_) => {
+                    self.pending.remove(0);
+                    return Some(KeyEvent::press('\x1b'));
+                }
+            }
+
+            let Some(end) = self.pending[2..]
+                .iter()
+                .position(|byte| (0x40..=0x7E).contains(byte))
+            else {
+                if self.pending.len() > MAX_SEQUENCE {
+                    self.pending.clear();
+                }
+                return None;
+            };
+            let sequence: Vec<u8> = self.pending.drain(..end + 3).collect();
+            // Arrows and function keys end differently, none of them matter
+            if sequence[end + 2] == b'u' {
+                if let Some(event) = self.parse(&sequence[2..end + 2]) {
+                    return Some(event);
+                }
+            }
+        }
+    }
+
+    /// Parameters of a `CSI … u` key event. Keys pressed along with Ctrl,
+    /// Alt or the other command modifiers are shortcuts of the terminal or
+    /// the desktop, not keys for the emulator, so they are left out; Shift
+    /// and the lock keys only change the text.
+    fn parse(&mut self, sequence: &[u8]) -> Option<KeyEvent> {
+        let text = std::str::from_utf8(sequence).ok()?;
+        let mut fields = text.split(';');
+        let code: u32 = fields.next()?.split(':').next()?.parse().ok()?;
+        let (modifiers, event) = match fields.next() {
+            Some(field) => {
+                let mut parts = field.split(':');
+                let modifiers = match parts.next()? {
+                    "" => 1,
+                    modifiers => modifiers.parse::<u32>().ok()?,
+                };
+                (modifiers, parts.next().unwrap_or("1"))
+            }
+            None => (1, "1"),
+        };
+        let typed = fields
+            .next()
+            .and_then(|text| text.split(':').next())
+            .and_then(|code| code.parse().ok())
+            .and_then(char::from_u32);
+
+        let kind = match event {
+            "1" => KeyKind::Press,
+            "2" => KeyKind::Repeat,
+            "3" => KeyKind::Release,
+            _ => return None,
+        };
+        // A key going up is let go whatever else is held by then
+        if kind != KeyKind::Release && modifiers.saturating_sub(1) & COMMAND_MODIFIERS != 0 {
+            return None;
+        }
+        let key = match kind {
+            KeyKind::Release => self.held.remove(&code),
+            _ => typed,
+        }
+        .or_else(|| char::from_u32(code))?;
+        if kind == KeyKind::Press {
+            self.held.insert(code, key);
+        }
+        Some(KeyEvent { key, kind })
+    }
+}
+
+impl Drop for KeyReader {
+    fn drop(&mut self) {
+        // Nowhere to report a failure on the way out
+        let _ = self.restore(&mut io::stdout());
+    }
+}
+
+/// Whether `reply` holds an answer `CSI ? … final`
+fn has_reply(reply: &[u8], last: u8) -> bool {
+    reply.windows(3).enumerate().any(|(start, window)| {
+        window == b"\x1b[?"
+            && reply[start + 3..]
+                .iter()
+                .find(|&&byte| !(byte.is_ascii_digit() || byte == b';'))
+                == Some(&last)
+    })
+}
+
+/// Character at the start of `bytes` and its length, decoding UTF-8 so keys
+/// like the `é` of AZERTY keyboards arrive whole. `None` until all of the
+/// character is there; bytes that are not UTF-8 come out one by one.
+fn read_char(bytes: &[u8]) -> Option<(char, usize)> {
+    let first = *bytes.first()?;
+    let len = match first {
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => return Some((first as char, 1)),
+    };
+    let Some(bytes) = bytes.get(..len) else {
+        // Cut short by something that cannot continue it, it never will be
+        if bytes[1..].iter().any(|byte| byte & 0xC0 != 0x80) {
+            return Some((first as char, 1));
+        }
+        return None;
+    };
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.chars().next().map(|key| (key, len)),
+        Err(_) => Some((first as char, 1)),
+    }
+}
+
+/// Lets keypad keys go a number of frames after their last press, for
+/// terminals that only report presses. A held key repeats, which keeps it
+/// down, and any number of keys can be down at once.
+pub struct HoldTimer {
+    frames: u64,
+    /// Frame each key goes up on
+    until: [Option<u64>; 16],
+}
+
+impl HoldTimer {
+    pub fn new(frames: u64) -> Self {
+        HoldTimer {
+            frames,
+            until: [None; 16],
+        }
+    }
+
+    pub fn press(&mut self, key: Chip8Key, frame: u64) {
+        self.until[key.as_usize()] = Some(frame + self.frames);
+    }
+
+    /// Keys whose time is up by `frame`
+    pub fn expired(&mut self, frame: u64) -> Vec<Chip8Key> {
+        let mut keys = Vec::new();
+        for (n, until) in self.until.iter_mut().enumerate() {
+            if until.is_some_and(|until| until <= frame) {
+                *until = None;
+                keys.extend(Chip8Key::new(n as u8));
+            }
+        }
+        keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(reader: &mut KeyReader, bytes: &[u8]) -> Vec<KeyEvent> {
+        reader.pending.extend_from_slice(bytes);
+        std::iter::from_fn(|| reader.decode()).collect()
+    }
+
+    fn event(key: char, kind: KeyKind) -> KeyEvent {
+        KeyEvent { key, kind }
+    }
+
+    #[test]
+    fn test_has_reply() {
+        assert!(has_reply(b"\x1b[?0u\x1b[?62;22c", b'u'));
+        assert!(has_reply(b"\x1b[?0u\x1b[?62;22c", b'c'));
+        assert!(has_reply(b"typed\x1b[?1;2c", b'c'));
+        assert!(!has_reply(b"\x1b[?62;22c", b'u'));
+        // Not finished yet
+        assert!(!has_reply(b"\x1b[?62;2", b'c'));
+        assert!(!has_reply(b"\x1b[62c", b'c'));
+        assert!(!has_reply(b"", b'c'));
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut reader = KeyReader::with_protocol(true);
+        assert_eq!(reader.parse(b"119"), Some(event('w', KeyKind::Press)));
+        assert_eq!(reader.parse(b"119;1:2"), Some(event('w', KeyKind::Repeat)));
+        assert_eq!(reader.parse(b"119;1:3"), Some(event('w', KeyKind::Release)));
+
+        // Shifted keys type their text, and are let go by code without it
+        assert_eq!(
+            reader.parse(b"113:81;2;81"),
+            Some(event('Q', KeyKind::Press))
+        );
+        assert_eq!(reader.parse(b"113;1:3"), Some(event('Q', KeyKind::Release)));
+        assert_eq!(reader.parse(b"113;1:3"), Some(event('q', KeyKind::Release)));
+        assert_eq!(
+            reader.parse(b"233;1:1;233"),
+            Some(event('é', KeyKind::Press))
+        );
+
+        for malformed in [&b""[..], b"x", b"119;1:4", b"119;x:1", b"\xff"] {
+            assert_eq!(reader.parse(malformed), None, "{:?}", malformed);
+        }
+    }
+
+    #[test]
+    fn test_parse_modifiers() {
+        let mut reader = KeyReader::with_protocol(true);
+        // Ctrl, Alt and Ctrl+Shift
+        assert_eq!(reader.parse(b"119;5"), None);
+        assert_eq!(reader.parse(b"119;3:2"), None);
+        assert_eq!(reader.parse(b"119;6;87"), None);
+        // Caps and Num Lock
+        assert_eq!(reader.parse(b"119;65;87"), Some(event('W', KeyKind::Press)));
+        assert_eq!(reader.parse(b"119;129"), Some(event('w', KeyKind::Press)));
+        // Ctrl pressed while the key is held still lets it go
+        assert_eq!(reader.parse(b"119;5:3"), Some(event('w', KeyKind::Release)));
+    }
+
+    #[test]
+    fn test_read_char() {
+        assert_eq!(read_char(b"w"), Some(('w', 1)));
+        assert_eq!(read_char("éa".as_bytes()), Some(('é', 2)));
+        assert_eq!(read_char("²".as_bytes()), Some(('²', 2)));
+        assert_eq!(read_char("€".as_bytes()), Some(('€', 3)));
+        assert_eq!(read_char("🎮".as_bytes()), Some(('🎮', 4)));
+        // Waits for the rest
+        assert_eq!(read_char(&"€".as_bytes()[..2]), None);
+        assert_eq!(read_char(b""), None);
+        // Never finished
+        assert_eq!(read_char(b"\xE2w"), Some(('\u{E2}', 1)));
+        assert_eq!(read_char(b"\xC3\xC3"), Some(('\u{C3}', 1)));
+        assert_eq!(read_char(b"\x80"), Some(('\u{80}', 1)));
+    }
+
+    #[test]
+    fn test_keys_split_across_reads() {
+        let mut reader = KeyReader::with_protocol(true);
+        assert_eq!(events(&mut reader, b"\x1b"), vec![]);
+        assert_eq!(events(&mut reader, b"[11"), vec![]);
+        assert_eq!(
+            events(&mut reader, b"9u\x1b[119;1:3u\x1b[1"),
+            vec![event('w', KeyKind::Press), event('w', KeyKind::Release)]
+        );
+        assert_eq!(
+            events(&mut reader, b"13u"),
+            vec![event('q', KeyKind::Press)]
+        );
+
+        let mut reader = KeyReader::with_protocol(false);
+        let euro = "€".as_bytes();
+        assert_eq!(events(&mut reader, &euro[..1]), vec![]);
+        assert_eq!(
+            events(&mut reader, &[&euro[1..], b"w"].concat()),
+            vec![event('€', KeyKind::Press), event('w', KeyKind::Press)]
+        );
+    }
+
+    #[test]
+    fn test_escape_sequences() {
+        let mut reader = KeyReader::with_protocol(true);
+        // ESC without a CSI keeps the byte after it
+        assert_eq!(
+            events(&mut reader, b"\x1bw"),
+            vec![event('\x1b', KeyKind::Press), event('w', KeyKind::Press)]
+        );
+        // Arrows are skipped
+        assert_eq!(
+            events(&mut reader, b"\x1b[A\x1b[1;5B\x1b[101u"),
+            vec![event('e', KeyKind::Press)]
+        );
+        // A sequence that never ends is dropped
+        assert_eq!(events(&mut reader, &[b'\x1b', b'['].repeat(40)), vec![]);
+        assert!(reader.pending.is_empty());
+        assert_eq!(events(&mut reader, b"x"), vec![event('x', KeyKind::Press)]);
+
+        // Without the protocol escape codes are only text
+        let mut reader = KeyReader::with_protocol(false);
+        assert_eq!(
+            events(&mut reader, b"\x1b[u"),
+            vec![
+                event('\x1b', KeyKind::Press),
+                event('[', KeyKind::Press),
+                event('u', KeyKind::Press)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hold_timer_expired() {
+        let key = |n| Chip8Key::new(n).unwrap();
+        let mut timer = HoldTimer::new(30);
+        timer.press(key(5), 0);
+        timer.press(key(0xA), 10);
+        assert_eq!(timer.expired(29), vec![]);
+        assert_eq!(timer.expired(30), vec![key(5)]);
+        assert_eq!(timer.expired(31), vec![]);
+
+        // Presses while held keep the key down
+        timer.press(key(0xA), 35);
+        assert_eq!(timer.expired(40), vec![]);
+        timer.press(key(5), 60);
+        assert_eq!(timer.expired(100), vec![key(5), key(0xA)]);
+    }
+}
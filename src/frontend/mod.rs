@@ -0,0 +1,8 @@
+pub mod args;
+pub mod commands;
+pub mod config;
+pub mod dap;
+pub mod debug_console;
+pub mod input;
+pub mod slots;
+pub mod terminal;
@@ -0,0 +1,58 @@
+use chip_eight::chip::Chip;
+use chip_eight::savestate::{SaveState, SaveStateError};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Number of save state slots reachable from the keyboard
+pub const SLOTS: u8 = 10;
+
+/// Numbered save state files kept next to the ROM, `game.ch8.state0` to
+/// `game.ch8.state9`.
+pub struct StateSlots {
+    rom: PathBuf,
+    current: u8,
+}
+
+impl StateSlots {
+    pub fn new(rom: &Path) -> Self {
+        StateSlots {
+            rom: rom.to_path_buf(),
+            current: 0,
+        }
+    }
+
+    pub fn current(&self) -> u8 {
+        self.current
+    }
+
+    pub fn next(&mut self) {
+        self.current = (self.current + 1) % SLOTS;
+    }
+
+    pub fn previous(&mut self) {
+        self.current = (self.current + SLOTS - 1) % SLOTS;
+    }
+
+    pub fn path(&self) -> PathBuf {
+        let mut name = self.rom.as_os_str().to_owned();
+        name.push(format!(".state{}", self.current));
+        PathBuf::from(name)
+    }
+
+    pub fn save(&self, chip: &Chip) -> Result<(), SaveStateError> {
+        let mut file = BufWriter::new(File::create(self.path())?);
+        SaveState::capture(chip).write_to(&mut file)?;
+        Ok(file.flush()?)
+    }
+
+    pub fn load(&self, chip: &mut Chip) -> Result<(), SaveStateError> {
+        load_state(&self.path(), chip)
+    }
+}
+
+/// Restore the machine from a save state file
+pub fn load_state(path: &Path, chip: &mut Chip) -> Result<(), SaveStateError> {
+    SaveState::read_from(File::open(path)?)?.restore(chip);
+    Ok(())
+}
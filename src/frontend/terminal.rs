@@ -0,0 +1,584 @@
+use chip_eight::display::display_trait::Ch8Display;
+use chip_eight::display::framebuffer::Framebuffer;
+use chip_eight::display::palette::{Palette, Rgb};
+use clap::ValueEnum;
+use std::env;
+use std::fmt::Write as _;
+use std::io::{stdout, Write};
+use termion::clear;
+use termion::cursor::Goto;
+use termion::style;
+
+/// Glyph per colour index, shading the XO-CHIP planes apart
+const GLYPHS: [char; 4] = [' ', '█', '▓', '▒'];
+const ASCII_GLYPHS: [char; 4] = [' ', '#', '*', '.'];
+
+/// First Braille pattern, no dots raised
+const BRAILLE_BLANK: u32 = 0x2800;
+/// Dot bit of each pixel in a 2x4 Braille cell, by row then column
+const BRAILLE_DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+/// How pixels are drawn as terminal characters
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Renderer {
+    /// One full block per pixel
+    #[default]
+    Block,
+    /// Two full blocks per pixel, which makes them about square
+    DoubleWidth,
+    /// Upper and lower half blocks, two rows of pixels per line
+    HalfBlock,
+    /// Braille patterns, 2x4 pixels per character, fits 128x64 in 64x16
+    Braille,
+    /// Plain ASCII, one character per pixel
+    Ascii,
+}
+
+/// Colour escapes a terminal understands
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorDepth {
+    /// The 16 standard colours
+    Ansi16,
+    /// The xterm 256 colour palette
+    Ansi256,
+    /// 24-bit colour
+    Truecolor,
+}
+
+impl ColorDepth {
+    /// Best colour depth the terminal claims through `COLORTERM` and `TERM`,
+    /// `None` for terminals without colour or when `NO_COLOR` is set
+    pub fn detect() -> Option<Self> {
+        let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        let var = |name| env::var(name).unwrap_or_default();
+        Self::from_env(no_color, &var("COLORTERM"), &var("TERM"))
+    }
+
+    /// Colour depth given whether `NO_COLOR` is set, and the values of
+    /// `COLORTERM` and `TERM`
+    fn from_env(no_color: bool, colorterm: &str, term: &str) -> Option<Self> {
+        if no_color {
+            return None;
+        }
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            Some(ColorDepth::Truecolor)
+        } else if term.contains("256color") {
+            Some(ColorDepth::Ansi256)
+        } else if term.is_empty() || term == "dumb" {
+            None
+        } else {
+            Some(ColorDepth::Ansi16)
+        }
+    }
+
+    /// Escape selecting `rgb`, or the closest colour there is, as the
+    /// foreground or background colour
+    fn escape(self, rgb: Rgb, background: bool) -> String {
+        let offset = if background { 10 } else { 0 };
+        match self {
+            ColorDepth::Ansi16 => {
+                let n = rgb.ansi16();
+                let code = if n < 8 { 30 + n } else { 90 + n - 8 };
+                format!("\x1b[{}m", code + offset)
+            }
+            ColorDepth::Ansi256 => format!("\x1b[{};5;{}m", 38 + offset, rgb.ansi256()),
+            ColorDepth::Truecolor => {
+                format!("\x1b[{};2;{};{};{}m", 38 + offset, rgb.0, rgb.1, rgb.2)
+            }
+        }
+    }
+}
+
+/// A character on the terminal with the colour indices it is drawn in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Cell {
+    glyph: char,
+    fg: u8,
+    bg: u8,
+}
+
+impl Renderer {
+    /// Terminal lines taken by a screen `height` pixels high
+    pub fn rows(self, height: usize) -> usize {
+        match self {
+            Renderer::HalfBlock => height.div_ceil(2),
+            Renderer::Braille => height.div_ceil(4),
+            Renderer::Block | Renderer::DoubleWidth | Renderer::Ascii => height,
+        }
+    }
+
+    /// Characters of every terminal line showing `screen`. In colour
+    /// pixels are told apart by their colours rather than by glyphs.
+    fn cells(self, screen: &Framebuffer, colored: bool) -> Vec<Vec<Cell>> {
+        let color = |x: usize, y: usize| {
+            if y < screen.height() {
+                screen.color(x, y)
+            } else {
+                0
+            }
+        };
+        match self {
+            Renderer::Block | Renderer::DoubleWidth | Renderer::Ascii => {
+                let width = if self == Renderer::DoubleWidth { 2 } else { 1 };
+                screen
+                    .rows()
+                    .map(|row| {
+                        row.iter()
+                            .flat_map(|&color| {
+                                let glyph = match (self, colored) {
+                                    (Renderer::Ascii, _) => ASCII_GLYPHS[color as usize],
+                                    // Painted with the background colour
+                                    (_, true) => ' ',
+                                    (_, false) => GLYPHS[color as usize],
+                                };
+                                let bg = if self == Renderer::Ascii { 0 } else { color };
+                                let cell = Cell {
+                                    glyph,
+                                    fg: color,
+                                    bg,
+                                };
+                                std::iter::repeat_n(cell, width)
+                            })
+                            .collect()
+                    })
+                    .collect()
+            }
+            Renderer::HalfBlock => (0..self.rows(screen.height()))
+                .map(|line| {
+                    (0..screen.width())
+                        .map(|x| {
+                            let (top, bottom) = (color(x, 2 * line), color(x, 2 * line + 1));
+                            if colored {
+                                // Both halves keep their own colour
+                                return Cell {
+                                    glyph: '▀',
+                                    fg: top,
+                                    bg: bottom,
+                                };
+                            }
+                            let glyph = match (top != 0, bottom != 0) {
+                                (true, true) => '█',
+                                (true, false) => '▀',
+                                (false, true) => '▄',
+                                (false, false) => ' ',
+                            };
+                            Cell {
+                                glyph,
+                                fg: top.max(bottom),
+                                bg: 0,
+                            }
+                        })
+                        .collect()
+                })
+                .collect(),
+            Renderer::Braille => (0..self.rows(screen.height()))
+                .map(|line| {
+                    (0..screen.width().div_ceil(2))
+                        .map(|cell| {
+                            let mut dots = 0;
+                            // A cell only has one colour, the first plane wins
+                            let mut fg = 0;
+                            for (dy, row) in BRAILLE_DOTS.iter().enumerate() {
+                                for (dx, dot) in row.iter().enumerate() {
+                                    let color = color(2 * cell + dx, 4 * line + dy);
+                                    if color != 0 {
+                                        dots |= dot;
+                                        fg = if fg == 0 { color } else { fg.min(color) };
+                                    }
+                                }
+                            }
+                            Cell {
+                                glyph: char::from_u32(BRAILLE_BLANK + dots).unwrap_or(' '),
+                                fg,
+                                bg: 0,
+                            }
+                        })
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Escapes for each colour index of a palette
+struct Pens {
+    fg: [String; 4],
+    bg: [String; 4],
+}
+
+impl Pens {
+    fn new(palette: &Palette, depth: ColorDepth) -> Self {
+        Pens {
+            fg: palette.colors.map(|rgb| depth.escape(rgb, false)),
+            bg: palette.colors.map(|rgb| depth.escape(rgb, true)),
+        }
+    }
+}
+
+/// Builds the escape codes for one update, switching colours only when
+/// the next character needs different ones
+struct Painter<'a> {
+    out: String,
+    pens: Option<&'a Pens>,
+    /// Colour indices currently selected, `None` for the terminal default
+    pen: Option<(u8, u8)>,
+}
+
+impl<'a> Painter<'a> {
+    fn new(pens: Option<&'a Pens>) -> Self {
+        Painter {
+            out: String::new(),
+            pens,
+            pen: None,
+        }
+    }
+
+    fn goto(&mut self, x: usize, y: usize) {
+        write!(self.out, "{}", Goto(1 + x as u16, 1 + y as u16)).unwrap();
+    }
+
+    fn put(&mut self, cell: Cell) {
+        if let Some(pens) = self.pens {
+            let (fg, bg) = self.pen.unzip();
+            if fg != Some(cell.fg) {
+                self.out.push_str(&pens.fg[cell.fg as usize]);
+            }
+            if bg != Some(cell.bg) {
+                self.out.push_str(&pens.bg[cell.bg as usize]);
+            }
+            self.pen = Some((cell.fg, cell.bg));
+        }
+        self.out.push(cell.glyph);
+    }
+
+    /// Back to the terminal colours
+    fn reset(&mut self) {
+        if self.pen.take().is_some() {
+            write!(self.out, "{}", style::Reset).unwrap();
+        }
+    }
+
+    fn clear_line_end(&mut self) {
+        self.reset();
+        write!(self.out, "{}", clear::UntilNewline).unwrap();
+    }
+
+    fn finish(mut self) -> String {
+        self.reset();
+        self.out
+    }
+}
+
+/// Draws the framebuffer in the terminal, rewriting only the characters
+/// that changed since the last time it was shown
+pub struct TerminalDisplay {
+    display_buffer: Framebuffer,
+    renderer: Renderer,
+    /// Colour escapes, `None` to tell pixels apart by glyphs alone
+    pens: Option<Pens>,
+    /// Characters on the terminal as last presented, by line
+    presented: Vec<Vec<Cell>>,
+}
+
+impl TerminalDisplay {
+    /// Display drawing with `renderer`, in the colours of the palette when
+    /// one is given
+    pub fn new(renderer: Renderer, colors: Option<(Palette, ColorDepth)>) -> Self {
+        TerminalDisplay {
+            display_buffer: Framebuffer::new(),
+            renderer,
+            pens: colors.map(|(palette, depth)| Pens::new(&palette, depth)),
+            presented: Vec::new(),
+        }
+    }
+}
+
+/// Escape codes turning the `old` lines on the terminal into `new`
+fn diff(old: &[Vec<Cell>], new: &[Vec<Cell>], pens: Option<&Pens>) -> String {
+    let mut painter = Painter::new(pens);
+    let resized = old.len() != new.len() || old.iter().zip(new).any(|(a, b)| a.len() != b.len());
+    if resized {
+        // The resolution changed, repaint everything
+        for (n, line) in new.iter().enumerate() {
+            painter.goto(0, n);
+            for &cell in line {
+                painter.put(cell);
+            }
+            painter.clear_line_end();
+        }
+        for n in new.len()..old.len() {
+            painter.goto(0, n);
+            write!(painter.out, "{}", clear::CurrentLine).unwrap();
+        }
+        return painter.finish();
+    }
+
+    for (n, (old, new)) in old.iter().zip(new).enumerate() {
+        let mut x = 0;
+        while x < new.len() {
+            if old[x] == new[x] {
+                x += 1;
+                continue;
+            }
+            // Move once for a run of changed characters
+            painter.goto(x, n);
+            while x < new.len() && old[x] != new[x] {
+                painter.put(new[x]);
+                x += 1;
+            }
+        }
+    }
+    painter.finish()
+}
+
+impl Ch8Display for TerminalDisplay {
+    fn buffer(&mut self) -> &mut Framebuffer {
+        &mut self.display_buffer
+    }
+    fn framebuffer(&self) -> &Framebuffer {
+        &self.display_buffer
+    }
+    fn render(&mut self) {
+        let cells = self
+            .renderer
+            .cells(&self.display_buffer, self.pens.is_some());
+        let update = diff(&self.presented, &cells, self.pens.as_ref());
+        self.presented = cells;
+        if update.is_empty() {
+            return;
+        }
+
+        // One write, so the terminal never shows half a frame
+        let mut out = stdout().lock();
+        out.write_all(update.as_bytes()).unwrap();
+        out.flush().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glyphs(line: &[Cell]) -> String {
+        line.iter().map(|cell| cell.glyph).collect()
+    }
+
+    #[test]
+    fn test_block_double_width_and_ascii() {
+        let mut screen = Framebuffer::new();
+        screen.set(1, 0, true);
+        screen.set_color(2, 0, 2);
+        screen.set_color(3, 1, 3);
+
+        let block = Renderer::Block.cells(&screen, false);
+        assert_eq!((block.len(), block[0].len()), (32, 64));
+        assert!(glyphs(&block[0]).starts_with(" █▓ "));
+        assert!(glyphs(&block[1]).starts_with("   ▒"));
+
+        let double = Renderer::DoubleWidth.cells(&screen, false);
+        assert_eq!((double.len(), double[0].len()), (32, 128));
+        assert!(glyphs(&double[0]).starts_with("  ██▓▓  "));
+
+        let ascii = Renderer::Ascii.cells(&screen, false);
+        assert!(glyphs(&ascii[0]).starts_with(" #* "));
+        assert!(glyphs(&ascii[1]).starts_with("   ."));
+        // ASCII keeps its glyphs in colour, the others paint the background
+        assert_eq!(Renderer::Ascii.cells(&screen, true), ascii);
+        let colored = Renderer::Block.cells(&screen, true);
+        assert_eq!(
+            colored[0][1],
+            Cell {
+                glyph: ' ',
+                fg: 1,
+                bg: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_half_block() {
+        let mut screen = Framebuffer::new();
+        screen.set(0, 0, true);
+        screen.set(1, 1, true);
+        screen.set(2, 0, true);
+        screen.set(2, 1, true);
+        screen.set(0, 3, true);
+
+        let cells = Renderer::HalfBlock.cells(&screen, false);
+        assert_eq!((cells.len(), cells[0].len()), (16, 64));
+        assert!(glyphs(&cells[0]).starts_with("▀▄█ "));
+        assert!(glyphs(&cells[1]).starts_with("▄ "));
+
+        // In colour each half keeps its own colour
+        let colored = Renderer::HalfBlock.cells(&screen, true);
+        assert_eq!(
+            colored[0][1],
+            Cell {
+                glyph: '▀',
+                fg: 0,
+                bg: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_braille_dots() {
+        let screen = Framebuffer::new();
+        let cells = Renderer::Braille.cells(&screen, false);
+        assert_eq!((cells.len(), cells[0].len()), (8, 32));
+        assert_eq!(cells[0][0].glyph, '⠀');
+
+        // Dots 1 2 3 7 down the left column, 4 5 6 8 down the right
+        let dots = [
+            ((0, 0), '⠁'),
+            ((0, 1), '⠂'),
+            ((0, 2), '⠄'),
+            ((0, 3), '⡀'),
+            ((1, 0), '⠈'),
+            ((1, 1), '⠐'),
+            ((1, 2), '⠠'),
+            ((1, 3), '⢀'),
+        ];
+        for ((x, y), glyph) in dots {
+            let mut screen = Framebuffer::new();
+            // In the second cell of the second line
+            screen.set(2 + x, 4 + y, true);
+            let cells = Renderer::Braille.cells(&screen, false);
+            assert_eq!(cells[1][1].glyph, glyph, "pixel {},{}", x, y);
+            assert_eq!(cells[1][1].fg, 1);
+        }
+
+        let mut screen = Framebuffer::new();
+        for y in 0..4 {
+            screen.set(0, y, true);
+            screen.set(1, y, true);
+        }
+        assert_eq!(Renderer::Braille.cells(&screen, false)[0][0].glyph, '⣿');
+    }
+
+    /// Cells of `text`, lit wherever it is not blank
+    fn line(text: &str) -> Vec<Cell> {
+        text.chars()
+            .map(|glyph| Cell {
+                glyph,
+                fg: (glyph != ' ') as u8,
+                bg: 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_diff_unchanged_writes_nothing() {
+        let lines = vec![line("# #"), line(" # ")];
+        assert_eq!(diff(&lines, &lines, None), "");
+    }
+
+    #[test]
+    fn test_diff_writes_changed_runs() {
+        let old = vec![line("      "), line("      ")];
+        let single = vec![line("      "), line("   #  ")];
+        assert_eq!(diff(&old, &single, None), format!("{}#", Goto(4, 2)));
+
+        // Adjacent changes share one move, separate ones get their own
+        let runs = vec![line("##  # "), line("      ")];
+        assert_eq!(
+            diff(&old, &runs, None),
+            format!("{}##{}#", Goto(1, 1), Goto(5, 1))
+        );
+    }
+
+    #[test]
+    fn test_diff_redraws_everything_on_resize() {
+        let small = vec![line("# "), line(" #")];
+        let full = format!(
+            "{}# {}{} #{}",
+            Goto(1, 1),
+            clear::UntilNewline,
+            Goto(1, 2),
+            clear::UntilNewline
+        );
+        assert_eq!(diff(&[], &small, None), full);
+
+        let big = vec![line("#   "), line("    "), line("    ")];
+        let update = diff(&big, &small, None);
+        assert!(update.starts_with(&full));
+        assert!(update.ends_with(&format!("{}{}", Goto(1, 3), clear::CurrentLine)));
+    }
+
+    #[test]
+    fn test_painter_switches_colours_only_when_needed() {
+        let pens = Pens::new(&Palette::default(), ColorDepth::Ansi256);
+        let old = vec![line("xxxx")];
+        let new = vec![line("## #")];
+        // The background stays black throughout, only the foreground changes
+        assert_eq!(
+            diff(&old, &new, Some(&pens)),
+            format!(
+                "{}\x1b[38;5;231m\x1b[48;5;16m##\x1b[38;5;16m \x1b[38;5;231m#{}",
+                Goto(1, 1),
+                style::Reset
+            )
+        );
+    }
+
+    #[test]
+    fn test_color_depth_from_env() {
+        let cases = [
+            (
+                false,
+                "truecolor",
+                "xterm-256color",
+                Some(ColorDepth::Truecolor),
+            ),
+            (false, "24bit", "xterm", Some(ColorDepth::Truecolor)),
+            (false, "truecolor", "", Some(ColorDepth::Truecolor)),
+            (false, "", "xterm-256color", Some(ColorDepth::Ansi256)),
+            (false, "yes", "screen-256color", Some(ColorDepth::Ansi256)),
+            (false, "", "xterm", Some(ColorDepth::Ansi16)),
+            (false, "", "linux", Some(ColorDepth::Ansi16)),
+            (false, "", "dumb", None),
+            (false, "", "", None),
+            (true, "truecolor", "xterm-256color", None),
+        ];
+        for (no_color, colorterm, term, depth) in cases {
+            assert_eq!(
+                ColorDepth::from_env(no_color, colorterm, term),
+                depth,
+                "NO_COLOR {} COLORTERM={:?} TERM={:?}",
+                no_color,
+                colorterm,
+                term
+            );
+        }
+    }
+
+    #[test]
+    fn test_color_escapes() {
+        let amber = Rgb(0xFF, 0xB0, 0x00);
+        assert_eq!(
+            ColorDepth::Truecolor.escape(amber, false),
+            "\x1b[38;2;255;176;0m"
+        );
+        assert_eq!(
+            ColorDepth::Truecolor.escape(amber, true),
+            "\x1b[48;2;255;176;0m"
+        );
+        assert_eq!(ColorDepth::Ansi256.escape(amber, false), "\x1b[38;5;214m");
+        assert_eq!(ColorDepth::Ansi256.escape(amber, true), "\x1b[48;5;214m");
+
+        // Normal colours are 30-37, bright ones 90-97
+        let red = Rgb(0xCD, 0x00, 0x00);
+        assert_eq!(ColorDepth::Ansi16.escape(red, false), "\x1b[31m");
+        assert_eq!(ColorDepth::Ansi16.escape(red, true), "\x1b[41m");
+        let white = Rgb(0xFF, 0xFF, 0xFF);
+        assert_eq!(ColorDepth::Ansi16.escape(white, false), "\x1b[97m");
+        assert_eq!(ColorDepth::Ansi16.escape(white, true), "\x1b[107m");
+    }
+
+    #[test]
+    fn test_rows() {
+        assert_eq!(Renderer::Block.rows(64), 64);
+        assert_eq!(Renderer::HalfBlock.rows(32), 16);
+        assert_eq!(Renderer::Braille.rows(64), 16);
+    }
+}
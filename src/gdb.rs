@@ -0,0 +1,571 @@
+//! GDB remote serial protocol stub, for debugging ROMs with `gdb-multiarch`
+//! or any other RSP client.
+//!
+//! Registers are numbered V0–VF (0–15, one byte each), I (16, two bytes),
+//! SP (17, one byte, the number of return addresses on the stack) and PC
+//! (18, two bytes). Multi-byte registers are sent little-endian like GDB
+//! expects by default, memory is sent as is and so stays big-endian.
+//!
+//! Supported packets are `?`, `g`/`G`, `p`/`P`, `m`/`M`, `s`, `c`,
+//! `Z0`/`z0` (and `Z1`/`z1`, handled the same), `k`, `D`, `qSupported`,
+//! `QStartNoAckMode` and the target description through `qXfer`. A Ctrl-C
+//! from the client interrupts a `c`.
+
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::chip::Chip;
+use crate::debugger::Debugger;
+use crate::error::{Chip8Error, Fault};
+use crate::nibble::Nibble;
+use crate::scheduler::Scheduler;
+use crate::stack::Stack;
+
+const REGISTER_I: usize = 16;
+const REGISTER_SP: usize = 17;
+const REGISTER_PC: usize = 18;
+const REGISTER_COUNT: usize = 19;
+
+/// Signals reported in stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+/// Byte GDB sends to interrupt a running target
+const INTERRUPT: u8 = 0x03;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" regnum="0" type="uint8"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+/// What the stub does after handling a packet
+enum Reply {
+    Packet(String),
+    /// Send the packet, then end the session
+    Close(String),
+    /// End the session without replying
+    Kill,
+}
+
+/// One GDB session on a connected client
+pub struct GdbStub<'a> {
+    chip: &'a mut Chip,
+    stream: TcpStream,
+    /// Bytes read but not yet consumed
+    pending: Vec<u8>,
+    debugger: Debugger,
+    instructions_per_frame: usize,
+    no_ack: bool,
+    /// Last packet sent, resent when the client asks with `-`
+    last_sent: Vec<u8>,
+}
+
+/// Wait for a client on `listener` and serve it until it detaches, kills the
+/// target or disconnects.
+pub fn serve(
+    chip: &mut Chip,
+    listener: &TcpListener,
+    instructions_per_frame: usize,
+) -> io::Result<()> {
+    let (stream, _) = listener.accept()?;
+    GdbStub::new(chip, stream, instructions_per_frame).run()
+}
+
+impl<'a> GdbStub<'a> {
+    pub fn new(chip: &'a mut Chip, stream: TcpStream, instructions_per_frame: usize) -> Self {
+        GdbStub {
+            chip,
+            stream,
+            pending: Vec::new(),
+            debugger: Debugger::new(),
+            instructions_per_frame,
+            no_ack: false,
+            last_sent: Vec::new(),
+        }
+    }
+
+    /// Serve packets until the session ends
+    pub fn run(mut self) -> io::Result<()> {
+        self.stream.set_nodelay(true)?;
+        while let Some(packet) = self.receive()? {
+            match self.handle(&packet)? {
+                Reply::Packet(reply) => self.send(&reply)?,
+                Reply::Close(reply) => return self.send(&reply),
+                Reply::Kill => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if self.pending.is_empty() {
+            let mut buf = [0; 256];
+            let read = self.stream.read(&mut buf)?;
+            if read == 0 {
+                return Ok(None);
+            }
+            self.pending.extend_from_slice(&buf[..read]);
+        }
+        Ok(Some(self.pending.remove(0)))
+    }
+
+    /// Next packet's data, `None` once the client disconnected
+    fn receive(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => {}
+                Some(b'-') => {
+                    let last = self.last_sent.clone();
+                    self.stream.write_all(&last)?;
+                    continue;
+                }
+                // Acks, and interrupts arriving while already stopped
+                Some(_) => continue,
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let mut checksum = [0; 2];
+            for digit in &mut checksum {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(byte) => *digit = byte,
+                }
+            }
+
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            if !self.no_ack {
+                if expected != Some(checksum_of(&data)) {
+                    self.stream.write_all(b"-")?;
+                    continue;
+                }
+                self.stream.write_all(b"+")?;
+            }
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())?;
+        self.last_sent = packet.into_bytes();
+        Ok(())
+    }
+
+    /// Whether the client sent a Ctrl-C, without blocking
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut buf = [0; 256];
+        let read = match self.stream.read(&mut buf) {
+            Ok(0) => {
+                self.stream.set_nonblocking(false)?;
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => 0,
+            Err(err) => {
+                self.stream.set_nonblocking(false)?;
+                return Err(err);
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        self.pending.extend_from_slice(&buf[..read]);
+
+        match self.pending.iter().position(|&byte| byte == INTERRUPT) {
+            Some(idx) => {
+                self.pending.remove(idx);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn handle(&mut self, packet: &str) -> io::Result<Reply> {
+        let (command, args) = packet.split_at(packet.len().min(1));
+        let reply = match command {
+            "?" => Some(stop_reply(SIGTRAP)),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(args),
+            "p" => self.read_register(args),
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "s" => {
+                let result = self.debugger.step(self.chip);
+                return Ok(self.stop(result.map(|_| ())));
+            }
+            "c" => return self.resume(),
+            "Z" | "z" => self.breakpoint(command == "Z", args),
+            "H" => ok(),
+            "k" => return Ok(Reply::Kill),
+            "D" => return Ok(Reply::Close("OK".to_string())),
+            "q" | "Q" => self.query(packet),
+            _ => Some(String::new()),
+        };
+        Ok(Reply::Packet(reply.unwrap_or_else(error)))
+    }
+
+    fn query(&mut self, packet: &str) -> Option<String> {
+        if packet.starts_with("qSupported") {
+            return Some("PacketSize=1000;qXfer:features:read+;QStartNoAckMode+".to_string());
+        }
+        if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, length) = args.split_once(',')?;
+            let offset = usize::from_str_radix(offset, 16).ok()?;
+            let length = usize::from_str_radix(length, 16).ok()?;
+            let xml = TARGET_XML.as_bytes();
+            let start = offset.min(xml.len());
+            let end = (start + length).min(xml.len());
+            let marker = if end == xml.len() { 'l' } else { 'm' };
+            return Some(format!("{}{}", marker, &TARGET_XML[start..end]));
+        }
+        match packet {
+            "QStartNoAckMode" => {
+                // This packet has already been acknowledged
+                self.no_ack = true;
+                ok()
+            }
+            "qAttached" => Some("1".to_string()),
+            "qC" => Some("QC1".to_string()),
+            "qfThreadInfo" => Some("m1".to_string()),
+            "qsThreadInfo" => Some("l".to_string()),
+            _ => Some(String::new()),
+        }
+    }
+
+    fn register(&self, n: usize) -> Option<String> {
+        Some(match n {
+            0..=15 => format!("{:02x}", self.chip.registers.get(Nibble::from_low(n as u8))),
+            REGISTER_I => hex_le16(self.chip.registers.get_i()),
+            REGISTER_SP => format!("{:02x}", self.chip.stack.as_slice().len()),
+            REGISTER_PC => hex_le16(self.chip.program_counter as u16),
+            _ => return None,
+        })
+    }
+
+    /// Set register `n` from its little-endian bytes
+    fn set_register(&mut self, n: usize, bytes: &[u8]) -> Option<()> {
+        let value = bytes
+            .iter()
+            .rev()
+            .fold(0usize, |value, &byte| (value << 8) | byte as usize);
+        match (n, bytes.len()) {
+            (0..=15, 1) => self
+                .chip
+                .registers
+                .set(Nibble::from_low(n as u8), value as u8),
+            (REGISTER_I, 2) => self.chip.registers.set_i(value as u16),
+            (REGISTER_SP, 1) => {
+                // Deeper than before pushes zeroes, shallower drops the top
+                let mut values = self.chip.stack.as_slice().to_vec();
+                values.resize(value, 0);
+                self.chip.stack = Stack::from_slice(&values).ok()?;
+            }
+            (REGISTER_PC, 2) => self.chip.program_counter = value,
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn read_registers(&self) -> Option<String> {
+        (0..REGISTER_COUNT).map(|n| self.register(n)).collect()
+    }
+
+    fn write_registers(&mut self, args: &str) -> Option<String> {
+        let bytes = decode_hex(args)?;
+        let mut offset = 0;
+        for n in 0..REGISTER_COUNT {
+            let size = register_size(n);
+            self.set_register(n, bytes.get(offset..offset + size)?)?;
+            offset += size;
+        }
+        ok()
+    }
+
+    fn read_register(&self, args: &str) -> Option<String> {
+        self.register(usize::from_str_radix(args, 16).ok()?)
+    }
+
+    fn write_register(&mut self, args: &str) -> Option<String> {
+        let (n, value) = args.split_once('=')?;
+        let n = usize::from_str_radix(n, 16).ok()?;
+        self.set_register(n, &decode_hex(value)?)?;
+        ok()
+    }
+
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let (addr, length) = parse_range(args)?;
+        let bytes = self
+            .chip
+            .memory
+            .slice(addr, addr.checked_add(length)?)
+            .ok()?;
+        Some(encode_hex(bytes))
+    }
+
+    fn write_memory(&mut self, args: &str) -> Option<String> {
+        let (range, data) = args.split_once(':')?;
+        let (addr, length) = parse_range(range)?;
+        let bytes = decode_hex(data)?;
+        if bytes.len() != length {
+            return None;
+        }
+        self.chip.memory.load(addr, &bytes).ok()?;
+        ok()
+    }
+
+    fn breakpoint(&mut self, insert: bool, args: &str) -> Option<String> {
+        let mut fields = args.split(',');
+        let kind = fields.next()?;
+        let addr = usize::from_str_radix(fields.next()?, 16).ok()?;
+        if kind != "0" && kind != "1" {
+            // Watchpoints are not supported
+            return Some(String::new());
+        }
+        if insert {
+            self.debugger.add_breakpoint(addr);
+        } else {
+            self.debugger.remove_breakpoint(addr);
+        }
+        ok()
+    }
+
+    /// Run at normal speed until a breakpoint, a fault or a Ctrl-C
+    fn resume(&mut self) -> io::Result<Reply> {
+        let mut scheduler = Scheduler::new();
+        self.debugger.resume(self.chip);
+        loop {
+            for _ in 0..scheduler.wait() {
+                let frame = self
+                    .debugger
+                    .run_frame(self.chip, self.instructions_per_frame);
+                match frame {
+                    Ok(frame) if !frame.halted && !self.debugger.is_paused() => {}
+                    result => return Ok(self.stop(result.map(|_| ()))),
+                }
+            }
+            if self.interrupted()? {
+                self.debugger.pause();
+                return Ok(Reply::Packet(stop_reply(SIGINT)));
+            }
+        }
+    }
+
+    /// Stop reply for the outcome of running
+    fn stop(&self, result: Result<(), Chip8Error>) -> Reply {
+        let signal = match result {
+            Ok(()) if self.chip.halted => return Reply::Close("W00".to_string()),
+            Ok(()) => SIGTRAP,
+            Err(Chip8Error::Execution {
+                fault: Fault::InvalidOpcode,
+                ..
+            }) => SIGILL,
+            Err(_) => SIGSEGV,
+        };
+        Reply::Packet(stop_reply(signal))
+    }
+}
+
+/// Bytes of register `n` in a `g` packet
+fn register_size(n: usize) -> usize {
+    match n {
+        REGISTER_I | REGISTER_PC => 2,
+        _ => 1,
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn stop_reply(signal: u8) -> String {
+    format!("S{:02x}", signal)
+}
+
+fn ok() -> Option<String> {
+    Some("OK".to_string())
+}
+
+fn error() -> String {
+    "E01".to_string()
+}
+
+fn hex_le16(value: u16) -> String {
+    encode_hex(&value.to_le_bytes())
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(hex.get(idx..idx + 2)?, 16).ok())
+        .collect()
+}
+
+/// `addr,length` in hex
+fn parse_range(args: &str) -> Option<(usize, usize)> {
+    let (addr, length) = args.split_once(',')?;
+    Some((
+        usize::from_str_radix(addr, 16).ok()?,
+        usize::from_str_radix(length, 16).ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::headless::HeadlessDisplay;
+    use std::thread;
+
+    // 200: LD V0, 0x05 ; 202: ADD V0, 1 ; 204: JP 202
+    const ROM: [u8; 6] = [0x60, 0x05, 0x70, 0x01, 0x12, 0x02];
+
+    /// Scripted RSP client
+    struct Client(TcpStream);
+
+    impl Client {
+        fn request(&mut self, data: &str) -> String {
+            self.send(data);
+            self.reply()
+        }
+
+        fn send(&mut self, data: &str) {
+            let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+            self.0.write_all(packet.as_bytes()).unwrap();
+            let mut ack = [0];
+            self.0.read_exact(&mut ack).unwrap();
+            assert_eq!(ack[0], b'+');
+        }
+
+        fn reply(&mut self) -> String {
+            let mut reply = Vec::new();
+            let mut byte = [0];
+            loop {
+                self.0.read_exact(&mut byte).unwrap();
+                if byte[0] == b'#' {
+                    break;
+                }
+                if byte[0] != b'$' {
+                    reply.push(byte[0]);
+                }
+            }
+            let mut checksum = [0; 2];
+            self.0.read_exact(&mut checksum).unwrap();
+            let checksum = u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16).unwrap();
+            assert_eq!(checksum, checksum_of(&reply));
+            self.0.write_all(b"+").unwrap();
+            String::from_utf8(reply).unwrap()
+        }
+    }
+
+    /// Serve a chip running [`ROM`] to `script`, returning the chip afterwards
+    fn session(script: impl FnOnce(&mut Client) + Send + 'static) -> Chip {
+        let mut chip = Chip::new(HeadlessDisplay::new());
+        chip.load_rom(&ROM).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut client = Client(TcpStream::connect(addr).unwrap());
+            script(&mut client);
+        });
+        serve(&mut chip, &listener, 10).unwrap();
+        client.join().unwrap();
+        chip
+    }
+
+    #[test]
+    fn test_registers_and_step() {
+        session(|client| {
+            assert_eq!(client.request("?"), "S05");
+            assert_eq!(client.request("s"), "S05");
+            let registers = client.request("g");
+            assert_eq!(registers.len(), 2 * (16 + 2 + 1 + 2));
+            assert_eq!(&registers[..2], "05");
+            assert_eq!(client.request("p12"), "0202");
+            assert_eq!(client.request("P3=2a"), "OK");
+            assert_eq!(client.request("p3"), "2a");
+            assert_eq!(client.request("p13"), "E01");
+            assert_eq!(client.request("D"), "OK");
+        });
+    }
+
+    #[test]
+    fn test_memory_read_write() {
+        let chip = session(|client| {
+            assert_eq!(client.request("m200,4"), "60057001");
+            assert_eq!(client.request("M300,2:abcd"), "OK");
+            assert_eq!(client.request("m300,2"), "abcd");
+            assert_eq!(client.request("mfff,2"), "E01");
+            assert_eq!(client.request("D"), "OK");
+        });
+        assert_eq!(chip.memory.read(0x301usize).unwrap(), 0xCD);
+    }
+
+    #[test]
+    fn test_breakpoint_and_continue() {
+        let chip = session(|client| {
+            assert_eq!(client.request("Z0,204,2"), "OK");
+            assert_eq!(client.request("c"), "S05");
+            assert_eq!(client.request("p12"), "0402");
+            assert_eq!(client.request("c"), "S05");
+            assert_eq!(client.request("p0"), "07");
+            assert_eq!(client.request("z0,204,2"), "OK");
+            client.send("k");
+        });
+        assert_eq!(chip.program_counter, 0x204);
+    }
+
+    #[test]
+    fn test_interrupt() {
+        session(|client| {
+            client.send("c");
+            thread::sleep(std::time::Duration::from_millis(50));
+            client.0.write_all(&[INTERRUPT]).unwrap();
+            assert_eq!(client.reply(), "S02");
+            assert_eq!(client.request("D"), "OK");
+        });
+    }
+}
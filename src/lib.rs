@@ -0,0 +1,42 @@
+//! CHIP 8 emulator core.
+//!
+//! Everything needed to load a ROM and execute it lives here, without any
+//! dependency on a terminal. Frontends drive the [`chip::Chip`] through
+//! [`chip::Chip::step`] or [`chip::Chip::run_frame`] and provide their own
+//! [`display::display_trait::Ch8Display`].
+
+pub mod asm;
+pub mod audio;
+pub mod chip;
+pub mod controls;
+pub mod debugger;
+pub mod disasm;
+pub mod display;
+pub mod error;
+pub mod execute;
+pub mod font;
+pub mod gdb;
+pub mod memory;
+pub mod movie;
+pub mod nibble;
+pub mod nibbles;
+pub mod opcode;
+pub mod platform;
+pub mod quirks;
+pub mod random;
+pub mod registers;
+pub mod rewind;
+pub mod savestate;
+pub mod scheduler;
+pub mod screenshot;
+pub mod stack;
+pub mod timers;
+pub mod trace;
+pub mod video;
+
+pub const MEMORY_SIZE: usize = 4096;
+pub const XO_CHIP_MEMORY_SIZE: usize = 65536;
+pub const DISPLAY_HEIGHT: usize = 32;
+pub const DISPLAY_WIDTH: usize = 64;
+pub const HIRES_DISPLAY_HEIGHT: usize = 64;
+pub const HIRES_DISPLAY_WIDTH: usize = 128;
@@ -1,46 +1,48 @@
-extern crate rand;
 use clap::Parser;
 use std::fs;
-use std::path::PathBuf;
-use std::thread;
-use std::time::Duration;
-
-mod args;
-mod chip;
-mod controls;
-mod display;
-mod execute;
-mod font;
-mod memory;
-mod nibble;
-mod nibbles;
-mod opcode;
-mod registers;
-mod stack;
-mod timers;
-extern crate termion;
-
-use args::Args;
-use font::*;
-use std::io::{stdout, Read, Write};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+mod frontend;
+
+use std::io::{stdout, Write};
 use termion::async_stdin;
 use termion::raw::IntoRawMode;
 
-use crate::controls::Chip8Key;
-use crate::display::terminal::TerminalDisplay;
-use crate::nibble::Nibble;
-// use crate::nibbles::Nibbles;
-use crate::opcode::Opcode;
+use chip_eight::audio::audio_sink::AudioSink;
+use chip_eight::audio::bell::BellSink;
+use chip_eight::audio::null::NullSink;
+use chip_eight::audio::pcm::PcmSink;
+use chip_eight::audio::wav::WavSink;
+use chip_eight::chip::Chip;
+use chip_eight::controls::Chip8Key;
+use chip_eight::display::display_trait::Ch8Display;
+use chip_eight::display::headless::HeadlessDisplay;
+use chip_eight::movie::{rom_hash, Movie};
+use chip_eight::platform::Platform;
+use chip_eight::rewind::{Rewind, DEFAULT_INTERVAL};
+use chip_eight::scheduler::Scheduler;
+use chip_eight::screenshot::Screenshot;
+use chip_eight::trace::Tracer;
+use chip_eight::video::gif::GifSink;
+use chip_eight::video::video_sink::VideoSink;
+use chip_eight::video::y4m::Y4mSink;
+use chip_eight::video::VideoFormat;
 use fern::Dispatch;
+use frontend::args::{Args, AudioOutput};
+use frontend::commands;
+use frontend::config::{Config, Hotkey, Hotkeys};
+use frontend::debug_console::DebugConsole;
+use frontend::input::{HoldTimer, KeyKind, KeyReader};
+use frontend::slots::{load_state, StateSlots};
+use frontend::terminal::{Renderer, TerminalDisplay};
 use log::info;
 
-const MEMORY_SIZE: usize = 4096;
-const DISPLAY_HEIGHT: usize = 32;
-const DISPLAY_WIDTH: usize = 64;
-
-// Due to how inputs are captured, this is the only way to exit the game,
-// without external interrupt or kill commands.
-const EXIT_GAME_KEY: u8 = b't';
+// Without release events a held key shows up as the key repeating, which
+// every terminal reports. Rewinding then goes on for this many frames after
+// the last repeat, which bridges the delay before the repeat starts.
+const REWIND_HOLD_FRAMES: u32 = 30;
 
 fn init_logging() -> Result<(), Box<dyn std::error::Error>> {
     Dispatch::new()
@@ -58,23 +60,109 @@ fn init_logging() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// What the frontend should do after a key press
+enum Input {
+    /// Any other key, possibly bound to the keypad
+    Key(char),
+    Quit,
+    SaveState,
+    LoadState,
+    SlotChanged,
+    Rewind,
+    Screenshot,
+}
+
 /// Processes one key press from stdin
-fn handle_input(
-    key_byte: u8,
-    chip: &mut chip::Chip,
-    stdout: &mut termion::raw::RawTerminal<std::io::StdoutLock<'static>>,
-) -> Option<Chip8Key> {
-    if key_byte == EXIT_GAME_KEY {
-        write!(
-            stdout,
-            "{}{}",
-            termion::clear::All,
-            termion::cursor::Goto(1, 1)
-        )
-        .unwrap();
-        panic!("User requested exit");
+fn handle_input(key: char, hotkeys: &Hotkeys, slots: &mut StateSlots) -> Input {
+    match hotkeys.lookup(key) {
+        // Due to how inputs are captured, this is the only way to exit the
+        // game, without external interrupt or kill commands.
+        Some(Hotkey::Quit) => Input::Quit,
+        Some(Hotkey::SaveState) => Input::SaveState,
+        Some(Hotkey::LoadState) => Input::LoadState,
+        Some(Hotkey::Rewind) => Input::Rewind,
+        Some(Hotkey::Screenshot) => Input::Screenshot,
+        Some(Hotkey::PreviousSlot) => {
+            slots.previous();
+            Input::SlotChanged
+        }
+        Some(Hotkey::NextSlot) => {
+            slots.next();
+            Input::SlotChanged
+        }
+        // Only meaningful under --debug, which handles it before
+        Some(Hotkey::Debug) | None => Input::Key(key),
+    }
+}
+
+/// Input movie being recorded or played back
+enum MovieMode {
+    Off,
+    Recording(Movie),
+    Playing(Movie),
+}
+
+/// Everything the event loop drives besides the chip and the terminal
+struct Session {
+    instructions_per_frame: usize,
+    audio: Box<dyn AudioSink>,
+    slots: StateSlots,
+    rewind: Rewind,
+    movie: MovieMode,
+    /// Frames run since power on, the clock movies are timed by
+    frame: u64,
+    debug: Option<DebugConsole>,
+    keys: KeyReader,
+    /// Lets keypad keys go when the terminal does not report releases
+    holds: Option<HoldTimer>,
+    hotkeys: Hotkeys,
+    /// Decides how many lines the screen takes, and so where the panels go
+    renderer: Renderer,
+    screenshot: Screenshot,
+    /// Screenshots are saved next to the ROM
+    rom_path: PathBuf,
+    video: Option<Box<dyn VideoSink>>,
+}
+
+impl Session {
+    /// Whether jumping around in time would break the movie
+    fn movie_active(&self) -> bool {
+        match &self.movie {
+            MovieMode::Off => false,
+            MovieMode::Recording(_) => true,
+            MovieMode::Playing(movie) => !movie.finished(self.frame),
+        }
     }
-    chip.try_press(key_byte as char)
+
+    /// Put a keypad key down or up, recording it when a movie is
+    fn keypad_event(&mut self, chip: &mut Chip, key: Chip8Key, pressed: bool) {
+        let playing = self.movie_active();
+        match &mut self.movie {
+            MovieMode::Playing(_) if playing => return,
+            MovieMode::Recording(movie) => movie.record(self.frame, key, pressed),
+            _ => {}
+        }
+        if !pressed {
+            chip.key_up(key);
+            return;
+        }
+        chip.key_down(key);
+        if let Some(holds) = &mut self.holds {
+            holds.press(key, self.frame);
+        }
+    }
+}
+
+/// `game.ch8.123.png` for a screenshot of `game.ch8` taken at frame 123
+fn screenshot_path(rom: &Path, frame: u64) -> PathBuf {
+    let mut name = rom.as_os_str().to_owned();
+    name.push(format!(".{}.png", frame));
+    PathBuf::from(name)
+}
+
+/// Terminal lines the screen currently takes
+fn screen_rows(chip: &Chip, renderer: Renderer) -> u16 {
+    renderer.rows(chip.display.framebuffer().height()) as u16
 }
 
 fn setup_terminal() -> (
@@ -89,65 +177,441 @@ fn setup_terminal() -> (
     (stdout, stdin)
 }
 
-fn load_rom(path: &PathBuf) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+fn load_rom(path: &Path) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
     info!("- Loading ROM from {:?}", path);
     let content = fs::read(path)?;
     Ok(content)
 }
 
 fn run_emulator(
-    chip: &mut chip::Chip,
+    chip: &mut Chip,
     stdout: &mut termion::raw::RawTerminal<std::io::StdoutLock<'static>>,
     stdin: &mut termion::AsyncReader,
-    frame_interval_ms: u64,
-) {
+    session: &mut Session,
+) -> Result<(), Box<dyn std::error::Error>> {
     info!("Starting event loop...");
     info!("VALU | OPCO | DESCRIPTION");
-    let mut wait_for_input: Option<usize> = None;
-
-    let mut bytes_iter = stdin.bytes();
-    while chip.program_counter < 4096 {
-        thread::sleep(Duration::from_millis(frame_interval_ms));
-
-        let key_pressed: Option<Chip8Key> = bytes_iter
-            .next()
-            .and_then(|res| res.ok())
-            .and_then(|b| handle_input(b, chip, stdout));
-
-        chip.timers.tick();
-        if let Some(vx) = wait_for_input {
-            write!(
-                stdout,
-                "{}",
-                termion::cursor::Goto(1, 2 + DISPLAY_HEIGHT as u16)
-            )
-            .unwrap();
-            if let Some(key) = key_pressed {
-                write!(stdout, "{}", termion::clear::CurrentLine).unwrap();
-                chip.registers.set(Nibble::from_low(vx as u8), key.as_u8());
-                wait_for_input = None;
-            } else {
-                write!(stdout, "Waiting...").unwrap();
+
+    let mut scheduler = Scheduler::new();
+    let mut was_waiting = false;
+    let mut message: Option<String> = None;
+    let mut rewind_frames = 0;
+    let mut redraw_debug = true;
+    while chip.program_counter < chip.memory.len() {
+        for _ in 0..scheduler.wait() {
+            if let Some(video) = &mut session.video {
+                video.frame(chip.display.framebuffer())?;
+            }
+            let mut redraw_status = false;
+            // Drain every key that arrived since the last frame.
+            while let Some(event) = session.keys.read(stdin) {
+                let key = event.key;
+                if event.kind == KeyKind::Release {
+                    if session.hotkeys.lookup(key) == Some(Hotkey::Rewind) {
+                        rewind_frames = 0;
+                    } else if let Some(key) = chip.keypad.lookup(key) {
+                        session.keypad_event(chip, key, false);
+                    }
+                    continue;
+                }
+                if let Some(debug) = &mut session.debug {
+                    let hotkey = session.hotkeys.lookup(key);
+                    if hotkey == Some(Hotkey::Debug) {
+                        if debug.debugger.is_paused() {
+                            debug.debugger.resume(chip);
+                        } else {
+                            debug.debugger.pause();
+                        }
+                        redraw_debug = true;
+                        continue;
+                    }
+                    if debug.debugger.is_paused() && hotkey != Some(Hotkey::Quit) {
+                        // The console only has ASCII commands
+                        if key.is_ascii() {
+                            debug.handle_key(key as u8, chip)?;
+                        }
+                        redraw_debug = true;
+                        continue;
+                    }
+                }
+                let slot = session.slots.current();
+                let input = handle_input(key, &session.hotkeys, &mut session.slots);
+                match input {
+                    Input::Quit => return Ok(()),
+                    // A repeat is not a new press, the key is still down
+                    Input::Key(_) if event.kind == KeyKind::Repeat => continue,
+                    Input::Key(ch) => {
+                        if let Some(key) = chip.keypad.lookup(ch) {
+                            session.keypad_event(chip, key, true);
+                        }
+                        continue;
+                    }
+                    Input::Rewind | Input::LoadState if session.movie_active() => {
+                        message = Some("Not available during a movie".to_string());
+                    }
+                    Input::Rewind => {
+                        rewind_frames = REWIND_HOLD_FRAMES;
+                        continue;
+                    }
+                    Input::SaveState => {
+                        message = Some(match session.slots.save(chip) {
+                            Ok(()) => format!("Saved slot {}", slot),
+                            Err(err) => format!("Saving slot {} failed: {}", slot, err),
+                        });
+                    }
+                    Input::LoadState => {
+                        message = Some(match session.slots.load(chip) {
+                            Ok(()) => format!("Loaded slot {}", slot),
+                            Err(err) => format!("Loading slot {} failed: {}", slot, err),
+                        });
+                    }
+                    Input::Screenshot => {
+                        let path = screenshot_path(&session.rom_path, session.frame);
+                        message = Some(
+                            match session.screenshot.save(chip.display.framebuffer(), &path) {
+                                Ok(()) => format!("Saved {}", path.display()),
+                                Err(err) => format!("Screenshot failed: {}", err),
+                            },
+                        );
+                    }
+                    Input::SlotChanged => {
+                        message = Some(format!("Slot {}", session.slots.current()))
+                    }
+                }
+                if let Some(message) = &message {
+                    info!("{}", message);
+                }
+                redraw_status = true;
+            }
+
+            if rewind_frames > 0 {
+                // Terminals reporting releases end it when the key goes up
+                if !session.keys.reports_releases() {
+                    rewind_frames -= 1;
+                }
+                session.rewind.rewind(chip);
+                session.audio.frame(false, &chip.audio)?;
                 continue;
             }
-            stdout.flush().unwrap();
+
+            if let MovieMode::Playing(movie) = &session.movie {
+                for event in movie.events(session.frame) {
+                    event.apply(chip);
+                }
+            }
+            let expired = match &mut session.holds {
+                Some(holds) => holds.expired(session.frame),
+                None => Vec::new(),
+            };
+            for key in expired {
+                session.keypad_event(chip, key, false);
+            }
+
+            let frame = match &mut session.debug {
+                Some(debug) => {
+                    let was_paused = debug.debugger.is_paused();
+                    let frame = debug
+                        .debugger
+                        .run_frame(chip, session.instructions_per_frame)?;
+                    redraw_debug |= debug.debugger.is_paused() != was_paused;
+                    if redraw_debug {
+                        let top = 4 + screen_rows(chip, session.renderer);
+                        debug.draw(stdout, chip, top)?;
+                        redraw_debug = false;
+                    }
+                    if was_paused {
+                        session.audio.frame(false, &chip.audio)?;
+                        continue;
+                    }
+                    frame
+                }
+                None => chip.run_frame(session.instructions_per_frame)?,
+            };
+            session.frame += 1;
+            session.rewind.record(chip);
+            session
+                .audio
+                .frame(chip.timers.is_sound_active(), &chip.audio)?;
+            if frame.halted {
+                return Ok(());
+            }
+
+            if let MovieMode::Playing(movie) = &session.movie {
+                if movie.finished(session.frame) && !movie.finished(session.frame - 1) {
+                    message = Some("Movie finished".to_string());
+                    info!("Movie finished at frame {}", session.frame);
+                    redraw_status = true;
+                }
+            }
+
+            if frame.waiting_for_key != was_waiting || redraw_status {
+                write!(
+                    stdout,
+                    "{}{}",
+                    termion::cursor::Goto(1, 2 + screen_rows(chip, session.renderer)),
+                    termion::clear::CurrentLine
+                )
+                .unwrap();
+                if frame.waiting_for_key {
+                    write!(stdout, "Waiting... ").unwrap();
+                }
+                if let Some(message) = &message {
+                    write!(stdout, "{}", message).unwrap();
+                }
+                stdout.flush().unwrap();
+                was_waiting = frame.waiting_for_key;
+            }
         }
-        let instruction_u16: u16 = chip.next_u16();
-        let opcode = Opcode::decode(instruction_u16);
+        // Present once for all the frames run since the last wait
+        chip.display.render();
+    }
+    Ok(())
+}
 
-        info!("{:?}", opcode);
+/// Opens the audio output picked on the command line. Headless runs have
+/// no bell to ring, and are the only ones leaving stdout free for
+/// `--audio-out -`.
+fn audio_sink(
+    args: &Args,
+    headless: bool,
+) -> Result<Box<dyn AudioSink>, Box<dyn std::error::Error>> {
+    let path = || {
+        args.audio_out
+            .as_ref()
+            .ok_or_else(|| format!("--audio {:?} needs --audio-out", args.audio))
+    };
+    Ok(match args.audio {
+        AudioOutput::None => Box::new(NullSink),
+        AudioOutput::Bell if headless => Box::new(NullSink),
+        AudioOutput::Bell => Box::new(BellSink::new(stdout())),
+        AudioOutput::Pcm => {
+            let path = path()?;
+            let out: Box<dyn Write> = if path == Path::new("-") {
+                if !headless {
+                    return Err(
+                        "--audio-out - needs --headless, the screen is drawn on stdout".into(),
+                    );
+                }
+                Box::new(stdout())
+            } else {
+                Box::new(BufWriter::new(File::create(path)?))
+            };
+            Box::new(PcmSink::new(out, args.tone()))
+        }
+        AudioOutput::Wav => {
+            let out = BufWriter::new(File::create(path()?)?);
+            Box::new(WavSink::new(out, args.tone())?)
+        }
+    })
+}
+
+/// Chip configured from the command line, the ROM not loaded yet
+struct Machine {
+    chip: Chip,
+    rom: Vec<u8>,
+    playback: Option<Movie>,
+    instructions_per_frame: usize,
+}
 
-        chip.execute(opcode);
+fn create_machine(
+    args: &Args,
+    display: impl Ch8Display + 'static,
+) -> Result<Machine, Box<dyn std::error::Error>> {
+    let rom = load_rom(args.rom())?;
+    let playback = match &args.play_movie {
+        Some(path) => {
+            info!("- Playing movie {:?}", path);
+            let movie = Movie::read_from(File::open(path)?)?;
+            if !movie.matches_rom(&rom) {
+                return Err(format!("{:?} was recorded with a different ROM", path).into());
+            }
+            Some(movie)
+        }
+        None => None,
+    };
+
+    let platform = playback
+        .as_ref()
+        .map_or_else(|| args.platform(), |movie| movie.platform);
+    info!("- Creating {:?} emulator...", platform);
+    let mut chip = Chip::with_platform(display, platform);
+    let mut instructions_per_frame = args.ipf;
+    if let Some(movie) = &playback {
+        movie.prepare(&mut chip);
+        instructions_per_frame = movie.instructions_per_frame;
+    } else {
+        if let Some(preset) = args.quirks {
+            info!("- Using {:?} quirks", preset);
+            chip.quirks = preset.into();
+        }
+        let (random, seed) = args.random();
+        info!("- Random numbers from {:?} seeded with {}", random, seed);
+        chip.random = random;
+    }
+
+    if let Some(path) = &args.trace {
+        info!("- Tracing to {:?}", path);
+        let out = BufWriter::new(File::create(path)?);
+        chip.tracer = Some(Tracer::with_format(out, args.trace_format.into()));
     }
+
+    Ok(Machine {
+        chip,
+        rom,
+        playback,
+        instructions_per_frame,
+    })
+}
+
+/// Load the ROM, and the save state given with `--load-state`
+fn start(chip: &mut Chip, rom: &[u8], args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    chip.load_rom(rom)?;
+    if let Some(path) = &args.load_state {
+        info!("- Loading state from {:?}", path);
+        load_state(path, chip)?;
+    }
+    Ok(())
+}
+
+/// `--headless`: run `frames` frames without the terminal, as fast as
+/// possible, taking the screenshot of `--screenshot-after-frames` on the way
+fn run_headless(args: &Args, frames: u64) -> Result<(), Box<dyn std::error::Error>> {
+    let Machine {
+        mut chip,
+        rom,
+        playback,
+        instructions_per_frame,
+    } = create_machine(args, HeadlessDisplay::new())?;
+    let mut shot = args.screenshot_after_frames()?;
+    let mut audio = audio_sink(args, true)?;
+    let mut video = video_sink(args, true, chip.platform)?;
+
+    let result = start(&mut chip, &rom, args).and_then(|_| {
+        for frame in 0..frames {
+            if let Some(movie) = &playback {
+                for event in movie.events(frame) {
+                    event.apply(&mut chip);
+                }
+            }
+            let halted = chip.run_frame(instructions_per_frame)?.halted;
+            audio.frame(chip.timers.is_sound_active(), &chip.audio)?;
+            if let Some(video) = &mut video {
+                video.frame(chip.display.framebuffer())?;
+            }
+            if halted {
+                break;
+            }
+            if let Some((_, path)) = shot.take_if(|(after, _)| *after == frame + 1) {
+                args.screenshot().save(chip.display.framebuffer(), &path)?;
+            }
+        }
+        // The ROM exited before the screenshot was due
+        if let Some((_, path)) = shot {
+            args.screenshot().save(chip.display.framebuffer(), &path)?;
+        }
+        Ok(())
+    });
+
+    let traced = chip.tracer.take().map_or(Ok(()), Tracer::finish);
+    let played = audio.finish();
+    let recorded = video.as_mut().map_or(Ok(()), |video| video.finish());
+    result
+        .and_then(|_| Ok(traced?))
+        .and_then(|_| Ok(played?))
+        .and_then(|_| Ok(recorded?))
+}
+
+/// Opens the video recording picked on the command line, sized for the
+/// screens of `platform`. Like `--audio-out -`, `-` needs a headless run.
+fn video_sink(
+    args: &Args,
+    headless: bool,
+    platform: Platform,
+) -> Result<Option<Box<dyn VideoSink>>, Box<dyn std::error::Error>> {
+    let Some(path) = &args.record_video else {
+        return Ok(None);
+    };
+    let format = args
+        .video_format
+        .map(VideoFormat::from)
+        .or_else(|| VideoFormat::from_path(path))
+        .ok_or_else(|| format!("{:?} is not a .gif or .y4m file, pick --video-format", path))?;
+    let out: Box<dyn Write> = if path == Path::new("-") {
+        if !headless {
+            return Err("--record-video - needs --headless, the screen is drawn on stdout".into());
+        }
+        if matches!(args.audio, AudioOutput::Pcm) && args.audio_out.as_deref() == Some(path) {
+            return Err("--audio-out and --record-video cannot both write to stdout".into());
+        }
+        Box::new(BufWriter::new(stdout()))
+    } else {
+        Box::new(BufWriter::new(File::create(path)?))
+    };
+    Ok(Some(match format {
+        VideoFormat::Gif => Box::new(GifSink::new(out, args.screenshot(), platform)),
+        VideoFormat::Y4m => Box::new(Y4mSink::new(out, args.screenshot(), platform)),
+    }))
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Parse args first before we mess with the terminal.
     let args = Args::parse();
+    if let Some(command) = &args.command {
+        if let Err(err) = commands::run(command) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    let headless = args
+        .headless_frames()
+        .map_err(Into::into)
+        .and_then(|frames| match frames {
+            Some(frames) => run_headless(&args, frames).map(|_| true),
+            None => Ok(false),
+        });
+    match headless {
+        Ok(true) => return Ok(()),
+        Ok(false) => {}
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+    init_logging()?;
+    info!("Initializing...");
+    // Everything that can fail before the terminal is taken over, so errors
+    // are readable
+    let setup = create_machine(
+        &args,
+        TerminalDisplay::new(args.renderer, args.terminal_colors()),
+    )
+    .and_then(|machine| {
+        let controls = Config::load(args.config.as_deref())
+            .and_then(|config| config.controls(&machine.rom))
+            .map_err(|err| format!("Config: {}", err))?;
+        let audio = audio_sink(&args, false)?;
+        let video = video_sink(&args, false, machine.chip.platform)?;
+        Ok((machine, controls, audio, video))
+    });
+    let (machine, controls, audio, video) = match setup {
+        Ok(setup) => setup,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    let Machine {
+        mut chip,
+        rom,
+        playback,
+        instructions_per_frame,
+    } = machine;
 
     let (mut stdout, mut stdin) = setup_terminal();
 
-    init_logging()?;
+    let keys = KeyReader::new(&mut stdout, &mut stdin)?;
+    info!("Key releases reported: {}", keys.reports_releases());
 
     write!(
         stdout,
@@ -156,21 +620,79 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         termion::cursor::Goto(1, 1)
     )
     .unwrap();
-    info!("Initializing...");
 
-    info!("- Initializing display...");
-    let display = TerminalDisplay::new();
-    info!("- Creating emulator...");
-    let mut chip = chip::Chip::new(display);
+    info!("ROM hash {:08x}", rom_hash(&rom));
+    chip.keypad.keymap = controls.keymap;
 
-    chip.load_rom(&load_rom(&args.rom)?);
-    info!("- Starting event loop...");
-    info!("VALU | OPCO | DESCRIPTION");
+    let movie = match playback {
+        Some(movie) => MovieMode::Playing(movie),
+        None if args.record_movie.is_some() => {
+            MovieMode::Recording(Movie::new(&chip, &rom, instructions_per_frame))
+        }
+        None => MovieMode::Off,
+    };
+    let mut session = Session {
+        instructions_per_frame,
+        audio,
+        slots: StateSlots::new(args.rom()),
+        rewind: Rewind::new(args.rewind_capacity(), DEFAULT_INTERVAL),
+        movie,
+        frame: 0,
+        debug: args.debug.then(DebugConsole::new),
+        holds: (!keys.reports_releases()).then(|| HoldTimer::new(args.key_hold_frames())),
+        keys,
+        hotkeys: controls.hotkeys,
+        renderer: args.renderer,
+        screenshot: args.screenshot(),
+        rom_path: args.rom().to_path_buf(),
+        video,
+    };
 
-    run_emulator(&mut chip, &mut stdout, &mut stdin, args.frame_interval_ms);
-    Ok(())
-}
+    let result = start(&mut chip, &rom, &args)
+        .and_then(|_| run_emulator(&mut chip, &mut stdout, &mut stdin, &mut session))
+        .and_then(|_| Ok(session.audio.finish()?));
+
+    // Flush the trace even when the emulator failed, it leads up to the
+    // failure.
+    let traced = chip.tracer.take().map_or(Ok(()), Tracer::finish);
+    let result = result.and_then(|_| Ok(traced?));
+    // Same for the video, the clip is what shows what went wrong
+    let recorded = session
+        .video
+        .as_mut()
+        .map_or(Ok(()), |video| video.finish());
+    let result = result.and_then(|_| Ok(recorded?));
 
-fn x(value: u16) -> u8 {
-    ((value & 0b0000111100000000) >> 8) as u8
+    // Keep the recording even when the emulator failed, it is what
+    // reproduces the failure.
+    if let (MovieMode::Recording(movie), Some(path)) = (&session.movie, &args.record_movie) {
+        info!("Writing movie to {:?}", path);
+        let written = File::create(path).and_then(|file| {
+            let mut out = BufWriter::new(file);
+            movie.write_to(&mut out)?;
+            out.flush()
+        });
+        if let Err(err) = written {
+            log::error!("Writing movie failed: {}", err);
+        }
+    }
+
+    session.keys.restore(&mut stdout)?;
+    write!(
+        stdout,
+        "{}{}",
+        termion::clear::All,
+        termion::cursor::Goto(1, 1)
+    )
+    .unwrap();
+    stdout.flush().unwrap();
+    // Leave raw mode before reporting, so the report is readable.
+    drop(stdout);
+
+    if let Err(err) = result {
+        log::error!("{}", err);
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+    Ok(())
 }
@@ -1,55 +1,89 @@
+use crate::error::Fault;
 use crate::MEMORY_SIZE;
 
 pub struct Memory {
-    ram: [u8; MEMORY_SIZE],
+    ram: Vec<u8>,
 }
 
 impl Memory {
     pub fn new() -> Self {
+        Self::with_size(MEMORY_SIZE)
+    }
+
+    /// Memory of `size` bytes, 64 KiB for XO-CHIP
+    pub fn with_size(size: usize) -> Self {
         Memory {
-            ram: [0u8; MEMORY_SIZE],
+            ram: vec![0u8; size],
         }
     }
 
-    pub fn read<A>(&self, addr: A) -> u8
+    pub fn len(&self) -> usize {
+        self.ram.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ram.is_empty()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn read<A>(&self, addr: A) -> Result<u8, Fault>
     where
         A: Into<usize>,
     {
         let addr = addr.into();
-        self.ram[addr]
+        self.ram
+            .get(addr)
+            .copied()
+            .ok_or(Fault::MemoryOutOfBounds { addr })
     }
 
-    pub fn write<A>(&mut self, addr: A, value: u8)
+    pub fn write<A>(&mut self, addr: A, value: u8) -> Result<(), Fault>
     where
         A: Into<usize>,
     {
         let addr = addr.into();
-        self.ram[addr] = value;
+        let cell = self
+            .ram
+            .get_mut(addr)
+            .ok_or(Fault::MemoryOutOfBounds { addr })?;
+        *cell = value;
+        Ok(())
     }
 
-    pub fn read_u16<A>(&self, addr: A) -> u16
+    pub fn read_u16<A>(&self, addr: A) -> Result<u16, Fault>
     where
         A: Into<usize>,
     {
         let addr = addr.into();
-        ((self.ram[addr] as u16) << 8) | self.ram[addr + 1] as u16
+        Ok(((self.read(addr)? as u16) << 8) | self.read(addr + 1)? as u16)
     }
 
-    pub fn slice<A>(&mut self, start: A, end: A) -> &[u8]
+    pub fn slice<A>(&self, start: A, end: A) -> Result<&[u8], Fault>
     where
         A: Into<usize>,
     {
         let start = start.into();
         let end = end.into();
-        &self.ram[start..end]
+        if end > self.ram.len() {
+            return Err(Fault::MemoryOutOfBounds { addr: end - 1 });
+        }
+        Ok(&self.ram[start.min(end)..end])
     }
 
-    pub fn load<A>(&mut self, start: A, bytes: &[u8])
+    pub fn load<A>(&mut self, start: A, bytes: &[u8]) -> Result<(), Fault>
     where
         A: Into<usize>,
     {
         let start = start.into();
-        self.ram[start..start + bytes.len()].copy_from_slice(bytes);
+        let end = start + bytes.len();
+        if end > self.ram.len() {
+            return Err(Fault::MemoryOutOfBounds { addr: end - 1 });
+        }
+        self.ram[start..end].copy_from_slice(bytes);
+        Ok(())
     }
     // pub fn print(&self, columns: usize) {
     //     info!("DUMPED RAM:");
@@ -62,3 +96,9 @@ impl Memory {
     //     println!();
     // }
 }
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,449 @@
+//! Recordings of the keypad input of a session.
+//!
+//! A movie holds everything that decides how a run unfolds besides the ROM:
+//! the platform, the quirks, the random number generator at power on, the
+//! instructions per frame and every key press with the frame it happened
+//! on. Replaying it against the same ROM gives the exact same session.
+//!
+//! Movies are plain text so they can be attached to bug reports and read:
+//!
+//! ```text
+//! chip8-movie 1
+//! platform schip
+//! quirks shift load_store jump clipping
+//! random xorshift 12345678
+//! ipf 10
+//! rom 1a2b3c4d
+//! 120 5
+//! 131 5 up
+//! 185 5
+//! 185 6
+//! 190 5 up
+//! ```
+//!
+//! Each line after the header is a frame number and the CHIP 8 key, in
+//! hex, that went down before that frame ran, or went up when followed by
+//! `up`.
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use crate::chip::Chip;
+use crate::controls::Chip8Key;
+use crate::platform::Platform;
+use crate::quirks::{LoadStore, Quirks};
+use crate::random::Random;
+
+pub const VERSION: u32 = 1;
+
+/// Quirks as named on the `quirks` line. `load_store` leaves I unchanged,
+/// `load_store_x` advances it by X.
+const QUIRK_NAMES: [&str; 7] = [
+    "shift",
+    "load_store",
+    "jump",
+    "vf_reset",
+    "clipping",
+    "display_wait",
+    "load_store_x",
+];
+
+#[derive(Debug)]
+pub enum MovieError {
+    Io(io::Error),
+    /// The line cannot be understood
+    Parse {
+        line: usize,
+        message: String,
+    },
+    /// Written by another version of the emulator
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::Io(err) => write!(f, "{}", err),
+            MovieError::Parse { line, message } => {
+                write!(f, "movie line {}: {}", line, message)
+            }
+            MovieError::UnsupportedVersion(v) => {
+                write!(f, "movie version {} is not supported", v)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<io::Error> for MovieError {
+    fn from(err: io::Error) -> Self {
+        MovieError::Io(err)
+    }
+}
+
+/// A key going down or up on a given frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MovieEvent {
+    pub frame: u64,
+    pub key: Chip8Key,
+    /// Whether the key went down rather than up
+    pub pressed: bool,
+}
+
+impl MovieEvent {
+    pub fn apply(&self, chip: &mut Chip) {
+        if self.pressed {
+            chip.key_down(self.key);
+        } else {
+            chip.key_up(self.key);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub platform: Platform,
+    pub quirks: Quirks,
+    /// Generator as it was at power on
+    pub random: Random,
+    pub instructions_per_frame: usize,
+    /// [`rom_hash`] of the ROM the movie was recorded with
+    pub rom_hash: u32,
+    /// Key presses and releases in frame order
+    pub events: Vec<MovieEvent>,
+}
+
+impl Movie {
+    /// Start recording a session of `chip`, which must not have run yet
+    pub fn new(chip: &Chip, rom: &[u8], instructions_per_frame: usize) -> Self {
+        Movie {
+            platform: chip.platform,
+            quirks: chip.quirks,
+            random: chip.random,
+            instructions_per_frame,
+            rom_hash: rom_hash(rom),
+            events: Vec::new(),
+        }
+    }
+
+    /// Record a key going down, or up, before `frame` runs
+    pub fn record(&mut self, frame: u64, key: Chip8Key, pressed: bool) {
+        self.events.push(MovieEvent {
+            frame,
+            key,
+            pressed,
+        });
+    }
+
+    /// Set up `chip`, freshly created for [`Movie::platform`], to replay
+    /// the movie.
+    pub fn prepare(&self, chip: &mut Chip) {
+        chip.quirks = self.quirks;
+        chip.random = self.random;
+    }
+
+    pub fn matches_rom(&self, rom: &[u8]) -> bool {
+        self.rom_hash == rom_hash(rom)
+    }
+
+    /// Keys going down or up before `frame` runs
+    pub fn events(&self, frame: u64) -> impl Iterator<Item = &MovieEvent> + '_ {
+        let start = self.events.partition_point(|e| e.frame < frame);
+        self.events[start..]
+            .iter()
+            .take_while(move |e| e.frame == frame)
+    }
+
+    /// Whether every key event happened before `frame`
+    pub fn finished(&self, frame: u64) -> bool {
+        self.events.last().is_none_or(|e| e.frame < frame)
+    }
+
+    pub fn write_to(&self, mut out: impl Write) -> io::Result<()> {
+        writeln!(out, "chip8-movie {}", VERSION)?;
+        writeln!(out, "platform {}", platform_name(self.platform))?;
+        write!(out, "quirks")?;
+        for (name, on) in QUIRK_NAMES.iter().zip(quirk_flags(&self.quirks)) {
+            if on {
+                write!(out, " {}", name)?;
+            }
+        }
+        writeln!(out)?;
+        match self.random {
+            Random::Xorshift(state) => writeln!(out, "random xorshift {}", state)?,
+            Random::Vip(r9) => writeln!(out, "random vip {}", r9)?,
+        }
+        writeln!(out, "ipf {}", self.instructions_per_frame)?;
+        writeln!(out, "rom {:08x}", self.rom_hash)?;
+        for event in &self.events {
+            write!(out, "{} {:x}", event.frame, event.key.as_u8())?;
+            if !event.pressed {
+                write!(out, " up")?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from(input: impl Read) -> Result<Self, MovieError> {
+        let mut lines = BufReader::new(input).lines();
+        let mut number = 0;
+        let mut next_line = |expected: &str| -> Result<(usize, String), MovieError> {
+            number += 1;
+            match lines.next() {
+                Some(line) => Ok((number, line?)),
+                None => Err(parse_error(number, format!("expected {}", expected))),
+            }
+        };
+
+        let (n, line) = next_line("header")?;
+        let version = header(n, &line, "chip8-movie")?
+            .parse()
+            .map_err(|_| parse_error(n, "bad version"))?;
+        if version != VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+
+        let (n, line) = next_line("platform")?;
+        let platform = match header(n, &line, "platform")? {
+            "chip8" => Platform::Chip8,
+            "schip" => Platform::SuperChip,
+            "xo-chip" => Platform::XoChip,
+            other => return Err(parse_error(n, format!("unknown platform {}", other))),
+        };
+
+        let (n, line) = next_line("quirks")?;
+        let names = header(n, &line, "quirks")?;
+        let mut flags = [false; 7];
+        for name in names.split_whitespace() {
+            let idx = QUIRK_NAMES
+                .iter()
+                .position(|q| *q == name)
+                .ok_or_else(|| parse_error(n, format!("unknown quirk {}", name)))?;
+            flags[idx] = true;
+        }
+        let load_store = match (flags[1], flags[6]) {
+            (true, true) => {
+                return Err(parse_error(n, "load_store and load_store_x both set"));
+            }
+            (true, false) => LoadStore::Unchanged,
+            (false, true) => LoadStore::IncrementByX,
+            (false, false) => LoadStore::Increment,
+        };
+        let quirks = Quirks {
+            shift: flags[0],
+            load_store,
+            jump: flags[2],
+            vf_reset: flags[3],
+            clipping: flags[4],
+            display_wait: flags[5],
+        };
+
+        let (n, line) = next_line("random")?;
+        let random = match header(n, &line, "random")?.split_once(' ') {
+            Some(("xorshift", state)) => {
+                state.parse().ok().filter(|&s| s != 0).map(Random::Xorshift)
+            }
+            Some(("vip", r9)) => r9.parse().ok().map(Random::Vip),
+            _ => None,
+        }
+        .ok_or_else(|| parse_error(n, "bad random number generator"))?;
+
+        let (n, line) = next_line("ipf")?;
+        let instructions_per_frame = header(n, &line, "ipf")?
+            .parse()
+            .map_err(|_| parse_error(n, "bad instructions per frame"))?;
+
+        let (n, line) = next_line("rom")?;
+        let rom_hash = u32::from_str_radix(header(n, &line, "rom")?, 16)
+            .map_err(|_| parse_error(n, "bad ROM hash"))?;
+
+        let mut events: Vec<MovieEvent> = Vec::new();
+        for (n, line) in lines.enumerate() {
+            let n = number + 1 + n;
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let event = parse_event(&line)
+                .ok_or_else(|| parse_error(n, "expected a frame, a key and maybe up"))?;
+            if events.last().is_some_and(|last| last.frame > event.frame) {
+                return Err(parse_error(n, "frames out of order"));
+            }
+            events.push(event);
+        }
+
+        Ok(Movie {
+            platform,
+            quirks,
+            random,
+            instructions_per_frame,
+            rom_hash,
+            events,
+        })
+    }
+}
+
+/// FNV-1a hash identifying the ROM a movie belongs to
+pub fn rom_hash(rom: &[u8]) -> u32 {
+    rom.iter().fold(0x811C_9DC5, |hash, &byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+fn platform_name(platform: Platform) -> &'static str {
+    match platform {
+        Platform::Chip8 => "chip8",
+        Platform::SuperChip => "schip",
+        Platform::XoChip => "xo-chip",
+    }
+}
+
+fn quirk_flags(quirks: &Quirks) -> [bool; 7] {
+    [
+        quirks.shift,
+        quirks.load_store == LoadStore::Unchanged,
+        quirks.jump,
+        quirks.vf_reset,
+        quirks.clipping,
+        quirks.display_wait,
+        quirks.load_store == LoadStore::IncrementByX,
+    ]
+}
+
+/// Value of a `key value` header line
+fn header<'a>(line_number: usize, line: &'a str, key: &str) -> Result<&'a str, MovieError> {
+    match line.split_once(' ') {
+        Some((k, value)) if k == key => Ok(value.trim()),
+        _ if line.trim() == key => Ok(""),
+        _ => Err(parse_error(line_number, format!("expected {}", key))),
+    }
+}
+
+/// `frame key`, or `frame key up` since version 2
+fn parse_event(line: &str) -> Option<MovieEvent> {
+    let mut words = line.split_whitespace();
+    let frame = words.next()?.parse().ok()?;
+    let key = Chip8Key::new(u8::from_str_radix(words.next()?, 16).ok()?)?;
+    let pressed = match (words.next(), words.next()) {
+        (None, _) => true,
+        (Some("up"), None) => false,
+        _ => return None,
+    };
+    Some(MovieEvent {
+        frame,
+        key,
+        pressed,
+    })
+}
+
+fn parse_error(line: usize, message: impl Into<String>) -> MovieError {
+    MovieError::Parse {
+        line,
+        message: message.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::headless::HeadlessDisplay;
+    use crate::savestate::SaveState;
+
+    // loop: LD V1, K ; RND V2, 0xFF ; ADD V0, V2 ; JP loop
+    const ROM: [u8; 8] = [0xF1, 0x0A, 0xC2, 0xFF, 0x80, 0x24, 0x12, 0x00];
+
+    fn run(movie: &Movie, mut live: impl FnMut(u64) -> Option<Chip8Key>) -> (Chip, Movie) {
+        let mut chip = Chip::with_platform(HeadlessDisplay::new(), movie.platform);
+        movie.prepare(&mut chip);
+        chip.load_rom(&ROM).unwrap();
+        let mut recorded = Movie::new(&chip, &ROM, movie.instructions_per_frame);
+
+        for frame in 0..100 {
+            let live = live(frame).map(|key| MovieEvent {
+                frame,
+                key,
+                pressed: true,
+            });
+            for event in movie.events(frame).copied().chain(live) {
+                event.apply(&mut chip);
+                recorded.record(frame, event.key, event.pressed);
+            }
+            chip.run_frame(movie.instructions_per_frame).unwrap();
+        }
+        (chip, recorded)
+    }
+
+    #[test]
+    fn test_replay_reproduces_session() {
+        let mut chip = Chip::new(HeadlessDisplay::new());
+        chip.random = Random::seeded(99);
+        let blank = Movie::new(&chip, &ROM, 4);
+
+        let (original, recorded) = run(&blank, |frame| {
+            (frame % 7 == 3).then(|| Chip8Key::new((frame % 16) as u8).unwrap())
+        });
+
+        let mut file = Vec::new();
+        recorded.write_to(&mut file).unwrap();
+        let loaded = Movie::read_from(file.as_slice()).unwrap();
+        assert_eq!(loaded, recorded);
+        assert!(loaded.matches_rom(&ROM));
+
+        let (replayed, _) = run(&loaded, |_| None);
+        assert!(SaveState::capture(&replayed) == SaveState::capture(&original));
+    }
+
+    #[test]
+    fn test_vip_generator_round_trips() {
+        let mut chip = Chip::new(HeadlessDisplay::new());
+        chip.random = Random::vip(0x1234);
+        let movie = Movie::new(&chip, &ROM, 10);
+
+        let mut file = Vec::new();
+        movie.write_to(&mut file).unwrap();
+        assert!(String::from_utf8_lossy(&file).contains("\nrandom vip 4660\n"));
+        assert_eq!(Movie::read_from(file.as_slice()).unwrap(), movie);
+    }
+
+    #[test]
+    fn test_presses_by_frame() {
+        let chip = Chip::new(HeadlessDisplay::new());
+        let mut movie = Movie::new(&chip, &ROM, 10);
+        let key = |n| Chip8Key::new(n).unwrap();
+        movie.record(2, key(1), true);
+        movie.record(2, key(4), true);
+        movie.record(5, key(1), false);
+
+        assert_eq!(movie.events(1).count(), 0);
+        let keys: Vec<_> = movie.events(2).map(|e| e.key).collect();
+        assert_eq!(keys, [key(1), key(4)]);
+        assert!(!movie.events(5).next().unwrap().pressed);
+        assert!(!movie.finished(5));
+        assert!(movie.finished(6));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = Movie::read_from("chip8-movie 1\nplatform nes\n".as_bytes()).unwrap_err();
+        assert!(matches!(err, MovieError::Parse { line: 2, .. }));
+
+        let text = "chip8-movie 1\nplatform chip8\nquirks load_store load_store_x\n";
+        let err = Movie::read_from(text.as_bytes()).unwrap_err();
+        assert!(matches!(err, MovieError::Parse { line: 3, .. }));
+
+        let header = "chip8-movie 1\nplatform chip8\nquirks\nrandom xorshift 1\nipf 10\nrom 0\n";
+        for (events, line) in [("3 5\n3 5 down\n", 8), ("8 5\n3 5 up\n", 8), ("3 G\n", 7)] {
+            let err = Movie::read_from(format!("{}{}", header, events).as_bytes()).unwrap_err();
+            assert!(
+                matches!(err, MovieError::Parse { line: l, .. } if l == line),
+                "{}",
+                events
+            );
+        }
+
+        let err = Movie::read_from("chip8-movie 9\n".as_bytes()).unwrap_err();
+        assert!(matches!(err, MovieError::UnsupportedVersion(9)));
+    }
+}
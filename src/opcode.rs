@@ -1,52 +1,192 @@
-use crate::{nibble::Nibble, nibbles::Nibbles};
+use std::fmt;
 
-#[derive(Debug)]
+use crate::{nibble::Nibble, nibbles::Nibbles, platform::Platform};
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Opcode {
     CLS,
     RET,
 
-    JP { addr: u16 },
-    CALL { addr: u16 },
-
-    SEByte { x: Nibble, byte: u8 },
-    SNEByte { x: Nibble, byte: u8 },
-    SEReg { x: Nibble, y: Nibble },
-
-    LDByte { x: Nibble, byte: u8 },
-    ADDByte { x: Nibble, byte: u8 },
-
-    LDReg { x: Nibble, y: Nibble },
-    OR { x: Nibble, y: Nibble },
-    AND { x: Nibble, y: Nibble },
-    XOR { x: Nibble, y: Nibble },
-    ADD { x: Nibble, y: Nibble },
-    SUB { x: Nibble, y: Nibble },
-    SHR { x: Nibble },
-    SUBN { x: Nibble, y: Nibble },
-    SHL { x: Nibble },
-
-    SNEReg { x: Nibble, y: Nibble },
-
-    LDI { addr: u16 },
-    JPPlusV0 { addr: u16 },
-    RND { x: Nibble, byte: u8 },
-    DRW { x: Nibble, y: Nibble, n: Nibble },
-
-    SKP { x: Nibble },
-    SKNP { x: Nibble },
-
-    LDxDT { x: Nibble },
-    LDxK { x: Nibble },
-    LDdtX { x: Nibble },
-    LDstX { x: Nibble },
-    ADDI { x: Nibble },
-    LDF { x: Nibble },
-    LDB { x: Nibble },
-    LDIStore { x: Nibble },
-    LDIRead { x: Nibble },
+    JP {
+        addr: u16,
+    },
+    CALL {
+        addr: u16,
+    },
+
+    SEByte {
+        x: Nibble,
+        byte: u8,
+    },
+    SNEByte {
+        x: Nibble,
+        byte: u8,
+    },
+    SEReg {
+        x: Nibble,
+        y: Nibble,
+    },
+
+    LDByte {
+        x: Nibble,
+        byte: u8,
+    },
+    ADDByte {
+        x: Nibble,
+        byte: u8,
+    },
+
+    LDReg {
+        x: Nibble,
+        y: Nibble,
+    },
+    OR {
+        x: Nibble,
+        y: Nibble,
+    },
+    AND {
+        x: Nibble,
+        y: Nibble,
+    },
+    XOR {
+        x: Nibble,
+        y: Nibble,
+    },
+    ADD {
+        x: Nibble,
+        y: Nibble,
+    },
+    SUB {
+        x: Nibble,
+        y: Nibble,
+    },
+    SHR {
+        x: Nibble,
+        y: Nibble,
+    },
+    SUBN {
+        x: Nibble,
+        y: Nibble,
+    },
+    SHL {
+        x: Nibble,
+        y: Nibble,
+    },
+
+    SNEReg {
+        x: Nibble,
+        y: Nibble,
+    },
+
+    LDI {
+        addr: u16,
+    },
+    JPPlusV0 {
+        addr: u16,
+    },
+    RND {
+        x: Nibble,
+        byte: u8,
+    },
+    DRW {
+        x: Nibble,
+        y: Nibble,
+        n: Nibble,
+    },
+
+    SKP {
+        x: Nibble,
+    },
+    SKNP {
+        x: Nibble,
+    },
+
+    LDxDT {
+        x: Nibble,
+    },
+    LDxK {
+        x: Nibble,
+    },
+    LDdtX {
+        x: Nibble,
+    },
+    LDstX {
+        x: Nibble,
+    },
+    ADDI {
+        x: Nibble,
+    },
+    LDF {
+        x: Nibble,
+    },
+    LDB {
+        x: Nibble,
+    },
+    LDIStore {
+        x: Nibble,
+    },
+    LDIRead {
+        x: Nibble,
+    },
+
+    // SUPER-CHIP 1.1
+    SCD {
+        n: Nibble,
+    },
+    SCR,
+    SCL,
+    EXIT,
+    LOW,
+    HIGH,
+    LDHF {
+        x: Nibble,
+    },
+    LDRStore {
+        x: Nibble,
+    },
+    LDRRead {
+        x: Nibble,
+    },
+
+    // XO-CHIP
+    SCU {
+        n: Nibble,
+    },
+    LDRangeStore {
+        x: Nibble,
+        y: Nibble,
+    },
+    LDRangeRead {
+        x: Nibble,
+        y: Nibble,
+    },
+    /// F000 NNNN, the address is the word following the instruction
+    LDILong {
+        addr: u16,
+    },
+    PLANE {
+        n: Nibble,
+    },
+    AUDIO,
+    PITCH {
+        x: Nibble,
+    },
+
+    /// 0NNN, calls a machine code routine on the original hardware
+    Sys {
+        addr: u16,
+    },
+    /// Any word that is not an instruction
+    Unknown(u16),
 }
 
 impl Opcode {
+    /// Decode one instruction word.
+    ///
+    /// Every word decodes to something, words that are no instruction to
+    /// [`Opcode::Unknown`]. `F000` decodes to [`Opcode::LDILong`] with a zero
+    /// address, the caller fills it in from the following word.
     pub fn decode(raw: u16) -> Self {
         let n = Nibbles::from_u16(raw);
 
@@ -58,6 +198,18 @@ impl Opcode {
         ) {
             (0x0, 0x0, 0xE, 0x0) => Opcode::CLS,
             (0x0, 0x0, 0xE, 0xE) => Opcode::RET,
+            (0x0, 0x0, 0xC, n) => Opcode::SCD {
+                n: Nibble::from_low(n),
+            },
+            (0x0, 0x0, 0xD, n) => Opcode::SCU {
+                n: Nibble::from_low(n),
+            },
+            (0x0, 0x0, 0xF, 0xB) => Opcode::SCR,
+            (0x0, 0x0, 0xF, 0xC) => Opcode::SCL,
+            (0x0, 0x0, 0xF, 0xD) => Opcode::EXIT,
+            (0x0, 0x0, 0xF, 0xE) => Opcode::LOW,
+            (0x0, 0x0, 0xF, 0xF) => Opcode::HIGH,
+            (0x0, _, _, _) => Opcode::Sys { addr: raw & 0x0FFF },
 
             (0x1, _, _, _) => Opcode::JP { addr: raw & 0x0FFF },
             (0x2, _, _, _) => Opcode::CALL { addr: raw & 0x0FFF },
@@ -74,6 +226,14 @@ impl Opcode {
                 x: Nibble::from_low(x),
                 y: Nibble::from_low(y),
             },
+            (0x5, x, y, 0x2) => Opcode::LDRangeStore {
+                x: Nibble::from_low(x),
+                y: Nibble::from_low(y),
+            },
+            (0x5, x, y, 0x3) => Opcode::LDRangeRead {
+                x: Nibble::from_low(x),
+                y: Nibble::from_low(y),
+            },
 
             (0x6, x, _, _) => Opcode::LDByte {
                 x: Nibble::from_low(x),
@@ -108,15 +268,17 @@ impl Opcode {
                 x: Nibble::from_low(x),
                 y: Nibble::from_low(y),
             },
-            (0x8, x, _, 0x6) => Opcode::SHR {
+            (0x8, x, y, 0x6) => Opcode::SHR {
                 x: Nibble::from_low(x),
+                y: Nibble::from_low(y),
             },
             (0x8, x, y, 0x7) => Opcode::SUBN {
                 x: Nibble::from_low(x),
                 y: Nibble::from_low(y),
             },
-            (0x8, x, _, 0xE) => Opcode::SHL {
+            (0x8, x, y, 0xE) => Opcode::SHL {
                 x: Nibble::from_low(x),
+                y: Nibble::from_low(y),
             },
 
             (0x9, x, y, 0x0) => Opcode::SNEReg {
@@ -143,6 +305,11 @@ impl Opcode {
                 x: Nibble::from_low(x),
             },
 
+            (0xF, 0x0, 0x0, 0x0) => Opcode::LDILong { addr: 0 },
+            (0xF, n, 0x0, 0x1) => Opcode::PLANE {
+                n: Nibble::from_low(n),
+            },
+            (0xF, 0x0, 0x0, 0x2) => Opcode::AUDIO,
             (0xF, x, 0x0, 0x7) => Opcode::LDxDT {
                 x: Nibble::from_low(x),
             },
@@ -161,6 +328,12 @@ impl Opcode {
             (0xF, x, 0x2, 0x9) => Opcode::LDF {
                 x: Nibble::from_low(x),
             },
+            (0xF, x, 0x3, 0x0) => Opcode::LDHF {
+                x: Nibble::from_low(x),
+            },
+            (0xF, x, 0x3, 0xA) => Opcode::PITCH {
+                x: Nibble::from_low(x),
+            },
             (0xF, x, 0x3, 0x3) => Opcode::LDB {
                 x: Nibble::from_low(x),
             },
@@ -170,8 +343,209 @@ impl Opcode {
             (0xF, x, 0x6, 0x5) => Opcode::LDIRead {
                 x: Nibble::from_low(x),
             },
+            (0xF, x, 0x7, 0x5) => Opcode::LDRStore {
+                x: Nibble::from_low(x),
+            },
+            (0xF, x, 0x8, 0x5) => Opcode::LDRRead {
+                x: Nibble::from_low(x),
+            },
+
+            _ => Opcode::Unknown(raw),
+        }
+    }
+
+    /// Instruction word, the inverse of [`Opcode::decode`].
+    ///
+    /// For [`Opcode::LDILong`] this is `F000`, the address is the word
+    /// following it.
+    pub fn encode(&self) -> u16 {
+        let x_ = |x: &Nibble| (x.as_u8() as u16) << 8;
+        let xy = |op: u16, x: &Nibble, y: &Nibble, n: u16| {
+            op << 12 | x_(x) | (y.as_u8() as u16) << 4 | n
+        };
+        let xb = |op: u16, x: &Nibble, byte: &u8| op << 12 | x_(x) | *byte as u16;
+        // Addresses beyond 12 bits would spill into the instruction nibble
+        let nnn = |op: u16, addr: &u16| op << 12 | addr & 0x0FFF;
 
-            _ => panic!("Invalid opcode {:04X}", raw),
+        match self {
+            Opcode::CLS => 0x00E0,
+            Opcode::RET => 0x00EE,
+            Opcode::SCD { n } => 0x00C0 | n.as_u8() as u16,
+            Opcode::SCU { n } => 0x00D0 | n.as_u8() as u16,
+            Opcode::SCR => 0x00FB,
+            Opcode::SCL => 0x00FC,
+            Opcode::EXIT => 0x00FD,
+            Opcode::LOW => 0x00FE,
+            Opcode::HIGH => 0x00FF,
+            Opcode::Sys { addr } => nnn(0x0, addr),
+            Opcode::JP { addr } => nnn(0x1, addr),
+            Opcode::CALL { addr } => nnn(0x2, addr),
+            Opcode::SEByte { x, byte } => xb(0x3, x, byte),
+            Opcode::SNEByte { x, byte } => xb(0x4, x, byte),
+            Opcode::SEReg { x, y } => xy(0x5, x, y, 0x0),
+            Opcode::LDRangeStore { x, y } => xy(0x5, x, y, 0x2),
+            Opcode::LDRangeRead { x, y } => xy(0x5, x, y, 0x3),
+            Opcode::LDByte { x, byte } => xb(0x6, x, byte),
+            Opcode::ADDByte { x, byte } => xb(0x7, x, byte),
+            Opcode::LDReg { x, y } => xy(0x8, x, y, 0x0),
+            Opcode::OR { x, y } => xy(0x8, x, y, 0x1),
+            Opcode::AND { x, y } => xy(0x8, x, y, 0x2),
+            Opcode::XOR { x, y } => xy(0x8, x, y, 0x3),
+            Opcode::ADD { x, y } => xy(0x8, x, y, 0x4),
+            Opcode::SUB { x, y } => xy(0x8, x, y, 0x5),
+            Opcode::SHR { x, y } => xy(0x8, x, y, 0x6),
+            Opcode::SUBN { x, y } => xy(0x8, x, y, 0x7),
+            Opcode::SHL { x, y } => xy(0x8, x, y, 0xE),
+            Opcode::SNEReg { x, y } => xy(0x9, x, y, 0x0),
+            Opcode::LDI { addr } => nnn(0xA, addr),
+            Opcode::JPPlusV0 { addr } => nnn(0xB, addr),
+            Opcode::RND { x, byte } => xb(0xC, x, byte),
+            Opcode::DRW { x, y, n } => xy(0xD, x, y, n.as_u8() as u16),
+            Opcode::SKP { x } => 0xE09E | x_(x),
+            Opcode::SKNP { x } => 0xE0A1 | x_(x),
+            Opcode::LDILong { .. } => 0xF000,
+            Opcode::PLANE { n } => 0xF001 | x_(n),
+            Opcode::AUDIO => 0xF002,
+            Opcode::LDxDT { x } => 0xF007 | x_(x),
+            Opcode::LDxK { x } => 0xF00A | x_(x),
+            Opcode::LDdtX { x } => 0xF015 | x_(x),
+            Opcode::LDstX { x } => 0xF018 | x_(x),
+            Opcode::ADDI { x } => 0xF01E | x_(x),
+            Opcode::LDF { x } => 0xF029 | x_(x),
+            Opcode::LDHF { x } => 0xF030 | x_(x),
+            Opcode::PITCH { x } => 0xF03A | x_(x),
+            Opcode::LDB { x } => 0xF033 | x_(x),
+            Opcode::LDIStore { x } => 0xF055 | x_(x),
+            Opcode::LDIRead { x } => 0xF065 | x_(x),
+            Opcode::LDRStore { x } => 0xF075 | x_(x),
+            Opcode::LDRRead { x } => 0xF085 | x_(x),
+            Opcode::Unknown(raw) => *raw,
         }
     }
+
+    /// First platform that implements the instruction
+    pub fn platform(&self) -> Platform {
+        match self {
+            Opcode::SCD { .. }
+            | Opcode::SCR
+            | Opcode::SCL
+            | Opcode::EXIT
+            | Opcode::LOW
+            | Opcode::HIGH
+            | Opcode::LDHF { .. }
+            | Opcode::LDRStore { .. }
+            | Opcode::LDRRead { .. } => Platform::SuperChip,
+
+            Opcode::SCU { .. }
+            | Opcode::LDRangeStore { .. }
+            | Opcode::LDRangeRead { .. }
+            | Opcode::LDILong { .. }
+            | Opcode::PLANE { .. }
+            | Opcode::AUDIO
+            | Opcode::PITCH { .. } => Platform::XoChip,
+
+            _ => Platform::Chip8,
+        }
+    }
+
+    /// Size of the instruction in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            Opcode::LDILong { .. } => 4,
+            _ => 2,
+        }
+    }
+}
+
+/// Conventional mnemonics, `LD V3, 0x05`, as used by most CHIP 8 assemblers
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let v = |n: &Nibble| format!("V{:X}", n.as_u8());
+        match self {
+            Opcode::CLS => write!(f, "CLS"),
+            Opcode::RET => write!(f, "RET"),
+            Opcode::JP { addr } => write!(f, "JP {:#05X}", addr),
+            Opcode::CALL { addr } => write!(f, "CALL {:#05X}", addr),
+            Opcode::SEByte { x, byte } => write!(f, "SE {}, {:#04X}", v(x), byte),
+            Opcode::SNEByte { x, byte } => write!(f, "SNE {}, {:#04X}", v(x), byte),
+            Opcode::SEReg { x, y } => write!(f, "SE {}, {}", v(x), v(y)),
+            Opcode::LDByte { x, byte } => write!(f, "LD {}, {:#04X}", v(x), byte),
+            Opcode::ADDByte { x, byte } => write!(f, "ADD {}, {:#04X}", v(x), byte),
+            Opcode::LDReg { x, y } => write!(f, "LD {}, {}", v(x), v(y)),
+            Opcode::OR { x, y } => write!(f, "OR {}, {}", v(x), v(y)),
+            Opcode::AND { x, y } => write!(f, "AND {}, {}", v(x), v(y)),
+            Opcode::XOR { x, y } => write!(f, "XOR {}, {}", v(x), v(y)),
+            Opcode::ADD { x, y } => write!(f, "ADD {}, {}", v(x), v(y)),
+            Opcode::SUB { x, y } => write!(f, "SUB {}, {}", v(x), v(y)),
+            Opcode::SHR { x, y } => write!(f, "SHR {}, {}", v(x), v(y)),
+            Opcode::SUBN { x, y } => write!(f, "SUBN {}, {}", v(x), v(y)),
+            Opcode::SHL { x, y } => write!(f, "SHL {}, {}", v(x), v(y)),
+            Opcode::SNEReg { x, y } => write!(f, "SNE {}, {}", v(x), v(y)),
+            Opcode::LDI { addr } => write!(f, "LD I, {:#05X}", addr),
+            Opcode::JPPlusV0 { addr } => write!(f, "JP V0, {:#05X}", addr),
+            Opcode::RND { x, byte } => write!(f, "RND {}, {:#04X}", v(x), byte),
+            Opcode::DRW { x, y, n } => write!(f, "DRW {}, {}, {}", v(x), v(y), n.as_u8()),
+            Opcode::SKP { x } => write!(f, "SKP {}", v(x)),
+            Opcode::SKNP { x } => write!(f, "SKNP {}", v(x)),
+            Opcode::LDxDT { x } => write!(f, "LD {}, DT", v(x)),
+            Opcode::LDxK { x } => write!(f, "LD {}, K", v(x)),
+            Opcode::LDdtX { x } => write!(f, "LD DT, {}", v(x)),
+            Opcode::LDstX { x } => write!(f, "LD ST, {}", v(x)),
+            Opcode::ADDI { x } => write!(f, "ADD I, {}", v(x)),
+            Opcode::LDF { x } => write!(f, "LD F, {}", v(x)),
+            Opcode::LDB { x } => write!(f, "LD B, {}", v(x)),
+            Opcode::LDIStore { x } => write!(f, "LD [I], {}", v(x)),
+            Opcode::LDIRead { x } => write!(f, "LD {}, [I]", v(x)),
+            Opcode::SCD { n } => write!(f, "SCD {}", n.as_u8()),
+            Opcode::SCR => write!(f, "SCR"),
+            Opcode::SCL => write!(f, "SCL"),
+            Opcode::EXIT => write!(f, "EXIT"),
+            Opcode::LOW => write!(f, "LOW"),
+            Opcode::HIGH => write!(f, "HIGH"),
+            Opcode::LDHF { x } => write!(f, "LD HF, {}", v(x)),
+            Opcode::LDRStore { x } => write!(f, "LD R, {}", v(x)),
+            Opcode::LDRRead { x } => write!(f, "LD {}, R", v(x)),
+            Opcode::SCU { n } => write!(f, "SCU {}", n.as_u8()),
+            Opcode::LDRangeStore { x, y } => write!(f, "LD [I], {}-{}", v(x), v(y)),
+            Opcode::LDRangeRead { x, y } => write!(f, "LD {}-{}, [I]", v(x), v(y)),
+            Opcode::LDILong { addr } => write!(f, "LD I, LONG {:#06X}", addr),
+            Opcode::PLANE { n } => write!(f, "PLANE {}", n.as_u8()),
+            Opcode::AUDIO => write!(f, "AUDIO"),
+            Opcode::PITCH { x } => write!(f, "PITCH {}", v(x)),
+            Opcode::Sys { addr } => write!(f, "SYS {:#05X}", addr),
+            // Assembles back to the same word
+            Opcode::Unknown(raw) => write!(f, "DW {:#06X}", raw),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_word_round_trips() {
+        for raw in 0..=u16::MAX {
+            let opcode = Opcode::decode(raw);
+            assert_eq!(opcode.encode(), raw, "{:04X} decoded to {:?}", raw, opcode);
+            assert_eq!(Opcode::decode(opcode.encode()), opcode);
+        }
+    }
+
+    #[test]
+    fn test_unknown_and_sys() {
+        assert_eq!(Opcode::decode(0x0123), Opcode::Sys { addr: 0x123 });
+        assert_eq!(Opcode::decode(0x5AB1), Opcode::Unknown(0x5AB1));
+        assert_eq!(Opcode::decode(0xFFFF), Opcode::Unknown(0xFFFF));
+        assert_eq!(Opcode::Unknown(0x5AB1).to_string(), "DW 0x5AB1");
+    }
+
+    #[test]
+    fn test_addresses_keep_the_instruction_nibble() {
+        assert_eq!(Opcode::Sys { addr: 0x2345 }.encode(), 0x0345);
+        assert_eq!(Opcode::JP { addr: 0x2345 }.encode(), 0x1345);
+        assert_eq!(Opcode::CALL { addr: 0xF345 }.encode(), 0x2345);
+        assert_eq!(Opcode::LDI { addr: 0x1FFF }.encode(), 0xAFFF);
+        assert_eq!(Opcode::JPPlusV0 { addr: 0x1000 }.encode(), 0xB000);
+    }
 }
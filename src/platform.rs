@@ -0,0 +1,31 @@
+use crate::quirks::Quirks;
+use crate::{MEMORY_SIZE, XO_CHIP_MEMORY_SIZE};
+
+/// Instruction set the chip implements.
+///
+/// Platforms are ordered, each one is a superset of the previous.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Platform {
+    #[default]
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    pub fn memory_size(self) -> usize {
+        match self {
+            Platform::Chip8 | Platform::SuperChip => MEMORY_SIZE,
+            Platform::XoChip => XO_CHIP_MEMORY_SIZE,
+        }
+    }
+
+    /// Quirks used unless the user picks others
+    pub fn default_quirks(self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::default(),
+            Platform::SuperChip => Quirks::SUPER_CHIP,
+            Platform::XoChip => Quirks::XO_CHIP,
+        }
+    }
+}
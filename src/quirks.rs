@@ -0,0 +1,85 @@
+/// Behaviour of the instructions that differ between CHIP 8 interpreters.
+///
+/// `Quirks::default()` keeps the behaviour this emulator always had, the
+/// named presets match the original platforms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VX in place instead of shifting VY into VX
+    pub shift: bool,
+    /// Where FX55/FX65 leave I
+    pub load_store: LoadStore,
+    /// BNNN jumps to NNN + VX (X being the highest nibble of NNN) instead of NNN + V0
+    pub jump: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to 0
+    pub vf_reset: bool,
+    /// Sprites are clipped at the screen edges instead of wrapping around
+    pub clipping: bool,
+    /// DXYN ends the frame, limiting drawing to one sprite per 60 Hz frame
+    pub display_wait: bool,
+}
+
+/// Where FX55/FX65 leave I after storing or loading V0 to VX
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadStore {
+    /// Past the last register, I + X + 1
+    Increment,
+    /// On the last register, I + X, as CHIP-48 does
+    IncrementByX,
+    /// Where it was
+    Unchanged,
+}
+
+impl Quirks {
+    /// The original COSMAC VIP interpreter
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift: false,
+        load_store: LoadStore::Increment,
+        jump: false,
+        vf_reset: true,
+        clipping: true,
+        display_wait: true,
+    };
+
+    /// CHIP-48 on the HP-48 calculators
+    pub const CHIP_48: Quirks = Quirks {
+        shift: true,
+        load_store: LoadStore::IncrementByX,
+        jump: true,
+        vf_reset: false,
+        clipping: true,
+        display_wait: false,
+    };
+
+    /// SUPER-CHIP 1.1
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift: true,
+        load_store: LoadStore::Unchanged,
+        jump: true,
+        vf_reset: false,
+        clipping: true,
+        display_wait: false,
+    };
+
+    /// XO-CHIP as implemented by Octo
+    pub const XO_CHIP: Quirks = Quirks {
+        shift: false,
+        load_store: LoadStore::Increment,
+        jump: false,
+        vf_reset: false,
+        clipping: false,
+        display_wait: false,
+    };
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            shift: true,
+            load_store: LoadStore::Unchanged,
+            jump: false,
+            vf_reset: false,
+            clipping: false,
+            display_wait: false,
+        }
+    }
+}
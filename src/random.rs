@@ -0,0 +1,126 @@
+//! Random numbers for CXNN.
+//!
+//! The generator lives on the [`Chip`](crate::chip::Chip) and is part of
+//! its save state, so a run started from the same seed, or resumed from the
+//! same state, with the same input produces the same numbers.
+
+/// Page `0x100`–`0x1FF` of the COSMAC VIP CHIP-8 interpreter, which its
+/// CXNN routine mixes into the generator
+#[rustfmt::skip]
+const VIP_PAGE: [u8; 256] = [
+    0x9B, 0xBF, 0xF8, 0xFF, 0xAF, 0x93, 0x5F, 0x8F, 0x32, 0xDF, 0x2F, 0x30, 0x05, 0x42, 0xB5, 0x42,
+    0xA5, 0xD4, 0x8D, 0xA7, 0x87, 0x32, 0xAC, 0x2A, 0x27, 0x30, 0x12, 0xD4, 0x8D, 0xBC, 0x07, 0x30,
+    0xA5, 0xF8, 0x6F, 0xAC, 0xE2, 0x8D, 0x52, 0xF8, 0x80, 0xF2, 0xFE, 0xFE, 0xFE, 0xFE, 0xAD, 0xF8,
+    0x07, 0x22, 0x52, 0x8D, 0xF4, 0x73, 0x02, 0x5C, 0xD4, 0x45, 0x07, 0x30, 0x88, 0xD4, 0x45, 0x07,
+    0x3A, 0x88, 0xD4, 0x45, 0x07, 0x52, 0x45, 0xF3, 0x3A, 0x88, 0x15, 0x15, 0xD4, 0x45, 0x56, 0xD4,
+    0x45, 0xE6, 0xF4, 0x56, 0xD4, 0x45, 0xFA, 0x0F, 0x3A, 0x69, 0x07, 0x56, 0xD4, 0xAF, 0x22, 0xF8,
+    0xD3, 0x73, 0x8F, 0xF9, 0xF0, 0x52, 0xE6, 0x07, 0xD2, 0x56, 0xF8, 0xFF, 0xA6, 0xF8, 0x00, 0x7E,
+    0x56, 0xD4, 0x19, 0x89, 0xAE, 0x93, 0xBE, 0x99, 0xEE, 0xF4, 0x56, 0x76, 0xE6, 0xF4, 0xB9, 0x56,
+    0x45, 0xF2, 0x56, 0xD4, 0x45, 0xAA, 0x86, 0xFA, 0x0F, 0xBA, 0xD4, 0xF8, 0x81, 0xBC, 0xF8, 0x95,
+    0xAC, 0x22, 0xDC, 0x12, 0x56, 0xD4, 0x06, 0xB8, 0xD4, 0x06, 0xA8, 0xD4, 0x64, 0x0A, 0x01, 0xE6,
+    0x8A, 0xF4, 0xAA, 0x3B, 0x28, 0x9A, 0xFC, 0x01, 0xBA, 0xD4, 0xF8, 0x81, 0xBA, 0x06, 0xFA, 0x0F,
+    0xAA, 0x0A, 0xAA, 0xD4, 0xE6, 0x06, 0xBF, 0x93, 0xBE, 0xF8, 0x1B, 0xAE, 0x2A, 0x1A, 0xF8, 0x00,
+    0x5A, 0x0E, 0xF5, 0x3B, 0x4B, 0x56, 0x0A, 0xFC, 0x01, 0x5A, 0x30, 0x40, 0x4E, 0xF6, 0x3B, 0x3C,
+    0x9F, 0x56, 0x2A, 0x2A, 0xD4, 0x00, 0x22, 0x86, 0x52, 0xF8, 0xF0, 0xA7, 0x07, 0x5A, 0x87, 0xF3,
+    0x17, 0x1A, 0x3A, 0x5B, 0x12, 0xD4, 0x22, 0x86, 0x52, 0xF8, 0xF0, 0xA7, 0x0A, 0x57, 0x87, 0xF3,
+    0x17, 0x1A, 0x3A, 0x6B, 0x12, 0xD4, 0x15, 0x85, 0x22, 0x73, 0x95, 0x52, 0x25, 0x45, 0xA5, 0x86,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Random {
+    /// xorshift64* holding its 64-bit state
+    Xorshift(u64),
+    /// The COSMAC VIP interpreter's CXNN routine, holding its R9 register.
+    ///
+    /// Each call increments R9, then adds the byte of the interpreter at
+    /// `0x100 + R9.0` to R9.1 and returns it.
+    Vip(u16),
+}
+
+impl Random {
+    /// xorshift64* generator started from `seed`
+    pub fn seeded(seed: u64) -> Self {
+        // Run the seed through SplitMix64 so small or zero seeds still
+        // give a well mixed, non-zero state.
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Random::Xorshift(z.max(1))
+    }
+
+    /// xorshift64* generator with a random seed
+    pub fn from_entropy() -> Self {
+        Self::seeded(rand::random())
+    }
+
+    /// COSMAC VIP generator with R9 set from `seed`
+    pub fn vip(seed: u64) -> Self {
+        Random::Vip(seed as u16)
+    }
+
+    /// Next random byte, before the CXNN mask
+    pub fn next_byte(&mut self) -> u8 {
+        match self {
+            Random::Xorshift(state) => {
+                let mut x = *state;
+                x ^= x >> 12;
+                x ^= x << 25;
+                x ^= x >> 27;
+                *state = x;
+                (x.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+            }
+            Random::Vip(r9) => {
+                let counter = r9.wrapping_add(1);
+                let table = VIP_PAGE[(counter & 0xFF) as usize];
+                let high = ((counter >> 8) as u8).wrapping_add(table);
+                *r9 = (high as u16) << 8 | (counter & 0xFF);
+                high
+            }
+        }
+    }
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Self::from_entropy()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_sequence() {
+        let mut a = Random::seeded(42);
+        let mut b = Random::seeded(42);
+        let mut c = Random::seeded(43);
+
+        let seq_a: Vec<u8> = (0..32).map(|_| a.next_byte()).collect();
+        let seq_b: Vec<u8> = (0..32).map(|_| b.next_byte()).collect();
+        let seq_c: Vec<u8> = (0..32).map(|_| c.next_byte()).collect();
+        assert_eq!(seq_a, seq_b);
+        assert_ne!(seq_a, seq_c);
+    }
+
+    #[test]
+    fn test_vip_adds_interpreter_byte_to_high_byte() {
+        let mut random = Random::vip(0x2000);
+
+        let first = 0x20u8.wrapping_add(VIP_PAGE[0x01]);
+        assert_eq!(random.next_byte(), first);
+        let second = first.wrapping_add(VIP_PAGE[0x02]);
+        assert_eq!(random.next_byte(), second);
+        assert_eq!(random, Random::Vip(u16::from(second) << 8 | 0x02));
+    }
+
+    #[test]
+    fn test_vip_increment_carries_into_high_byte() {
+        // R9 is incremented as a whole, the table index wraps to 0
+        let mut random = Random::vip(0x00FF);
+        let high = 0x01u8.wrapping_add(VIP_PAGE[0x00]);
+        assert_eq!(random.next_byte(), high);
+        assert_eq!(random, Random::Vip(u16::from(high) << 8));
+    }
+}
@@ -1,13 +1,18 @@
-use crate::*;
+use crate::nibble::Nibble;
 
 pub struct Registers {
-    v: [u8; 16], // V0–VF
-    i: u16,      // Index register
+    v: [u8; 16],     // V0–VF
+    i: u16,          // Index register
+    flags: [u8; 16], // SUPER-CHIP RPL user flags
 }
 
 impl Registers {
     pub fn new() -> Self {
-        Registers { v: [0; 16], i: 0 }
+        Registers {
+            v: [0; 16],
+            i: 0,
+            flags: [0; 16],
+        }
     }
 
     /// Get Vx
@@ -27,6 +32,35 @@ impl Registers {
         self.v[0xF] = carry as u8;
     }
 
+    /// Copy of V0–VF
+    pub fn all(&self) -> [u8; 16] {
+        self.v
+    }
+
+    /// Save V0–Vx to the RPL user flags (FX75)
+    pub fn store_flags(&mut self, x: Nibble) {
+        let n = x.as_usize() + 1;
+        self.flags[..n].copy_from_slice(&self.v[..n]);
+    }
+
+    /// Restore V0–Vx from the RPL user flags (FX85)
+    pub fn load_flags(&mut self, x: Nibble) {
+        let n = x.as_usize() + 1;
+        self.v[..n].copy_from_slice(&self.flags[..n]);
+    }
+
+    /// Copy of the RPL user flags
+    pub fn flags(&self) -> [u8; 16] {
+        self.flags
+    }
+
+    /// Overwrite V0–VF, I and the RPL user flags, e.g. from a save state
+    pub fn restore(&mut self, v: [u8; 16], i: u16, flags: [u8; 16]) {
+        self.v = v;
+        self.i = i;
+        self.flags = flags;
+    }
+
     /// Set VF (carry flag)
     pub fn set_carry(&mut self, value: u8) {
         self.v[0xF] = value;
@@ -46,7 +80,7 @@ impl Registers {
     }
 
     pub fn get_vx(&self, instruction: u16) -> u8 {
-        self.v[x(instruction) as usize]
+        self.v[Nibble::from_opcode(instruction, 8).as_usize()]
     }
 
     pub fn get_vy(&self, instruction: u16) -> u8 {
@@ -54,14 +88,20 @@ impl Registers {
     }
 
     pub fn set_vx(&mut self, instruction: u16, value: u8) {
-        let idx = x(instruction) as usize;
+        let idx = Nibble::from_opcode(instruction, 8).as_usize();
         self.v[idx] = value;
     }
 
     pub fn add_vx(&mut self, instruction: u16, value: u8) {
-        let idx = x(instruction) as usize;
+        let idx = Nibble::from_opcode(instruction, 8).as_usize();
         let (res, carry) = self.v[idx].overflowing_add(value);
         self.v[idx] = res;
         self.v[0xF] = carry as u8;
     }
 }
+
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
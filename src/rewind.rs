@@ -0,0 +1,197 @@
+//! History of past machine states for stepping time backwards.
+//!
+//! Snapshots are [`SaveState`] bytes. Only the newest one is kept whole,
+//! every older one is stored as the bytes that differ from the snapshot
+//! after it, which for a running game is a handful of registers, a few
+//! bytes of RAM and whatever changed on screen.
+
+use std::collections::VecDeque;
+
+use crate::chip::Chip;
+use crate::savestate::SaveState;
+
+/// Default number of snapshots kept
+pub const DEFAULT_CAPACITY: usize = 600;
+
+/// Default number of frames between snapshots
+pub const DEFAULT_INTERVAL: u32 = 2;
+
+/// Changes turning a snapshot back into the one taken before it
+struct Delta {
+    len: usize,
+    runs: Vec<(usize, Vec<u8>)>,
+}
+
+impl Delta {
+    /// Delta that rebuilds `older` from `newer`
+    fn between(older: &[u8], newer: &[u8]) -> Self {
+        let mut runs: Vec<(usize, Vec<u8>)> = Vec::new();
+        let mut end = None;
+        for (i, &byte) in older.iter().enumerate() {
+            if newer.get(i) == Some(&byte) {
+                continue;
+            }
+            match runs.last_mut() {
+                Some((_, bytes)) if end == Some(i) => bytes.push(byte),
+                _ => runs.push((i, vec![byte])),
+            }
+            end = Some(i + 1);
+        }
+        Delta {
+            len: older.len(),
+            runs,
+        }
+    }
+
+    fn apply(&self, newer: &[u8]) -> Vec<u8> {
+        let mut older = newer.to_vec();
+        older.resize(self.len, 0);
+        for (offset, bytes) in &self.runs {
+            older[*offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+        older
+    }
+}
+
+/// Bounded ring buffer of snapshots taken every few frames.
+pub struct Rewind {
+    capacity: usize,
+    interval: u32,
+    frames: u32,
+    latest: Option<Vec<u8>>,
+    history: VecDeque<Delta>,
+}
+
+impl Rewind {
+    /// Keep up to `capacity` snapshots, one every `interval` frames
+    pub fn new(capacity: usize, interval: u32) -> Self {
+        Rewind {
+            capacity,
+            interval: interval.max(1),
+            frames: 0,
+            latest: None,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Number of snapshots held
+    pub fn len(&self) -> usize {
+        self.latest.as_ref().map_or(0, |_| self.history.len() + 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.history.clear();
+        self.frames = 0;
+    }
+
+    /// Call once per emulated frame, takes a snapshot every `interval` frames
+    pub fn record(&mut self, chip: &Chip) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.frames += 1;
+        if self.frames < self.interval {
+            return;
+        }
+        self.frames = 0;
+        self.push(SaveState::capture(chip).to_bytes());
+    }
+
+    fn push(&mut self, snapshot: Vec<u8>) {
+        if let Some(previous) = self.latest.take() {
+            self.history
+                .push_front(Delta::between(&previous, &snapshot));
+        }
+        self.latest = Some(snapshot);
+        // The newest snapshot counts towards the capacity as well.
+        self.history.truncate(self.capacity - 1);
+    }
+
+    /// Restore the newest snapshot and drop it, returning false when the
+    /// history is exhausted.
+    pub fn rewind(&mut self, chip: &mut Chip) -> bool {
+        let Some(latest) = self.latest.take() else {
+            return false;
+        };
+        SaveState::from_bytes(&latest)
+            .expect("snapshots are written by SaveState")
+            .restore(chip);
+        self.latest = self.history.pop_front().map(|delta| delta.apply(&latest));
+        self.frames = 0;
+        true
+    }
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY, DEFAULT_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::headless::HeadlessDisplay;
+
+    fn counting_chip() -> Chip {
+        // loop: ADD V0, 1 ; JP loop
+        let mut chip = Chip::new(HeadlessDisplay::new());
+        chip.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        chip
+    }
+
+    #[test]
+    fn test_delta_round_trip() {
+        let older = [1, 2, 3, 4, 5, 6];
+        let newer = [1, 9, 9, 4, 5, 7, 8];
+
+        let delta = Delta::between(&older, &newer);
+        assert_eq!(delta.runs, vec![(1, vec![2, 3]), (5, vec![6])]);
+        assert_eq!(delta.apply(&newer), older);
+    }
+
+    #[test]
+    fn test_rewind_steps_back_through_snapshots() {
+        let mut chip = counting_chip();
+        let mut rewind = Rewind::new(10, 1);
+
+        for _ in 0..3 {
+            chip.step().unwrap();
+            chip.step().unwrap();
+            rewind.record(&chip);
+        }
+        assert_eq!(rewind.len(), 3);
+
+        chip.step().unwrap();
+        chip.step().unwrap();
+        assert_eq!(chip.registers.all()[0], 4);
+
+        for expected in [3, 2, 1] {
+            assert!(rewind.rewind(&mut chip));
+            assert_eq!(chip.registers.all()[0], expected);
+        }
+        assert!(!rewind.rewind(&mut chip));
+        assert_eq!(chip.registers.all()[0], 1);
+    }
+
+    #[test]
+    fn test_capacity_and_interval() {
+        let mut chip = counting_chip();
+        let mut rewind = Rewind::new(4, 3);
+
+        for _ in 0..30 {
+            chip.run_frame(2).unwrap();
+            rewind.record(&chip);
+        }
+        assert_eq!(rewind.len(), 4);
+
+        // The oldest snapshot kept is from frame 21
+        while rewind.rewind(&mut chip) {}
+        assert_eq!(chip.registers.all()[0], 21);
+    }
+}
@@ -0,0 +1,449 @@
+//! Snapshots of the complete machine and their on-disk format.
+//!
+//! A save state file is a four byte magic, a little-endian `u16` format
+//! version and the machine fields in a fixed order, see
+//! [`SaveState::to_bytes`]. Loading rejects files from other versions.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::audio::registers::AudioRegisters;
+use crate::chip::Chip;
+use crate::controls::Chip8Key;
+use crate::display::framebuffer::Framebuffer;
+use crate::memory::Memory;
+use crate::platform::Platform;
+use crate::quirks::{LoadStore, Quirks};
+use crate::random::Random;
+use crate::stack::Stack;
+use crate::{HIRES_DISPLAY_HEIGHT, HIRES_DISPLAY_WIDTH};
+
+const MAGIC: &[u8; 4] = b"C8ST";
+pub const VERSION: u16 = 1;
+
+/// Marks an empty `Option<u8>` in the file
+const NONE: u8 = 0xFF;
+
+#[derive(Debug)]
+pub enum SaveStateError {
+    Io(io::Error),
+    /// Not a save state file
+    BadMagic,
+    /// Written by another version of the emulator
+    UnsupportedVersion(u16),
+    /// The file ends before all fields were read
+    Truncated,
+    /// A field holds a value the machine cannot have
+    Invalid(&'static str),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveStateError::Io(err) => write!(f, "{}", err),
+            SaveStateError::BadMagic => write!(f, "not a save state file"),
+            SaveStateError::UnsupportedVersion(v) => {
+                write!(f, "save state version {} is not supported", v)
+            }
+            SaveStateError::Truncated => write!(f, "save state is truncated"),
+            SaveStateError::Invalid(field) => write!(f, "save state has an invalid {}", field),
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+impl From<io::Error> for SaveStateError {
+    fn from(err: io::Error) -> Self {
+        SaveStateError::Io(err)
+    }
+}
+
+/// Everything needed to resume a [`Chip`] exactly where it was.
+///
+/// Key bindings and the display implementation are not part of the
+/// machine and are left alone when restoring. States only come from
+/// [`SaveState::capture`] and [`SaveState::from_bytes`], which rejects what
+/// the machine cannot hold, so restoring one always succeeds.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SaveState {
+    platform: Platform,
+    quirks: Quirks,
+    program_counter: usize,
+    halted: bool,
+    wait_for_input: Option<u8>,
+    v: [u8; 16],
+    i: u16,
+    flags: [u8; 16],
+    stack: Vec<u16>,
+    delay: u8,
+    sound: u8,
+    keys: u16,
+    last_pressed: Option<Chip8Key>,
+    audio: AudioRegisters,
+    framebuffer: Framebuffer,
+    memory: Vec<u8>,
+    random: Random,
+}
+
+impl SaveState {
+    pub fn capture(chip: &Chip) -> Self {
+        SaveState {
+            platform: chip.platform,
+            quirks: chip.quirks,
+            program_counter: chip.program_counter,
+            halted: chip.halted,
+            wait_for_input: chip.wait_for_input,
+            v: chip.registers.all(),
+            i: chip.registers.get_i(),
+            flags: chip.registers.flags(),
+            stack: chip.stack.as_slice().to_vec(),
+            delay: chip.timers.get_delay(),
+            sound: chip.timers.get_sound(),
+            keys: chip.keypad.pressed_mask(),
+            last_pressed: chip.keypad.last_pressed(),
+            audio: chip.audio,
+            framebuffer: chip.display.framebuffer().clone(),
+            memory: chip.memory.as_slice().to_vec(),
+            random: chip.random,
+        }
+    }
+
+    pub fn restore(&self, chip: &mut Chip) {
+        chip.platform = self.platform;
+        chip.quirks = self.quirks;
+        chip.program_counter = self.program_counter;
+        chip.halted = self.halted;
+        chip.wait_for_input = self.wait_for_input;
+        chip.registers.restore(self.v, self.i, self.flags);
+        chip.stack = Stack::from_slice(&self.stack).expect("validated when created");
+        chip.timers.set_delay(self.delay);
+        chip.timers.set_sound(self.sound);
+        chip.keypad.restore(self.keys, self.last_pressed);
+        chip.audio = self.audio;
+        *chip.display.buffer() = self.framebuffer.clone();
+
+        let mut memory = Memory::with_size(self.memory.len());
+        memory
+            .load(0usize, &self.memory)
+            .expect("memory is sized to fit");
+        chip.memory = memory;
+        chip.random = self.random;
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.memory.len() + 8400);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+
+        out.push(match self.platform {
+            Platform::Chip8 => 0,
+            Platform::SuperChip => 1,
+            Platform::XoChip => 2,
+        });
+        out.push(quirks_to_bits(&self.quirks));
+        out.extend_from_slice(&(self.program_counter as u32).to_le_bytes());
+        out.push(self.halted as u8);
+        out.push(self.wait_for_input.unwrap_or(NONE));
+
+        out.extend_from_slice(&self.v);
+        out.extend_from_slice(&self.i.to_le_bytes());
+        out.extend_from_slice(&self.flags);
+
+        out.push(self.stack.len() as u8);
+        for addr in &self.stack {
+            out.extend_from_slice(&addr.to_le_bytes());
+        }
+
+        out.push(self.delay);
+        out.push(self.sound);
+        out.extend_from_slice(&self.keys.to_le_bytes());
+        out.push(self.last_pressed.map_or(NONE, |k| k.as_u8()));
+
+        out.push(self.audio.pitch);
+        match self.audio.pattern {
+            Some(pattern) => {
+                out.push(1);
+                out.extend_from_slice(&pattern);
+            }
+            None => {
+                out.push(0);
+                out.extend_from_slice(&[0; 16]);
+            }
+        }
+
+        out.push(self.framebuffer.hires() as u8);
+        out.push(self.framebuffer.planes());
+        for y in 0..HIRES_DISPLAY_HEIGHT {
+            for x in 0..HIRES_DISPLAY_WIDTH {
+                out.push(self.framebuffer.color(x, y));
+            }
+        }
+
+        out.extend_from_slice(&(self.memory.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.memory);
+
+        let (kind, state) = match self.random {
+            Random::Xorshift(state) => (0, state),
+            Random::Vip(r9) => (1, r9 as u64),
+        };
+        out.push(kind);
+        out.extend_from_slice(&state.to_le_bytes());
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SaveStateError> {
+        let mut r = Reader { bytes, pos: 0 };
+
+        if r.take(4)? != MAGIC {
+            return Err(SaveStateError::BadMagic);
+        }
+        let version = r.u16()?;
+        if version != VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+
+        let platform = match r.u8()? {
+            0 => Platform::Chip8,
+            1 => Platform::SuperChip,
+            2 => Platform::XoChip,
+            _ => return Err(SaveStateError::Invalid("platform")),
+        };
+        let quirks = quirks_from_bits(r.u8()?);
+        let program_counter = r.u32()? as usize;
+        let halted = r.u8()? != 0;
+        let wait_for_input = match r.u8()? {
+            NONE => None,
+            x if x < 16 => Some(x),
+            _ => return Err(SaveStateError::Invalid("key wait register")),
+        };
+
+        let v = r.array()?;
+        let i = r.u16()?;
+        let flags = r.array()?;
+
+        let depth = r.u8()? as usize;
+        let stack = (0..depth).map(|_| r.u16()).collect::<Result<Vec<_>, _>>()?;
+        Stack::from_slice(&stack).map_err(|_| SaveStateError::Invalid("stack"))?;
+
+        let delay = r.u8()?;
+        let sound = r.u8()?;
+        let keys = r.u16()?;
+        let last_pressed = match r.u8()? {
+            NONE => None,
+            k => Some(Chip8Key::new(k).ok_or(SaveStateError::Invalid("key"))?),
+        };
+
+        let pitch = r.u8()?;
+        let has_pattern = r.u8()? != 0;
+        let pattern: [u8; 16] = r.array()?;
+        let audio = AudioRegisters {
+            pattern: has_pattern.then_some(pattern),
+            pitch,
+        };
+
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.set_hires(r.u8()? != 0);
+        framebuffer.set_planes(r.u8()?);
+        for y in 0..HIRES_DISPLAY_HEIGHT {
+            for x in 0..HIRES_DISPLAY_WIDTH {
+                framebuffer.set_color(x, y, r.u8()?);
+            }
+        }
+
+        let memory_size = r.u32()? as usize;
+        if memory_size != platform.memory_size() {
+            return Err(SaveStateError::Invalid("memory size"));
+        }
+        let memory = r.take(memory_size)?.to_vec();
+
+        let kind = r.u8()?;
+        let state = u64::from_le_bytes(r.array()?);
+        let random = match kind {
+            0 if state != 0 => Random::Xorshift(state),
+            1 if state <= u16::MAX as u64 => Random::Vip(state as u16),
+            _ => return Err(SaveStateError::Invalid("random number generator")),
+        };
+
+        Ok(SaveState {
+            platform,
+            quirks,
+            program_counter,
+            halted,
+            wait_for_input,
+            v,
+            i,
+            flags,
+            stack,
+            delay,
+            sound,
+            keys,
+            last_pressed,
+            audio,
+            framebuffer,
+            memory,
+            random,
+        })
+    }
+
+    pub fn write_to(&self, mut out: impl Write) -> io::Result<()> {
+        out.write_all(&self.to_bytes())
+    }
+
+    pub fn read_from(mut input: impl Read) -> Result<Self, SaveStateError> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+fn quirks_to_bits(quirks: &Quirks) -> u8 {
+    [
+        quirks.shift,
+        quirks.load_store == LoadStore::Unchanged,
+        quirks.jump,
+        quirks.vf_reset,
+        quirks.clipping,
+        quirks.display_wait,
+        quirks.load_store == LoadStore::IncrementByX,
+    ]
+    .iter()
+    .enumerate()
+    .fold(0, |bits, (n, &on)| bits | ((on as u8) << n))
+}
+
+fn quirks_from_bits(bits: u8) -> Quirks {
+    let on = |n: u8| bits & (1 << n) != 0;
+    Quirks {
+        shift: on(0),
+        load_store: match (on(1), on(6)) {
+            (true, _) => LoadStore::Unchanged,
+            (false, true) => LoadStore::IncrementByX,
+            (false, false) => LoadStore::Increment,
+        },
+        jump: on(2),
+        vf_reset: on(3),
+        clipping: on(4),
+        display_wait: on(5),
+    }
+}
+
+/// Cursor over the bytes of a save state
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], SaveStateError> {
+        let end = self.pos + n;
+        let slice = self
+            .bytes
+            .get(self.pos..end)
+            .ok_or(SaveStateError::Truncated)?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], SaveStateError> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::headless::HeadlessDisplay;
+
+    fn running_chip() -> Chip {
+        // CALL 0x206 ; (unused) ; LD V5, 0x2A ; LD I, 0x050 ; DRW V0, V0, 5
+        let mut chip = Chip::with_platform(HeadlessDisplay::new(), Platform::SuperChip);
+        chip.load_rom(&[0x22, 0x04, 0x00, 0x00, 0x65, 0x2A, 0xA0, 0x50, 0xD0, 0x05])
+            .unwrap();
+        for _ in 0..4 {
+            chip.step().unwrap();
+        }
+        chip.timers.set_sound(7);
+        chip.try_press('w');
+        chip
+    }
+
+    #[test]
+    fn test_round_trip_through_bytes() {
+        let chip = running_chip();
+        let state = SaveState::capture(&chip);
+
+        let bytes = state.to_bytes();
+        let loaded = SaveState::from_bytes(&bytes).unwrap();
+        assert!(loaded == state);
+    }
+
+    #[test]
+    fn test_vip_generator_round_trips() {
+        let mut chip = running_chip();
+        chip.random = Random::vip(0xBEEF);
+        let state = SaveState::capture(&chip);
+
+        let loaded = SaveState::from_bytes(&state.to_bytes()).unwrap();
+        assert_eq!(loaded.random, Random::Vip(0xBEEF));
+    }
+
+    #[test]
+    fn test_quirk_presets_round_trip_through_bits() {
+        for quirks in [
+            Quirks::COSMAC_VIP,
+            Quirks::CHIP_48,
+            Quirks::SUPER_CHIP,
+            Quirks::XO_CHIP,
+        ] {
+            assert_eq!(quirks_from_bits(quirks_to_bits(&quirks)), quirks);
+        }
+    }
+
+    #[test]
+    fn test_restore_resumes_machine() {
+        let chip = running_chip();
+        let state = SaveState::capture(&chip);
+
+        let mut other = Chip::new(HeadlessDisplay::new());
+        state.restore(&mut other);
+        assert!(SaveState::capture(&other) == state);
+        assert_eq!(other.platform, Platform::SuperChip);
+        assert_eq!(other.stack.as_slice(), &[0x202]);
+        assert!(other.display.framebuffer().get(0, 0));
+        assert_eq!(other.random, chip.random);
+    }
+
+    #[test]
+    fn test_rejects_bad_files() {
+        assert!(matches!(
+            SaveState::from_bytes(b"nope"),
+            Err(SaveStateError::BadMagic)
+        ));
+
+        let mut bytes = SaveState::capture(&running_chip()).to_bytes();
+        bytes[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            SaveState::from_bytes(&bytes),
+            Err(SaveStateError::UnsupportedVersion(_))
+        ));
+
+        let bytes = SaveState::capture(&running_chip()).to_bytes();
+        assert!(matches!(
+            SaveState::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(SaveStateError::Truncated)
+        ));
+    }
+}
@@ -0,0 +1,92 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Rate of the delay and sound timers, and of frames
+pub const FRAME_RATE: u32 = 60;
+
+/// Frames the scheduler catches up on before giving up and resyncing
+const MAX_CATCH_UP: u32 = 5;
+
+/// Paces frames at a fixed rate.
+///
+/// Deadlines are kept on an absolute grid from the start time, so time
+/// spent emulating and rendering is compensated instead of adding up the
+/// way a fixed sleep per frame does.
+pub struct Scheduler {
+    period: Duration,
+    next: Instant,
+}
+
+impl Scheduler {
+    /// Scheduler running at [`FRAME_RATE`]
+    pub fn new() -> Self {
+        Self::with_rate(FRAME_RATE)
+    }
+
+    pub fn with_rate(rate: u32) -> Self {
+        let period = Duration::from_secs(1) / rate;
+        Scheduler {
+            period,
+            next: Instant::now() + period,
+        }
+    }
+
+    /// Number of frames due at `now`, advancing the deadline past them.
+    ///
+    /// Falling more than a few frames behind (a suspended process, a slow
+    /// terminal) drops the backlog rather than fast forwarding through it.
+    pub fn frames_due(&mut self, now: Instant) -> u32 {
+        if now < self.next {
+            return 0;
+        }
+        let behind = ((now - self.next).as_nanos() / self.period.as_nanos()) as u32 + 1;
+        if behind > MAX_CATCH_UP {
+            self.next = now + self.period;
+            return 1;
+        }
+        self.next += self.period * behind;
+        behind
+    }
+
+    /// Sleep until at least one frame is due and return how many are
+    pub fn wait(&mut self) -> u32 {
+        let now = Instant::now();
+        if now < self.next {
+            thread::sleep(self.next - now);
+        }
+        self.frames_due(Instant::now()).max(1)
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_due_on_grid() {
+        let mut scheduler = Scheduler::with_rate(100);
+        let start = scheduler.next - scheduler.period;
+
+        assert_eq!(scheduler.frames_due(start + Duration::from_millis(5)), 0);
+        assert_eq!(scheduler.frames_due(start + Duration::from_millis(12)), 1);
+        // Late by a frame and a half, the next deadline stays on the grid
+        assert_eq!(scheduler.frames_due(start + Duration::from_millis(35)), 2);
+        assert_eq!(scheduler.next, start + Duration::from_millis(40));
+    }
+
+    #[test]
+    fn test_frames_due_resyncs_when_far_behind() {
+        let mut scheduler = Scheduler::with_rate(100);
+        let start = scheduler.next - scheduler.period;
+        let late = start + Duration::from_secs(1);
+
+        assert_eq!(scheduler.frames_due(late), 1);
+        assert_eq!(scheduler.next, late + scheduler.period);
+    }
+}
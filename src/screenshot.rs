@@ -0,0 +1,301 @@
+//! Screenshots of the framebuffer as PNG, PBM or PPM images.
+//!
+//! PNG and PPM use the colours of a [`Palette`], PBM only has black and
+//! white and writes lit pixels as 1, which viewers show as black.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::display::framebuffer::Framebuffer;
+use crate::display::palette::Palette;
+
+/// Longest block of uncompressed deflate data
+const STORED_BLOCK_SIZE: usize = 0xFFFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Pbm,
+    Ppm,
+}
+
+impl ImageFormat {
+    /// Format going by the file extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "pbm" => Some(ImageFormat::Pbm),
+            "ppm" => Some(ImageFormat::Ppm),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ScreenshotError {
+    Io(io::Error),
+    /// The file extension is not png, pbm or ppm
+    UnknownFormat(String),
+}
+
+impl fmt::Display for ScreenshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScreenshotError::Io(err) => write!(f, "{}", err),
+            ScreenshotError::UnknownFormat(path) => {
+                write!(f, "{} is not a .png, .pbm or .ppm file", path)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScreenshotError {}
+
+impl From<io::Error> for ScreenshotError {
+    fn from(err: io::Error) -> Self {
+        ScreenshotError::Io(err)
+    }
+}
+
+/// How screenshots are drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Screenshot {
+    /// Size of a CHIP 8 pixel in image pixels
+    pub scale: usize,
+    pub palette: Palette,
+}
+
+impl Default for Screenshot {
+    fn default() -> Self {
+        Screenshot {
+            scale: 1,
+            palette: Palette::default(),
+        }
+    }
+}
+
+impl Screenshot {
+    /// Write `screen` to `path` in the format of its extension
+    pub fn save(&self, screen: &Framebuffer, path: &Path) -> Result<(), ScreenshotError> {
+        let format = ImageFormat::from_path(path)
+            .ok_or_else(|| ScreenshotError::UnknownFormat(path.display().to_string()))?;
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write(screen, format, &mut out)?;
+        out.flush()?;
+        Ok(())
+    }
+
+    pub fn write(
+        &self,
+        screen: &Framebuffer,
+        format: ImageFormat,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        match format {
+            ImageFormat::Png => self.write_png(screen, out),
+            ImageFormat::Pbm => self.write_pbm(screen, out),
+            ImageFormat::Ppm => {
+                let (width, height) = self.size(screen);
+                write!(out, "P6\n{} {}\n255\n", width, height)?;
+                out.write_all(&self.rgb(screen))
+            }
+        }
+    }
+
+    /// Image width and height
+    pub fn size(&self, screen: &Framebuffer) -> (usize, usize) {
+        (screen.width() * self.scale, screen.height() * self.scale)
+    }
+
+    /// Scaled image as rows of RGB bytes
+    pub fn rgb(&self, screen: &Framebuffer) -> Vec<u8> {
+        let (width, height) = self.size(screen);
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for y in 0..height {
+            for x in 0..width {
+                let rgb = self
+                    .palette
+                    .color(screen.color(x / self.scale, y / self.scale));
+                pixels.extend_from_slice(&[rgb.0, rgb.1, rgb.2]);
+            }
+        }
+        pixels
+    }
+
+    fn write_pbm(&self, screen: &Framebuffer, out: &mut impl Write) -> io::Result<()> {
+        let (width, height) = self.size(screen);
+        write!(out, "P4\n{} {}\n", width, height)?;
+        for y in 0..height {
+            // Rows are padded to whole bytes
+            let mut row = vec![0u8; width.div_ceil(8)];
+            for x in 0..width {
+                if screen.color(x / self.scale, y / self.scale) != 0 {
+                    row[x / 8] |= 0x80 >> (x % 8);
+                }
+            }
+            out.write_all(&row)?;
+        }
+        Ok(())
+    }
+
+    /// 8-bit RGB PNG, stored without compression
+    fn write_png(&self, screen: &Framebuffer, out: &mut impl Write) -> io::Result<()> {
+        let (width, height) = self.size(screen);
+        out.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(width as u32).to_be_bytes());
+        header.extend_from_slice(&(height as u32).to_be_bytes());
+        // Bit depth 8, RGB, deflate, adaptive filtering, no interlace
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        write_chunk(out, b"IHDR", &header)?;
+
+        // Every row starts with filter type 0, none
+        let mut scanlines = Vec::with_capacity(height * (width * 3 + 1));
+        for row in self.rgb(screen).chunks(width * 3) {
+            scanlines.push(0);
+            scanlines.extend_from_slice(row);
+        }
+        write_chunk(out, b"IDAT", &zlib_stored(&scanlines))?;
+        write_chunk(out, b"IEND", &[])
+    }
+}
+
+fn write_chunk(out: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let crc = crc32(kind.iter().chain(data));
+    out.write_all(&crc.to_be_bytes())
+}
+
+/// zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(STORED_BLOCK_SIZE).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none() as u8;
+        let len = block.len() as u16;
+        out.push(last);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32<'a>(bytes: impl IntoIterator<Item = &'a u8>) -> u32 {
+    !bytes.into_iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::palette::Rgb;
+
+    fn screen() -> Framebuffer {
+        let mut screen = Framebuffer::new();
+        screen.set(0, 0, true);
+        screen.set(63, 31, true);
+        screen
+    }
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn test_ppm_uses_palette_and_scale() {
+        let mut shot = Screenshot {
+            scale: 2,
+            ..Screenshot::default()
+        };
+        shot.palette.colors[0] = Rgb(1, 2, 3);
+        shot.palette.colors[1] = Rgb(0xFF, 0x80, 0);
+        let mut out = Vec::new();
+        shot.write(&screen(), ImageFormat::Ppm, &mut out).unwrap();
+
+        let header = b"P6\n128 64\n255\n";
+        assert_eq!(&out[..header.len()], header);
+        let pixels = &out[header.len()..];
+        assert_eq!(pixels.len(), 128 * 64 * 3);
+        // Top left CHIP 8 pixel covers 2x2 image pixels
+        assert_eq!(&pixels[..9], &[0xFF, 0x80, 0, 0xFF, 0x80, 0, 1, 2, 3]);
+        assert_eq!(
+            &pixels[128 * 3..128 * 3 + 6],
+            &[0xFF, 0x80, 0, 0xFF, 0x80, 0]
+        );
+    }
+
+    #[test]
+    fn test_pbm() {
+        let mut out = Vec::new();
+        Screenshot::default()
+            .write(&screen(), ImageFormat::Pbm, &mut out)
+            .unwrap();
+        let header = b"P4\n64 32\n";
+        assert_eq!(&out[..header.len()], header);
+        let bits = &out[header.len()..];
+        assert_eq!(bits.len(), 8 * 32);
+        assert_eq!(bits[0], 0x80);
+        assert_eq!(bits[bits.len() - 1], 0x01);
+    }
+
+    #[test]
+    fn test_png_structure() {
+        let mut out = Vec::new();
+        Screenshot::default()
+            .write(&screen(), ImageFormat::Png, &mut out)
+            .unwrap();
+        assert_eq!(&out[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&out[12..16], b"IHDR");
+        assert_eq!(&out[16..24], &[0, 0, 0, 64, 0, 0, 0, 32]);
+        assert_eq!(&out[out.len() - 12..], b"\0\0\0\0IEND\xAE\x42\x60\x82");
+
+        // The image data inflates back to one filtered row per line
+        let idat = &out[33..];
+        let len = u32::from_be_bytes(idat[..4].try_into().unwrap()) as usize;
+        let zlib = &idat[8..8 + len];
+        let stored = &zlib[2..zlib.len() - 4];
+        assert_eq!(stored[0], 1);
+        assert_eq!(
+            u16::from_le_bytes([stored[1], stored[2]]) as usize,
+            32 * (64 * 3 + 1)
+        );
+        assert_eq!(&stored[5..9], &[0, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn test_format_from_extension() {
+        assert_eq!(
+            ImageFormat::from_path(Path::new("a/shot.PNG")),
+            Some(ImageFormat::Png)
+        );
+        assert_eq!(ImageFormat::from_path(Path::new("shot.gif")), None);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::error::Fault;
+
 const STACK_SIZE: usize = 16;
 
 pub struct Stack {
@@ -13,29 +15,44 @@ impl Stack {
         }
     }
 
-    pub fn push(&mut self, value: u16) {
+    pub fn push(&mut self, value: u16) -> Result<(), Fault> {
         if self.pointer >= STACK_SIZE {
-            panic!("Stack overflow");
+            return Err(Fault::StackOverflow);
         }
         self.stack[self.pointer] = value;
         self.pointer += 1;
+        Ok(())
     }
 
-    pub fn pop(&mut self) -> u16 {
+    pub fn pop(&mut self) -> Result<u16, Fault> {
         if self.pointer == 0 {
-            panic!("Stack underflow");
+            return Err(Fault::StackUnderflow);
         }
         self.pointer -= 1;
         let value = self.stack[self.pointer];
         self.stack[self.pointer] = 0; // optional: clear
-        value
+        Ok(value)
     }
 
-    pub fn peek(&self) -> u16 {
+    pub fn peek(&self) -> Result<u16, Fault> {
         if self.pointer == 0 {
-            panic!("Stack empty");
+            return Err(Fault::StackUnderflow);
+        }
+        Ok(self.stack[self.pointer - 1])
+    }
+
+    /// Occupied part of the stack, bottom first
+    pub fn as_slice(&self) -> &[u16] {
+        &self.stack[..self.pointer]
+    }
+
+    /// Stack holding `values`, bottom first
+    pub fn from_slice(values: &[u16]) -> Result<Self, Fault> {
+        let mut stack = Stack::new();
+        for &value in values {
+            stack.push(value)?;
         }
-        self.stack[self.pointer - 1]
+        Ok(stack)
     }
 
     pub fn is_empty(&self) -> bool {
@@ -47,6 +64,12 @@ impl Stack {
     }
 }
 
+impl Default for Stack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -56,12 +79,12 @@ mod tests {
         let mut stack = Stack::new();
 
         // Push one value
-        stack.push(0x123);
-        assert_eq!(stack.peek(), 0x123);
+        stack.push(0x123).unwrap();
+        assert_eq!(stack.peek(), Ok(0x123));
 
         // Pop it
         let value = stack.pop();
-        assert_eq!(value, 0x123);
+        assert_eq!(value, Ok(0x123));
         assert!(stack.is_empty());
     }
 
@@ -69,32 +92,32 @@ mod tests {
     fn test_lifo_behavior() {
         let mut stack = Stack::new();
 
-        stack.push(1);
-        stack.push(2);
-        stack.push(3);
+        stack.push(1).unwrap();
+        stack.push(2).unwrap();
+        stack.push(3).unwrap();
 
-        assert_eq!(stack.pop(), 3);
-        assert_eq!(stack.pop(), 2);
-        assert_eq!(stack.pop(), 1);
+        assert_eq!(stack.pop(), Ok(3));
+        assert_eq!(stack.pop(), Ok(2));
+        assert_eq!(stack.pop(), Ok(1));
         assert!(stack.is_empty());
     }
 
     #[test]
-    #[should_panic(expected = "Stack underflow")]
     fn test_pop_underflow() {
         let mut stack = Stack::new();
-        stack.pop(); // Should panic
+        assert_eq!(stack.pop(), Err(Fault::StackUnderflow));
+        assert_eq!(stack.peek(), Err(Fault::StackUnderflow));
     }
 
     #[test]
-    #[should_panic(expected = "Stack overflow")]
     fn test_push_overflow() {
         let mut stack = Stack::new();
         for i in 0..STACK_SIZE {
-            stack.push(i as u16);
+            stack.push(i as u16).unwrap();
         }
-        // Next push should panic
-        stack.push(0xABCD);
+        // Next push should fail and leave the stack untouched
+        assert_eq!(stack.push(0xABCD), Err(Fault::StackOverflow));
+        assert_eq!(stack.peek(), Ok(STACK_SIZE as u16 - 1));
     }
 
     #[test]
@@ -104,7 +127,7 @@ mod tests {
         assert!(!stack.is_full());
 
         for i in 0..STACK_SIZE {
-            stack.push(i as u16);
+            stack.push(i as u16).unwrap();
         }
 
         assert!(stack.is_full());
@@ -114,11 +137,11 @@ mod tests {
     #[test]
     fn test_peek_does_not_pop() {
         let mut stack = Stack::new();
-        stack.push(0x55);
+        stack.push(0x55).unwrap();
 
         let top = stack.peek();
-        assert_eq!(top, 0x55);
-        assert_eq!(stack.pop(), 0x55);
+        assert_eq!(top, Ok(0x55));
+        assert_eq!(stack.pop(), Ok(0x55));
         assert!(stack.is_empty());
     }
 }
@@ -0,0 +1,559 @@
+//! Execution traces, one line per instruction, for comparing runs against
+//! each other or against other emulators.
+//!
+//! Each line holds the machine state before the instruction executes as
+//! `key=value` pairs, values in hex except the decimal cycle, followed by
+//! the mnemonic as a `;` comment:
+//!
+//! ```text
+//! cycle=0 pc=0200 op=6005 v0=00 v1=00 ... vf=00 i=0000 sp=0 dt=00 st=00 ; LD V0, 0x05
+//! ```
+//!
+//! [`TraceFormat::JsonLines`] writes the same keys as one JSON object per
+//! line, with the values as hex strings and the mnemonic as `asm`:
+//!
+//! ```text
+//! {"cycle":0,"pc":"0200","op":"6005","v0":"00",...,"st":"00","asm":"LD V0, 0x05"}
+//! ```
+//!
+//! Both formats are stable, tools may rely on the keys and their order.
+//! [`diff`] reads either, also mixed, and only compares keys present on both
+//! sides, so traces from other emulators converted to them may leave out
+//! what they lack. JSON values may also be plain numbers.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::chip::Chip;
+use crate::opcode::Opcode;
+
+/// Compared keys, in the order they are written
+const KEYS: [&str; 22] = [
+    "pc", "op", "v0", "v1", "v2", "v3", "v4", "v5", "v6", "v7", "v8", "v9", "va", "vb", "vc", "vd",
+    "ve", "vf", "i", "sp", "dt", "st",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// `key=value` pairs and a `;` comment
+    #[default]
+    Text,
+    /// One JSON object per line
+    JsonLines,
+}
+
+/// Writes a trace line for every executed instruction, see [`Chip::tracer`]
+pub struct Tracer {
+    out: Box<dyn Write>,
+    format: TraceFormat,
+    cycle: u64,
+    /// First write error, tracing stops after it
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new(out: impl Write + 'static) -> Self {
+        Self::with_format(out, TraceFormat::Text)
+    }
+
+    pub fn with_format(out: impl Write + 'static, format: TraceFormat) -> Self {
+        Tracer {
+            out: Box::new(out),
+            format,
+            cycle: 0,
+            error: None,
+        }
+    }
+
+    /// Trace `opcode` about to execute on `chip`
+    pub fn record(&mut self, chip: &Chip, pc: usize, opcode: &Opcode) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(err) = self.write_line(chip, pc, opcode) {
+            self.error = Some(err);
+        }
+        self.cycle += 1;
+    }
+
+    fn write_line(&mut self, chip: &Chip, pc: usize, opcode: &Opcode) -> io::Result<()> {
+        // Values in the order of KEYS
+        let mut values = vec![format!("{:04x}", pc), format!("{:04x}", opcode.encode())];
+        values.extend(chip.registers.all().iter().map(|v| format!("{:02x}", v)));
+        values.push(format!("{:04x}", chip.registers.get_i()));
+        values.push(format!("{:x}", chip.stack.as_slice().len()));
+        values.push(format!("{:02x}", chip.timers.get_delay()));
+        values.push(format!("{:02x}", chip.timers.get_sound()));
+
+        match self.format {
+            TraceFormat::Text => {
+                write!(self.out, "cycle={}", self.cycle)?;
+                for (key, value) in KEYS.iter().zip(&values) {
+                    write!(self.out, " {}={}", key, value)?;
+                }
+                writeln!(self.out, " ; {}", opcode)
+            }
+            TraceFormat::JsonLines => {
+                write!(self.out, "{{\"cycle\":{}", self.cycle)?;
+                for (key, value) in KEYS.iter().zip(&values) {
+                    write!(self.out, ",\"{}\":\"{}\"", key, value)?;
+                }
+                // Mnemonics hold no quotes or backslashes
+                writeln!(self.out, ",\"asm\":\"{}\"}}", opcode)
+            }
+        }
+    }
+
+    /// Flush the trace, reporting any write error met along the way
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.out.flush()
+    }
+}
+
+#[derive(Debug)]
+pub enum TraceError {
+    Io(io::Error),
+    /// A line that is not a trace line
+    Parse {
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceError::Io(err) => write!(f, "{}", err),
+            TraceError::Parse { line, message } => write!(f, "trace line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+impl From<io::Error> for TraceError {
+    fn from(err: io::Error) -> Self {
+        TraceError::Io(err)
+    }
+}
+
+/// One parsed trace line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceLine {
+    pub cycle: Option<u64>,
+    pub values: BTreeMap<String, u32>,
+    /// The line as written
+    pub text: String,
+}
+
+impl TraceLine {
+    /// Parse a line of either [`TraceFormat`]
+    pub fn parse(text: &str, line: usize) -> Result<Self, TraceError> {
+        if text.trim_start().starts_with('{') {
+            return Self::parse_json(text, line);
+        }
+        let error = |message: String| TraceError::Parse { line, message };
+        let fields = text.split(';').next().unwrap_or("");
+        let mut cycle = None;
+        let mut values = BTreeMap::new();
+        for field in fields.split_whitespace() {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or_else(|| error(format!("expected key=value, found {:?}", field)))?;
+            let key = key.to_ascii_lowercase();
+            if key == "cycle" {
+                cycle = Some(
+                    value
+                        .parse()
+                        .map_err(|_| error(format!("bad cycle {:?}", value)))?,
+                );
+                continue;
+            }
+            let value = u32::from_str_radix(value, 16)
+                .map_err(|_| error(format!("bad value {:?} for {}", value, key)))?;
+            values.insert(key, value);
+        }
+        Ok(TraceLine {
+            cycle,
+            values,
+            text: text.to_string(),
+        })
+    }
+
+    fn parse_json(text: &str, line: usize) -> Result<Self, TraceError> {
+        let error = |message: String| TraceError::Parse { line, message };
+        let fields = JsonReader::new(text).object().map_err(error)?;
+        let mut cycle = None;
+        let mut values = BTreeMap::new();
+        for (key, value) in fields {
+            let key = key.to_ascii_lowercase();
+            match (key.as_str(), value) {
+                // The comment of the text format
+                ("asm", _) => {}
+                ("cycle", Json::Number(n)) => cycle = Some(n),
+                ("cycle", Json::String(s)) => {
+                    return Err(error(format!("bad cycle {:?}", s)));
+                }
+                (_, Json::Number(n)) => {
+                    let value = u32::try_from(n)
+                        .map_err(|_| error(format!("value {} for {} is too large", n, key)))?;
+                    values.insert(key, value);
+                }
+                (_, Json::String(s)) => {
+                    let value = u32::from_str_radix(&s, 16)
+                        .map_err(|_| error(format!("bad value {:?} for {}", s, key)))?;
+                    values.insert(key, value);
+                }
+            }
+        }
+        Ok(TraceLine {
+            cycle,
+            values,
+            text: text.to_string(),
+        })
+    }
+}
+
+/// Value of a JSON trace line
+enum Json {
+    Number(u64),
+    String(String),
+}
+
+/// Reads the flat objects of JSON trace lines, whose values are only
+/// strings and whole numbers
+struct JsonReader<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl<'a> JsonReader<'a> {
+    fn new(text: &'a str) -> Self {
+        JsonReader {
+            chars: text.chars().peekable(),
+        }
+    }
+
+    fn skip_space(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_space();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("expected {:?}, found {:?}", expected, c)),
+            None => Err(format!(
+                "expected {:?}, found the end of the line",
+                expected
+            )),
+        }
+    }
+
+    /// Fields of the object making up the whole line
+    fn object(mut self) -> Result<Vec<(String, Json)>, String> {
+        self.expect('{')?;
+        let mut fields = Vec::new();
+        self.skip_space();
+        if self.chars.next_if_eq(&'}').is_none() {
+            loop {
+                let key = self.string()?;
+                self.expect(':')?;
+                fields.push((key, self.value()?));
+                self.skip_space();
+                match self.chars.next() {
+                    Some(',') => {}
+                    Some('}') => break,
+                    _ => return Err("expected ',' or '}' after a value".to_string()),
+                }
+            }
+        }
+        self.skip_space();
+        match self.chars.next() {
+            None => Ok(fields),
+            Some(c) => Err(format!("unexpected {:?} after the object", c)),
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_space();
+        match self.chars.peek() {
+            Some('"') => self.string().map(Json::String),
+            Some(c) if c.is_ascii_digit() => {
+                let mut digits = String::new();
+                while let Some(c) = self.chars.next_if(char::is_ascii_digit) {
+                    digits.push(c);
+                }
+                digits
+                    .parse()
+                    .map(Json::Number)
+                    .map_err(|_| format!("number {} is too large", digits))
+            }
+            _ => Err("expected a string or a whole number".to_string()),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut text = String::new();
+        loop {
+            let c = match self.chars.next() {
+                None => return Err("unterminated string".to_string()),
+                Some('"') => return Ok(text),
+                Some('\\') => match self.chars.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('u') => {
+                        let hex: String = (0..4).filter_map(|_| self.chars.next()).collect();
+                        u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .filter(|_| hex.len() == 4)
+                            .map(|code| char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER))
+                            .ok_or_else(|| format!("bad escape \\u{}", hex))?
+                    }
+                    Some(c @ ('"' | '\\' | '/')) => c,
+                    Some(c) => return Err(format!("bad escape \\{}", c)),
+                    None => return Err("unterminated string".to_string()),
+                },
+                Some(c) => c,
+            };
+            text.push(c);
+        }
+    }
+}
+
+/// Key that differs between two lines with both values
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    pub key: String,
+    pub left: u32,
+    pub right: u32,
+}
+
+/// Where two traces first part ways
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    /// Both traces have the line, with different values
+    Mismatch {
+        line: usize,
+        left: TraceLine,
+        right: TraceLine,
+        differences: Vec<Difference>,
+    },
+    /// The left trace ends before the right one, at `line`
+    LeftEnded { line: usize },
+    /// The right trace ends before the left one, at `line`
+    RightEnded { line: usize },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Divergence::Mismatch {
+                line,
+                left,
+                right,
+                differences,
+            } => {
+                write!(f, "first divergence at line {}", line)?;
+                if let Some(cycle) = left.cycle {
+                    write!(f, " (cycle {})", cycle)?;
+                }
+                writeln!(f)?;
+                writeln!(f, "  left:  {}", left.text)?;
+                writeln!(f, "  right: {}", right.text)?;
+                for d in differences {
+                    writeln!(f, "  {}: {:x} != {:x}", d.key, d.left, d.right)?;
+                }
+                Ok(())
+            }
+            Divergence::LeftEnded { line } => writeln!(f, "left trace ends at line {}", line),
+            Divergence::RightEnded { line } => {
+                writeln!(f, "right trace ends at line {}", line)
+            }
+        }
+    }
+}
+
+/// Compare two traces line by line, returning where they first differ
+pub fn diff(left: impl BufRead, right: impl BufRead) -> Result<Option<Divergence>, TraceError> {
+    let mut left = left.lines();
+    let mut right = right.lines();
+    let mut line = 0;
+    loop {
+        line += 1;
+        let (l, r) = match (left.next().transpose()?, right.next().transpose()?) {
+            (None, None) => return Ok(None),
+            (None, Some(_)) => return Ok(Some(Divergence::LeftEnded { line })),
+            (Some(_), None) => return Ok(Some(Divergence::RightEnded { line })),
+            (Some(l), Some(r)) => (TraceLine::parse(&l, line)?, TraceLine::parse(&r, line)?),
+        };
+
+        let mut differences = Vec::new();
+        let mut keys: Vec<&String> = l.values.keys().collect();
+        // Known keys in trace order, anything else after them
+        keys.sort_by_key(|key| KEYS.iter().position(|k| k == key).unwrap_or(KEYS.len()));
+        for key in keys {
+            if let (Some(&a), Some(&b)) = (l.values.get(key), r.values.get(key)) {
+                if a != b {
+                    differences.push(Difference {
+                        key: key.clone(),
+                        left: a,
+                        right: b,
+                    });
+                }
+            }
+        }
+        if !differences.is_empty() {
+            return Ok(Some(Divergence::Mismatch {
+                line,
+                left: l,
+                right: r,
+                differences,
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::headless::HeadlessDisplay;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Writer whose contents stay readable after the tracer took it
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn trace(rom: &[u8], steps: usize) -> String {
+        trace_as(rom, steps, TraceFormat::Text)
+    }
+
+    fn trace_as(rom: &[u8], steps: usize, format: TraceFormat) -> String {
+        let out = Shared::default();
+        let mut chip = Chip::new(HeadlessDisplay::new());
+        chip.tracer = Some(Tracer::with_format(out.clone(), format));
+        chip.load_rom(rom).unwrap();
+        for _ in 0..steps {
+            chip.step().unwrap();
+        }
+        chip.tracer.take().unwrap().finish().unwrap();
+        let text = String::from_utf8(out.0.borrow().clone()).unwrap();
+        text
+    }
+
+    #[test]
+    fn test_trace_lines() {
+        let text = trace(&[0x60, 0x05, 0xA1, 0x23], 2);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("cycle=0 pc=0200 op=6005 v0=00 v1=00"));
+        assert!(lines[0].ends_with(" i=0000 sp=0 dt=00 st=00 ; LD V0, 0x05"));
+        assert!(lines[1].starts_with("cycle=1 pc=0202 op=a123 v0=05"));
+    }
+
+    #[test]
+    fn test_json_lines_round_trip() {
+        let rom = [0x60, 0x05, 0xA1, 0x23, 0x22, 0x08];
+        let text = trace(&rom, 3);
+        let json = trace_as(&rom, 3, TraceFormat::JsonLines);
+        let first = json.lines().next().unwrap();
+        assert!(first.starts_with(r#"{"cycle":0,"pc":"0200","op":"6005","v0":"00","#));
+        assert!(first.ends_with(r#","sp":"0","dt":"00","st":"00","asm":"LD V0, 0x05"}"#));
+
+        for (n, (t, j)) in text.lines().zip(json.lines()).enumerate() {
+            let t = TraceLine::parse(t, n + 1).unwrap();
+            let j = TraceLine::parse(j, n + 1).unwrap();
+            assert_eq!((j.cycle, &j.values), (t.cycle, &t.values));
+            assert_eq!(j.values.len(), KEYS.len());
+        }
+        assert_eq!(diff(text.as_bytes(), json.as_bytes()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_json_lines_from_other_emulators() {
+        let line = TraceLine::parse(r#" { "PC": 512, "op" : "6005", "note": "\u0041\"" } "#, 1);
+        let line = line.unwrap_err().to_string();
+        assert_eq!(line, r#"trace line 1: bad value "A\"" for note"#);
+
+        let line = TraceLine::parse(r#"{"cycle": 7, "PC": 512, "v0": "0A"}"#, 1).unwrap();
+        assert_eq!(line.cycle, Some(7));
+        assert_eq!(line.values["pc"], 0x200);
+        assert_eq!(line.values["v0"], 0x0A);
+
+        for bad in [
+            r#"{"pc": "0200""#,
+            r#"{"pc": -1}"#,
+            r#"{"pc": 1.5}"#,
+            r#"{"pc": true}"#,
+            r#"{"pc": "0200"} x"#,
+            r#"{"cycle": "3"}"#,
+            r#"{"pc": 4294967296}"#,
+            r#"{"pc": "\q"}"#,
+        ] {
+            assert!(
+                matches!(
+                    TraceLine::parse(bad, 4),
+                    Err(TraceError::Parse { line: 4, .. })
+                ),
+                "{}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let a = trace(&[0x60, 0x05, 0x61, 0x01, 0x62, 0x02], 3);
+        let b = trace(&[0x60, 0x05, 0x61, 0x07, 0x62, 0x02], 3);
+        assert_eq!(diff(a.as_bytes(), a.as_bytes()).unwrap(), None);
+
+        match diff(a.as_bytes(), b.as_bytes()).unwrap() {
+            Some(Divergence::Mismatch {
+                line, differences, ..
+            }) => {
+                assert_eq!(line, 2);
+                assert_eq!(
+                    differences,
+                    [Difference {
+                        key: "op".into(),
+                        left: 0x6101,
+                        right: 0x6107
+                    }]
+                );
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let short: String = a.lines().take(2).map(|l| format!("{}\n", l)).collect();
+        assert_eq!(
+            diff(a.as_bytes(), short.as_bytes()).unwrap(),
+            Some(Divergence::RightEnded { line: 3 })
+        );
+    }
+
+    #[test]
+    fn test_diff_ignores_keys_missing_on_one_side() {
+        let ours = "cycle=0 pc=0200 op=6005 v0=00 dt=3c ; LD V0, 0x05\n";
+        let reference = "PC=0200 OP=6005 V0=00\n";
+        assert_eq!(diff(ours.as_bytes(), reference.as_bytes()).unwrap(), None);
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::display::framebuffer::Framebuffer;
+use crate::platform::Platform;
+use crate::screenshot::Screenshot;
+use crate::video::video_sink::VideoSink;
+use crate::video::{color_indices, video_size};
+
+/// LZW code size for a four colour table
+const MIN_CODE_SIZE: u8 = 2;
+const MAX_CODE_BITS: u8 = 12;
+
+/// Shortest frame delay, browsers slow shorter ones down to 10
+const MIN_DELAY_CENTIS: u64 = 2;
+
+/// Writes an animated, looping GIF.
+///
+/// GIF delays are in hundredths of a second, so a picture is only written
+/// once it changes and with the time it stayed on screen. Pictures shown
+/// for less than [`MIN_DELAY_CENTIS`] are dropped.
+pub struct GifSink<W: Write> {
+    out: W,
+    look: Screenshot,
+    size: (usize, usize),
+    /// Whether the header is written
+    started: bool,
+    /// Picture waiting for its delay to be known, with the frame it
+    /// appeared on
+    pending: Option<(Vec<u8>, u64)>,
+    frames: u64,
+}
+
+impl<W: Write> GifSink<W> {
+    /// Animation of a `platform` screen drawn with `look`
+    pub fn new(out: W, look: Screenshot, platform: Platform) -> Self {
+        GifSink {
+            out,
+            look,
+            size: video_size(&look, platform),
+            started: false,
+            pending: None,
+            frames: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn write_header(&mut self, width: usize, height: usize) -> io::Result<()> {
+        self.out.write_all(b"GIF89a")?;
+        self.out.write_all(&(width as u16).to_le_bytes())?;
+        self.out.write_all(&(height as u16).to_le_bytes())?;
+        // Global colour table of 2^(1+1) entries
+        self.out.write_all(&[0b1000_0001, 0, 0])?;
+        for rgb in self.look.palette.colors {
+            self.out.write_all(&[rgb.0, rgb.1, rgb.2])?;
+        }
+        // Loop forever
+        self.out
+            .write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00")
+    }
+
+    fn write_picture(&mut self, pixels: &[u8], delay: u64) -> io::Result<()> {
+        let (width, height) = self.size;
+        let delay = delay.min(u16::MAX as u64) as u16;
+        // Graphic control extension
+        self.out.write_all(&[0x21, 0xF9, 4, 0])?;
+        self.out.write_all(&delay.to_le_bytes())?;
+        self.out.write_all(&[0, 0])?;
+        // Image descriptor covering the whole screen
+        self.out.write_all(&[0x2C, 0, 0, 0, 0])?;
+        self.out.write_all(&(width as u16).to_le_bytes())?;
+        self.out.write_all(&(height as u16).to_le_bytes())?;
+        self.out.write_all(&[0, MIN_CODE_SIZE])?;
+        for block in lzw(pixels).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0])
+    }
+}
+
+/// Hundredths of a second at the start of `frame`
+fn centis(frame: u64) -> u64 {
+    frame * 100 / 60
+}
+
+impl<W: Write> VideoSink for GifSink<W> {
+    fn frame(&mut self, screen: &Framebuffer) -> io::Result<()> {
+        let (width, height) = self.size;
+        if !self.started {
+            self.write_header(width, height)?;
+            self.started = true;
+        }
+        let frame = self.frames;
+        self.frames += 1;
+
+        let pixels = color_indices(screen, width, height);
+        match self.pending.take() {
+            None => self.pending = Some((pixels, frame)),
+            Some((shown, start)) if shown == pixels => self.pending = Some((shown, start)),
+            Some((shown, start)) => {
+                let delay = centis(frame) - centis(start);
+                if delay >= MIN_DELAY_CENTIS {
+                    self.write_picture(&shown, delay)?;
+                    self.pending = Some((pixels, frame));
+                } else {
+                    self.pending = Some((pixels, start));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some((shown, start)) = self.pending.take() {
+            let delay = (centis(self.frames) - centis(start)).max(MIN_DELAY_CENTIS);
+            self.write_picture(&shown, delay)?;
+        }
+        if self.started {
+            self.out.write_all(&[0x3B])?;
+        }
+        self.out.flush()
+    }
+}
+
+/// Variable width LZW as GIF uses it, packed least significant bit first
+fn lzw(pixels: &[u8]) -> Vec<u8> {
+    let clear = 1u16 << MIN_CODE_SIZE;
+    let end = clear + 1;
+
+    let mut out = Vec::new();
+    let mut bits = 0u32;
+    let mut bit_count = 0u8;
+    let mut emit = |code: u16, width: u8, out: &mut Vec<u8>| {
+        bits |= (code as u32) << bit_count;
+        bit_count += width;
+        while bit_count >= 8 {
+            out.push(bits as u8);
+            bits >>= 8;
+            bit_count -= 8;
+        }
+    };
+
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut width = MIN_CODE_SIZE + 1;
+    emit(clear, width, &mut out);
+
+    let mut prefix: Option<u16> = None;
+    for &pixel in pixels {
+        let Some(current) = prefix else {
+            prefix = Some(pixel as u16);
+            continue;
+        };
+        if let Some(&code) = table.get(&(current, pixel)) {
+            prefix = Some(code);
+            continue;
+        }
+        emit(current, width, &mut out);
+        if next < 1 << MAX_CODE_BITS {
+            table.insert((current, pixel), next);
+            // The decoder widens one code later than the encoder adds it
+            if next == 1 << width {
+                width += 1;
+            }
+            next += 1;
+        } else {
+            emit(clear, width, &mut out);
+            table.clear();
+            next = end + 1;
+            width = MIN_CODE_SIZE + 1;
+        }
+        prefix = Some(pixel as u16);
+    }
+    if let Some(current) = prefix {
+        emit(current, width, &mut out);
+    }
+    emit(end, width, &mut out);
+    if bit_count > 0 {
+        out.push(bits as u8);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reference GIF LZW decoder
+    fn unlzw(data: &[u8]) -> Vec<u8> {
+        let clear = 1usize << MIN_CODE_SIZE;
+        let end = clear + 1;
+        let reset = || -> Vec<Vec<u8>> { (0..=end).map(|n| vec![n as u8]).collect() };
+        let mut table = reset();
+        let mut width = MIN_CODE_SIZE as usize + 1;
+        let mut out = Vec::new();
+        let mut previous: Option<Vec<u8>> = None;
+        let mut pos = 0;
+        loop {
+            let code = (0..width).fold(0, |code, bit| {
+                let at = pos + bit;
+                code | (((data[at / 8] >> (at % 8)) & 1) as usize) << bit
+            });
+            pos += width;
+            if code == clear {
+                table = reset();
+                width = MIN_CODE_SIZE as usize + 1;
+                previous = None;
+                continue;
+            }
+            if code == end {
+                return out;
+            }
+            let entry = match (table.get(code), &previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(prev)) => {
+                    let mut entry = prev.clone();
+                    entry.push(prev[0]);
+                    entry
+                }
+                (None, None) => panic!("bad code {}", code),
+            };
+            out.extend_from_slice(&entry);
+            if let Some(mut prev) = previous {
+                if table.len() < 1 << MAX_CODE_BITS {
+                    prev.push(entry[0]);
+                    table.push(prev);
+                    if table.len() == 1 << width && width < MAX_CODE_BITS as usize {
+                        width += 1;
+                    }
+                }
+            }
+            previous = Some(entry);
+        }
+    }
+
+    #[test]
+    fn test_lzw_round_trip() {
+        let simple = [0, 0, 0, 1, 1, 0, 0, 0, 1, 1, 2, 3];
+        assert_eq!(unlzw(&lzw(&simple)), simple);
+
+        // Long and varied enough to fill the table and clear it
+        let mut state = 1u32;
+        let noisy: Vec<u8> = (0..40_000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8 & 0b11
+            })
+            .collect();
+        assert_eq!(unlzw(&lzw(&noisy)), noisy);
+    }
+
+    #[test]
+    fn test_unchanged_frames_merge() {
+        let mut screen = Framebuffer::new();
+        let mut sink = GifSink::new(Vec::new(), Screenshot::default(), Platform::Chip8);
+        for _ in 0..30 {
+            sink.frame(&screen).unwrap();
+        }
+        screen.set(0, 0, true);
+        for _ in 0..30 {
+            sink.frame(&screen).unwrap();
+        }
+        sink.finish().unwrap();
+        let out = sink.into_inner();
+
+        assert_eq!(&out[..6], b"GIF89a");
+        assert_eq!(&out[6..10], &[64, 0, 32, 0]);
+        assert_eq!(out.last(), Some(&0x3B));
+        // Two pictures of half a second each
+        let delays: Vec<u16> = out
+            .windows(4)
+            .enumerate()
+            .filter(|(_, w)| w == &[0x21, 0xF9, 4, 0])
+            .map(|(idx, _)| u16::from_le_bytes([out[idx + 4], out[idx + 5]]))
+            .collect();
+        assert_eq!(delays, [50, 50]);
+    }
+
+    #[test]
+    fn test_short_pictures_are_dropped() {
+        let mut screen = Framebuffer::new();
+        let mut sink = GifSink::new(Vec::new(), Screenshot::default(), Platform::Chip8);
+        sink.frame(&screen).unwrap();
+        screen.set(0, 0, true);
+        sink.frame(&screen).unwrap();
+        sink.frame(&screen).unwrap();
+        sink.finish().unwrap();
+        let out = sink.into_inner();
+        let pictures = out.windows(4).filter(|w| w == &[0x21, 0xF9, 4, 0]).count();
+        assert_eq!(pictures, 1);
+    }
+}
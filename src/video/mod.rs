@@ -0,0 +1,56 @@
+//! Recording the screen to video files, one picture per 60 Hz frame.
+//!
+//! Pictures are drawn like [`Screenshot`]s, at the size of the largest
+//! screen of the platform so a SUPER-CHIP or XO-CHIP program switching to
+//! high resolution keeps every pixel. Low resolution pictures of those
+//! platforms are doubled.
+
+pub mod gif;
+pub mod video_sink;
+pub mod y4m;
+
+use std::path::Path;
+
+use crate::display::framebuffer::Framebuffer;
+use crate::platform::Platform;
+use crate::screenshot::Screenshot;
+use crate::{DISPLAY_HEIGHT, DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT, HIRES_DISPLAY_WIDTH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoFormat {
+    Gif,
+    Y4m,
+}
+
+impl VideoFormat {
+    /// Format going by the file extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "gif" => Some(VideoFormat::Gif),
+            "y4m" => Some(VideoFormat::Y4m),
+            _ => None,
+        }
+    }
+}
+
+/// Picture width and height of videos of `platform`
+pub fn video_size(look: &Screenshot, platform: Platform) -> (usize, usize) {
+    let (width, height) = if platform >= Platform::SuperChip {
+        (HIRES_DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT)
+    } else {
+        (DISPLAY_WIDTH, DISPLAY_HEIGHT)
+    };
+    (width * look.scale, height * look.scale)
+}
+
+/// Colour indices of `screen` stretched to `width` x `height`, row by row
+pub fn color_indices(screen: &Framebuffer, width: usize, height: usize) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            pixels.push(screen.color(x * screen.width() / width, y * screen.height() / height));
+        }
+    }
+    pixels
+}
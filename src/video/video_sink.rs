@@ -0,0 +1,13 @@
+use std::io;
+
+use crate::display::framebuffer::Framebuffer;
+
+pub trait VideoSink {
+    /// Called once per 60 Hz frame with the screen as it is shown
+    fn frame(&mut self, screen: &Framebuffer) -> io::Result<()>;
+
+    /// Write out whatever the sink buffered, called once when emulation ends
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+use std::io::{self, Write};
+
+use crate::display::framebuffer::Framebuffer;
+use crate::display::palette::Rgb;
+use crate::platform::Platform;
+use crate::screenshot::Screenshot;
+use crate::video::video_sink::VideoSink;
+use crate::video::{color_indices, video_size};
+
+/// Writes an uncompressed YUV4MPEG2 stream at 60 frames per second, with
+/// full resolution 4:4:4 chroma, for piping into an encoder such as
+/// `ffmpeg -i - clip.mp4`.
+pub struct Y4mSink<W: Write> {
+    out: W,
+    size: (usize, usize),
+    /// Whether the stream header is written
+    started: bool,
+    /// Y, U and V of every palette colour
+    yuv: [[u8; 3]; 4],
+}
+
+impl<W: Write> Y4mSink<W> {
+    /// Stream of a `platform` screen drawn with `look`
+    pub fn new(out: W, look: Screenshot, platform: Platform) -> Self {
+        Y4mSink {
+            out,
+            size: video_size(&look, platform),
+            started: false,
+            yuv: look.palette.colors.map(yuv),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// BT.601 limited range, what players assume for Y4M without a colour tag
+fn yuv(Rgb(r, g, b): Rgb) -> [u8; 3] {
+    let (r, g, b) = (r as i32, g as i32, b as i32);
+    let y = 16 + ((66 * r + 129 * g + 25 * b + 128) >> 8);
+    let u = 128 + ((-38 * r - 74 * g + 112 * b + 128) >> 8);
+    let v = 128 + ((112 * r - 94 * g - 18 * b + 128) >> 8);
+    [y as u8, u as u8, v as u8]
+}
+
+impl<W: Write> VideoSink for Y4mSink<W> {
+    fn frame(&mut self, screen: &Framebuffer) -> io::Result<()> {
+        let (width, height) = self.size;
+        if !self.started {
+            writeln!(
+                self.out,
+                "YUV4MPEG2 W{} H{} F60:1 Ip A1:1 C444",
+                width, height
+            )?;
+            self.started = true;
+        }
+
+        let pixels = color_indices(screen, width, height);
+        let mut frame = Vec::with_capacity(6 + pixels.len() * 3);
+        frame.extend_from_slice(b"FRAME\n");
+        for plane in 0..3 {
+            frame.extend(
+                pixels
+                    .iter()
+                    .map(|&idx| self.yuv[idx as usize & 0b11][plane]),
+            );
+        }
+        self.out.write_all(&frame)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_and_planes() {
+        let mut screen = Framebuffer::new();
+        screen.set(1, 0, true);
+        let mut sink = Y4mSink::new(Vec::new(), Screenshot::default(), Platform::Chip8);
+        sink.frame(&screen).unwrap();
+        sink.frame(&screen).unwrap();
+        let out = sink.into_inner();
+
+        let header = b"YUV4MPEG2 W64 H32 F60:1 Ip A1:1 C444\n";
+        assert_eq!(&out[..header.len()], header);
+        let frame_size = 6 + 64 * 32 * 3;
+        assert_eq!(out.len(), header.len() + 2 * frame_size);
+
+        let frame = &out[header.len()..header.len() + frame_size];
+        assert_eq!(&frame[..6], b"FRAME\n");
+        // Black and white luma, neutral chroma
+        assert_eq!(&frame[6..9], &[16, 235, 16]);
+        assert_eq!(frame[6 + 64 * 32], 128);
+        assert_eq!(frame[6 + 2 * 64 * 32 + 1], 128);
+    }
+
+    #[test]
+    fn test_low_resolution_is_doubled_on_schip() {
+        let mut screen = Framebuffer::new();
+        screen.set(1, 0, true);
+        let mut sink = Y4mSink::new(Vec::new(), Screenshot::default(), Platform::SuperChip);
+        sink.frame(&screen).unwrap();
+        screen.set_hires(true);
+        screen.set(127, 63, true);
+        sink.frame(&screen).unwrap();
+        let out = sink.into_inner();
+
+        let header = b"YUV4MPEG2 W128 H64 F60:1 Ip A1:1 C444\n";
+        assert_eq!(&out[..header.len()], header);
+        let frame_size = 6 + 128 * 64 * 3;
+        assert_eq!(out.len(), header.len() + 2 * frame_size);
+        let luma = |frame: usize, x: usize, y: usize| {
+            out[header.len() + frame * frame_size + 6 + y * 128 + x]
+        };
+        // The lit low resolution pixel covers 2x2
+        assert_eq!(
+            [luma(0, 1, 0), luma(0, 2, 0), luma(0, 3, 1), luma(0, 4, 0)],
+            [16, 235, 235, 16]
+        );
+        assert_eq!(luma(1, 127, 63), 235);
+    }
+}